@@ -0,0 +1,221 @@
+//! Caching of passing results, so that tests whose inputs didn't change
+//! since they last passed aren't run again.
+//!
+//! A passing test is recorded as an empty file in the cache directory,
+//! named after the hash of its inputs. The inputs are hashed with FNV-1a,
+//! which unlike the hasher of the standard library is the same across
+//! releases of Rust.
+
+use crate::config::{FixtureFile, Suite};
+use crate::runner::RunOptions;
+use std::path::{Path, PathBuf};
+
+/// Part of every key, as other versions of smokers may describe the same
+/// inputs differently.
+const KEY_VERSION: &str = concat!("smokers ", env!("CARGO_PKG_VERSION"));
+
+/// Characters separating the words of a shell script besides whitespace.
+const SCRIPT_SEPARATORS: &[char] = &['|', '&', ';', '(', ')', '<', '>', '`', '\'', '"', '='];
+
+/// Hashes the inputs of the test at `index` of `suite`: its configuration
+/// and the hooks of the suite, the binaries of its commands and hooks and
+/// the programs their arguments name, like those a script runs, the
+/// variables it passes from the environment and the files it reads.
+/// Returns `None` if a binary can't be found, such tests are always run.
+pub(crate) fn key(suite: &Suite, index: usize, options: &RunOptions) -> Option<String> {
+    let config = &suite.tests[index];
+    let mut hash = Fnv::new();
+    hash.add(KEY_VERSION.as_bytes());
+    // The debug representation covers every setting of the test.
+    hash.add(format!("{:?}", config).as_bytes());
+    hash.add(
+        format!(
+            "{:?}",
+            (
+                &suite.path,
+                &suite.before_all,
+                &suite.after_all,
+                &suite.before_each,
+                &suite.after_each,
+                &suite.services,
+            )
+        )
+        .as_bytes(),
+    );
+    hash.add(format!("{:?}", (options.normalize_newlines, options.timeout)).as_bytes());
+
+    let path = config.env.get("PATH").map(String::as_str);
+    let cwd = config.cwd.as_deref().unwrap_or_else(|| Path::new("."));
+    let commands = std::iter::once(&config.command)
+        .chain(&config.setup)
+        .chain(&config.teardown)
+        .chain(&config.validate_stdout)
+        .chain(config.services.iter().map(|(_, service)| &service.command));
+    for command in commands {
+        add_command(&mut hash, command, path, cwd)?;
+    }
+    let hooks = suite
+        .before_all
+        .iter()
+        .chain(&suite.after_all)
+        .chain(&suite.before_each)
+        .chain(&suite.after_each)
+        .chain(suite.services.iter().map(|(_, service)| &service.command));
+    for command in hooks {
+        add_command(&mut hash, command, None, Path::new("."))?;
+    }
+    for name in &config.pass_env {
+        hash.add(format!("{}={:?}", name, std::env::var_os(name)).as_bytes());
+    }
+
+    let sources = config.files.values().filter_map(|file| match file {
+        FixtureFile::Source { source } => Some(source.as_path()),
+        FixtureFile::Content(_) => None,
+    });
+    let content_files = config
+        .expect_files
+        .values()
+        .filter_map(|file| file.content_file.as_deref());
+    let read = [&config.stdin_file, &config.stdout_file, &config.stderr_file];
+    let files = sources
+        .chain(content_files)
+        .chain(read.into_iter().flatten().map(PathBuf::as_path));
+    for path in files {
+        // A missing file is an input as well, the test is likely to fail.
+        hash.add(&std::fs::read(path).unwrap_or_default());
+        hash.add(&[u8::from(path.is_file())]);
+    }
+    Some(format!("{:016x}", hash.0))
+}
+
+/// Hashes the binary of `command` and the programs named by the words of
+/// its arguments, looked up in `path` or the `PATH` of smokers and
+/// relative to `cwd`. Returns `None` if the binary can't be found.
+fn add_command(
+    hash: &mut Fnv,
+    command: &(String, Vec<String>),
+    path: Option<&str>,
+    cwd: &Path,
+) -> Option<()> {
+    hash.add(&std::fs::read(resolve_binary(&command.0, path, cwd)?).ok()?);
+    let words = command
+        .1
+        .iter()
+        .flat_map(|arg| arg.split(|c: char| c.is_whitespace() || SCRIPT_SEPARATORS.contains(&c)))
+        .filter(|word| !word.is_empty());
+    for word in words {
+        let program = resolve_binary(word, path, cwd).filter(|program| is_executable(program));
+        if let Some(content) = program.and_then(|program| std::fs::read(program).ok()) {
+            hash.add(word.as_bytes());
+            hash.add(&content);
+        }
+    }
+    Some(())
+}
+
+/// Finds the binary of `program`, in `path` or the `PATH` of smokers
+/// unless it is given as a path relative to `cwd`.
+fn resolve_binary(program: &str, path: Option<&str>, cwd: &Path) -> Option<PathBuf> {
+    let program = Path::new(program);
+    if program.components().count() > 1 {
+        return Some(cwd.join(program));
+    }
+    let path = match path {
+        Some(path) => path.into(),
+        None => std::env::var_os("PATH")?,
+    };
+    std::env::split_paths(&path)
+        .map(|dir| cwd.join(dir).join(program))
+        .find(|binary| binary.is_file())
+}
+
+/// Whether the file at `path` can be executed.
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    path.is_file()
+}
+
+/// The 64-bit FNV-1a hash.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    /// Adds `bytes` preceded by their length, so that consecutive values
+    /// can't be confused.
+    fn add(&mut self, bytes: &[u8]) {
+        let length = (bytes.len() as u64).to_le_bytes();
+        for byte in length.iter().chain(bytes) {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Whether the test with the inputs hashed to `key` passed before.
+pub(crate) fn is_cached(dir: &Path, key: &str) -> bool {
+    dir.join(key).is_file()
+}
+
+/// Records whether the test with the inputs hashed to `key` passed.
+pub(crate) fn store(dir: &Path, key: &str, passed: bool) -> std::io::Result<()> {
+    let entry = dir.join(key);
+    if passed {
+        std::fs::create_dir_all(dir)?;
+        return std::fs::write(entry, "");
+    }
+    match std::fs::remove_file(entry) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv() {
+        let mut hash = Fnv::new();
+        hash.add(b"smokers");
+        // Hashing the same input always results in the same key.
+        assert_eq!(format!("{:016x}", hash.0), "37c148250bf91908");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_key() {
+        let dir = std::env::temp_dir().join(format!("smokers-key-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("tool");
+        std::fs::write(&tool, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&tool, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        std::fs::write(dir.join("expected"), "one").unwrap();
+        let input = format!(
+            "tests:\n  - script: '{} | cat'\n    \
+             expect-files: {{out: {{content-file: {}}}}}\n",
+            tool.display(),
+            dir.join("expected").display()
+        );
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let key = || super::key(&suite, 0, &RunOptions::default()).unwrap();
+        let first = key();
+        assert_eq!(key(), first);
+        std::fs::write(dir.join("expected"), "two").unwrap();
+        let changed_file = key();
+        std::fs::write(&tool, "#!/bin/sh\nexit 1\n").unwrap();
+        let changed_tool = key();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(changed_file, first);
+        assert_ne!(changed_tool, changed_file);
+    }
+}
@@ -0,0 +1,3011 @@
+//! The configuration of test cases and the files they are defined in.
+
+use crate::report::TextDiff;
+use crate::signal::Signal;
+use crate::{interpolate, json, pattern, runner, Error};
+use regex::Regex;
+use serde::de::Error as SerdeError;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+/// The configuration structure used to define a test case.
+pub struct Configuration {
+    /// The name of the test case.
+    pub name: Option<String>,
+    /// What the test case checks, shown along with its failures.
+    pub description: Option<String>,
+    /// Tags used to select tests on the command line.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub tags: Vec<String>,
+    /// The names of tests of the same suite that have to pass before this
+    /// test runs. A matrix test is referred to by its name without the
+    /// parameters.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub depends_on: Vec<String>,
+    /// Skip the test, `true` or the reason why.
+    #[serde(default, deserialize_with = "deserialize_skip")]
+    pub skip: Option<String>,
+    /// Skip the test if any of these conditions hold.
+    #[serde(default)]
+    pub skip_if: SkipIf,
+    /// The test is known to fail. It passes if it fails and fails if it
+    /// passes.
+    #[serde(default)]
+    pub expected_failure: bool,
+    /// The executable and its arguments.
+    #[serde(deserialize_with = "deserialize_command")]
+    pub command: (String, Vec<String>),
+    /// Where the command is executed.
+    #[serde(default)]
+    pub runner: Runner,
+    /// The container the command is executed in with `runner: container`.
+    pub container: Option<Container>,
+    /// The machine the command is executed on with `runner: ssh`.
+    pub ssh: Option<Ssh>,
+    /// The Nix environment the command is executed in.
+    #[serde(default, deserialize_with = "deserialize_nix")]
+    pub nix: Option<Nix>,
+    /// Commands executed before the command.
+    #[serde(default, deserialize_with = "deserialize_commands")]
+    pub setup: Vec<(String, Vec<String>)>,
+    /// Commands executed after the command, even if the test failed.
+    #[serde(default, deserialize_with = "deserialize_commands")]
+    pub teardown: Vec<(String, Vec<String>)>,
+    /// Background processes started after the setup commands and stopped
+    /// after the teardown commands, in the order they are given.
+    #[serde(default, deserialize_with = "deserialize_services")]
+    pub services: Vec<(String, Service)>,
+    /// Conditions awaited after the setup commands, before the command
+    /// runs.
+    #[serde(default)]
+    pub wait_for: WaitFor,
+    /// Text written to the stdin of the process.
+    pub stdin: Option<String>,
+    /// A file streamed into the stdin of the process.
+    pub stdin_file: Option<PathBuf>,
+    /// Run the process attached to a pseudo-terminal. Everything the
+    /// process prints is captured as stdout.
+    #[serde(default)]
+    pub pty: bool,
+    /// Environment variables set for the process.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Don't inherit the environment of smokers, except for the variables
+    /// in `pass_env`.
+    #[serde(default)]
+    pub clean_env: bool,
+    /// Variables passed on to the process despite `clean_env`.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub pass_env: Vec<String>,
+    /// The working directory of the process.
+    pub cwd: Option<PathBuf>,
+    /// Files created in the working directory before the test runs, by
+    /// their path relative to it.
+    #[serde(default)]
+    pub files: BTreeMap<PathBuf, FixtureFile>,
+    /// The expected stdout.
+    pub stdout: Option<String>,
+    /// A file containing the expected stdout.
+    pub stdout_file: Option<PathBuf>,
+    /// The expected lines of stdout.
+    pub stdout_lines: Option<Vec<String>>,
+    /// The number of lines expected on stdout.
+    pub stdout_line_count: Option<usize>,
+    /// Whether stdout is expected to be empty, unlike `stdout: ""` this
+    /// isn't affected by trimming and masks.
+    #[serde(default)]
+    pub stdout_empty: bool,
+    /// The JSON value stdout is expected to contain, compared regardless
+    /// of formatting and the order of object keys.
+    pub stdout_json: Option<serde_json::Value>,
+    /// Values selected from the JSON on stdout with a query, and the values
+    /// they are expected to equal.
+    #[serde(default, deserialize_with = "deserialize_json_assertions")]
+    pub assert_json: Vec<(json::Query, serde_json::Value)>,
+    /// Lines of stdout by their number, counted from 1 or from the end
+    /// starting with -1, and the text they must equal.
+    #[serde(default, deserialize_with = "deserialize_line_assertions")]
+    pub assert_lines: Vec<(i64, String)>,
+    /// Strings that must be contained in stdout.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub stdout_contains: Vec<String>,
+    /// Strings that must not be contained in stdout.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub stdout_not_contains: Vec<String>,
+    /// A regular expression stdout must match.
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub stdout_regex: Option<Regex>,
+    /// The exact bytes expected on stdout, base64 encoded.
+    #[serde(default, deserialize_with = "deserialize_base64")]
+    pub stdout_base64: Option<Vec<u8>>,
+    /// The exact bytes expected on stdout, hex encoded.
+    #[serde(default, deserialize_with = "deserialize_hex")]
+    pub stdout_hex: Option<Vec<u8>>,
+    /// A command that is given stdout on stdin and decides whether it is
+    /// valid by its exit code.
+    #[serde(default, deserialize_with = "deserialize_optional_command")]
+    pub validate_stdout: Option<(String, Vec<String>)>,
+    /// The expected stderr.
+    pub stderr: Option<String>,
+    /// A file containing the expected stderr.
+    pub stderr_file: Option<PathBuf>,
+    /// The expected lines of stderr.
+    pub stderr_lines: Option<Vec<String>>,
+    /// The number of lines expected on stderr.
+    pub stderr_line_count: Option<usize>,
+    /// Whether stderr is expected to be empty.
+    #[serde(default)]
+    pub stderr_empty: bool,
+    /// Strings that must be contained in stderr.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub stderr_contains: Vec<String>,
+    /// Strings that must not be contained in stderr.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub stderr_not_contains: Vec<String>,
+    /// A regular expression stderr must match.
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub stderr_regex: Option<Regex>,
+    /// Expectations on files after the command ran, by their path relative
+    /// to the working directory.
+    #[serde(default)]
+    pub expect_files: BTreeMap<PathBuf, FileExpectation>,
+    /// The expected exit code(s) of the process.
+    #[serde(default)]
+    pub exit_code: ExitCode,
+    /// The signal that is expected to terminate the process.
+    pub expected_signal: Option<Signal>,
+    /// How often the test is retried if it fails.
+    pub retries: Option<u32>,
+    /// Kill the process if it runs longer than this.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    /// The signal sent to the process on timeout. It is killed if it
+    /// doesn't exit within a grace period.
+    pub timeout_signal: Option<Signal>,
+    /// Fail the test if the process runs longer than this.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub max_duration: Option<Duration>,
+    /// Fail the test if the process finishes faster than this.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub min_duration: Option<Duration>,
+    /// Fail the test if the maximum resident set size of the process
+    /// exceeds this many bytes.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_memory: Option<u64>,
+    /// Fail the test if the process spends more CPU time than this, in user
+    /// mode and in the kernel combined.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub max_cpu_time: Option<Duration>,
+    /// Run the test in a fresh temporary directory that is removed
+    /// afterwards.
+    #[serde(default)]
+    pub sandbox: bool,
+    /// Capture stderr together with stdout, like `2>&1`. The expectations
+    /// of stdout apply to the combined output.
+    #[serde(default)]
+    pub merge_output: bool,
+    /// The maximum number of bytes captured of stdout and stderr each.
+    /// Further output is discarded and fails the test.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub max_output_bytes: Option<u64>,
+    /// Resource limits applied to the process.
+    #[serde(default)]
+    pub limits: Limits,
+    /// The user the process runs as, by name or id. Requires privileges.
+    #[serde(default, deserialize_with = "deserialize_id")]
+    pub user: Option<String>,
+    /// The group the process runs as, by name or id, the primary group of
+    /// `user` by default. Requires privileges.
+    #[serde(default, deserialize_with = "deserialize_id")]
+    pub group: Option<String>,
+    /// The file mode creation mask of the process.
+    #[serde(default, deserialize_with = "deserialize_umask")]
+    pub umask: Option<u32>,
+    /// How much the scheduling priority of the process is lowered, or
+    /// raised if negative.
+    pub nice: Option<i32>,
+    /// The I/O scheduling class and priority of the process.
+    #[serde(default, deserialize_with = "deserialize_ionice")]
+    pub ionice: Option<IoPriority>,
+    /// Linux namespaces that isolate the process from the system.
+    #[serde(default)]
+    pub isolation: Isolation,
+    /// Ignore trailing newlines when comparing the output with `stdout`,
+    /// `stderr` and the expected output files.
+    pub trim_trailing_newline: Option<bool>,
+    /// How far numbers in the output may deviate from those in `stdout`,
+    /// `stderr` and the expected output files.
+    pub tolerance: Option<pattern::Tolerance>,
+    /// Regular expressions whose matches are replaced in both the output
+    /// and the expected text before comparing them.
+    #[serde(default, deserialize_with = "deserialize_mask")]
+    pub mask: Vec<(Regex, String)>,
+    /// How output is compared with `stdout`, `stderr` and the expected
+    /// output files.
+    #[serde(default)]
+    pub compare: CompareMode,
+    /// How line endings are treated when comparing output.
+    #[serde(default)]
+    pub newline_mode: NewlineMode,
+    /// Ignore case when comparing output with the expected text, lines,
+    /// substrings and regexes.
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Remove ANSI escape sequences like colors from the output before
+    /// comparing it.
+    #[serde(default)]
+    pub strip_ansi: bool,
+    /// Remove the indentation common to all lines of the expected `stdout`
+    /// and `stderr`.
+    #[serde(default)]
+    pub dedent: bool,
+}
+
+/// A file created in the working directory of a test.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum FixtureFile {
+    /// The content of the file.
+    Content(String),
+    /// A file to copy, relative to the configuration file.
+    Source { source: PathBuf },
+}
+
+/// The expected state of a file after the command ran.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FileExpectation {
+    /// Whether the file is expected to exist.
+    #[serde(default = "default_true")]
+    pub exists: bool,
+    /// The expected content.
+    pub content: Option<String>,
+    /// A file containing the expected content, relative to the
+    /// configuration file.
+    pub content_file: Option<PathBuf>,
+    /// Strings the content must contain.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub contains: Vec<String>,
+    /// Strings the content must not contain.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub not_contains: Vec<String>,
+    /// A regular expression the content must match.
+    #[serde(default, deserialize_with = "deserialize_regex")]
+    pub regex: Option<Regex>,
+}
+
+impl FileExpectation {
+    pub(crate) fn expectation(&self) -> OutputExpectation<'_> {
+        OutputExpectation {
+            exact: self.content.as_deref(),
+            file: self.content_file.as_deref(),
+            lines: None,
+            line_count: None,
+            empty: false,
+            line_assertions: &[],
+            json: None,
+            json_assertions: &[],
+            contains: &self.contains,
+            not_contains: &self.not_contains,
+            regex: self.regex.as_ref(),
+            bytes: vec![],
+            trim_trailing_newline: false,
+            newline_mode: NewlineMode::default(),
+            tolerance: None,
+            compare: CompareMode::default(),
+            case_insensitive: false,
+            strip_ansi: false,
+            diffs: None,
+            mask: &[],
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Conditions under which a test is skipped.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SkipIf {
+    /// Operating systems like `linux` or `windows`, or their family,
+    /// `unix`.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub os: Vec<String>,
+    /// Environment variables that are set to a non-empty value.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub env_set: Vec<String>,
+    /// Executables that can't be found in `PATH`.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub missing_binary: Vec<String>,
+}
+
+impl SkipIf {
+    /// Describes the first condition that holds.
+    pub fn reason(&self) -> Option<String> {
+        let (os, family) = (std::env::consts::OS, std::env::consts::FAMILY);
+        if let Some(os) = self.os.iter().find(|o| *o == os || *o == family) {
+            return Some(format!("running on {}", os));
+        }
+        if let Some(name) = self
+            .env_set
+            .iter()
+            .find(|name| std::env::var_os(name).is_some_and(|value| !value.is_empty()))
+        {
+            return Some(format!("{} is set", name));
+        }
+        if let Some(binary) = self.missing_binary.iter().find(|b| !is_in_path(b)) {
+            return Some(format!("{} is not installed", binary));
+        }
+        None
+    }
+}
+
+/// Whether an executable called `name` is found in `PATH`.
+fn is_in_path(name: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| {
+        let candidate = dir.join(name);
+        candidate.is_file() || (cfg!(windows) && candidate.with_extension("exe").is_file())
+    })
+}
+
+/// Conditions that have to hold before the command of a test runs, like a
+/// server started by a setup command accepting connections.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct WaitFor {
+    /// TCP addresses like `localhost:8080` that accept connections. A
+    /// port alone refers to localhost.
+    #[serde(default, deserialize_with = "deserialize_addresses")]
+    pub port: Vec<String>,
+    /// Files that exist, relative to the working directory.
+    #[serde(default, deserialize_with = "deserialize_paths")]
+    pub file: Vec<PathBuf>,
+    /// `http://` URLs that respond with the status 200.
+    #[serde(default, deserialize_with = "deserialize_urls")]
+    pub url: Vec<String>,
+    /// How long to wait for the conditions, 30 seconds by default.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    /// How long to wait between checks, 100 milliseconds by default.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub interval: Option<Duration>,
+}
+
+impl WaitFor {
+    /// Whether there is nothing to wait for.
+    pub fn is_empty(&self) -> bool {
+        self.port.is_empty() && self.file.is_empty() && self.url.is_empty()
+    }
+}
+
+/// A long-running background process, like a server that the tests talk
+/// to.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Service {
+    /// The executable and its arguments.
+    #[serde(deserialize_with = "deserialize_command")]
+    pub command: (String, Vec<String>),
+    /// Conditions that signal that the service is ready.
+    #[serde(default)]
+    pub wait_for: WaitFor,
+}
+
+/// Resource limits of a test process, applied with `setrlimit` before the
+/// command is executed. Only supported on Unix.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Limits {
+    /// The maximum size of the virtual memory in bytes.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub memory: Option<u64>,
+    /// The maximum CPU time, rounded up to whole seconds.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub cpu: Option<Duration>,
+    /// The maximum size of files written by the process in bytes.
+    #[serde(default, deserialize_with = "deserialize_size")]
+    pub file_size: Option<u64>,
+    /// The maximum number of open file descriptors.
+    pub open_files: Option<u64>,
+    /// The maximum number of processes of the user running the tests.
+    pub processes: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// How the process of a test is isolated from the rest of the system with
+/// Linux namespaces. Any of them implies a user namespace, so no privileges
+/// are needed.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Isolation {
+    /// Run without network access, only the loopback interface is
+    /// available.
+    #[serde(default)]
+    pub network: bool,
+    /// Run as the first process of a PID namespace, with a `/proc` of its
+    /// own.
+    #[serde(default)]
+    pub pid: bool,
+    /// Mount an empty, private `/tmp`.
+    #[serde(default)]
+    pub private_tmp: bool,
+}
+
+impl Isolation {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The I/O scheduling class and priority of a process, as set by
+/// `ionice`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoPriority {
+    pub class: IoClass,
+    /// The priority within the class from 0 (highest) to 7, 4 by default.
+    pub level: Option<u8>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    /// Only gets I/O time when no other process needs it.
+    Idle,
+}
+
+/// Accepts an I/O scheduling class or a mapping of the class and level.
+fn deserialize_ionice<'a, D: serde::Deserializer<'a>>(
+    d: D,
+) -> Result<Option<IoPriority>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Settings {
+        class: IoClass,
+        level: Option<u8>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Class(IoClass),
+        Settings(Settings),
+    }
+
+    let priority = match Repr::deserialize(d)? {
+        Repr::Class(class) => IoPriority { class, level: None },
+        Repr::Settings(Settings { class, level }) => IoPriority { class, level },
+    };
+    match priority.level {
+        Some(level) if level > 7 => Err(D::Error::custom(format!(
+            "Invalid ionice level {}, expected 0 to 7",
+            level
+        ))),
+        Some(_) if priority.class == IoClass::Idle => {
+            Err(D::Error::custom("The idle ionice class has no levels"))
+        }
+        _ => Ok(Some(priority)),
+    }
+}
+
+/// Where the command of a test is executed. Setup and teardown commands
+/// always run locally.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Runner {
+    /// On the machine running smokers.
+    #[default]
+    Local,
+    /// In a new container, as configured in `container`.
+    Container,
+    /// On a remote machine, connected to as configured in `ssh`.
+    Ssh,
+}
+
+/// A container that the command of a test is executed in.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Container {
+    /// The image the container is created from.
+    pub image: String,
+    /// The executable of the container engine, `docker` by default.
+    #[serde(default = "default_engine")]
+    pub engine: String,
+    /// Bind mounts, given as `source:target[:options]`.
+    #[serde(default, deserialize_with = "deserialize_mounts")]
+    pub mounts: Vec<Mount>,
+    /// Environment variables set in the container, besides those of the
+    /// test.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+/// A directory or file of the host that is mounted into a container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    /// The path on the host, relative to the configuration file.
+    pub source: PathBuf,
+    /// The path in the container, optionally followed by options like
+    /// `:ro`.
+    pub target: String,
+}
+
+impl Container {
+    /// Prepares `command` to run in a new container with the environment
+    /// variables `env`. The working directory `dir` is mounted at the same
+    /// path and used as the working directory in the container, too.
+    pub(crate) fn process(
+        &self,
+        command: &(String, Vec<String>),
+        env: &BTreeMap<String, String>,
+        dir: &Path,
+        tty: bool,
+    ) -> std::process::Command {
+        let dir = absolute(dir);
+        let mut process = std::process::Command::new(&self.engine);
+        process.args(["run", "--rm", "--interactive"]);
+        if tty {
+            process.arg("--tty");
+        }
+        for mount in &self.mounts {
+            process.arg("--volume").arg(format!(
+                "{}:{}",
+                absolute(&mount.source).display(),
+                mount.target
+            ));
+        }
+        process
+            .arg("--volume")
+            .arg(format!("{0}:{0}", dir.display()))
+            .arg("--workdir")
+            .arg(&dir);
+        for (name, value) in env.iter().chain(&self.env) {
+            process.arg("--env").arg(format!("{}={}", name, value));
+        }
+        process.arg(&self.image).arg(&command.0).args(&command.1);
+        process
+    }
+}
+
+/// A remote machine that the command of a test is executed on with the
+/// `ssh` client.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Ssh {
+    /// The host name or address of the machine.
+    pub host: String,
+    /// The user to log in as.
+    pub user: Option<String>,
+    /// The port of the SSH server.
+    pub port: Option<u16>,
+    /// The private key used to authenticate, relative to the configuration
+    /// file.
+    pub identity_file: Option<PathBuf>,
+    /// Options of the client like `StrictHostKeyChecking=no`.
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    pub options: Vec<String>,
+    /// The working directory on the machine, the home directory by
+    /// default.
+    pub cwd: Option<String>,
+}
+
+impl Ssh {
+    /// Prepares `command` to run on the machine with the environment
+    /// variables `env`.
+    pub(crate) fn process(
+        &self,
+        command: &(String, Vec<String>),
+        env: &BTreeMap<String, String>,
+        tty: bool,
+    ) -> std::process::Command {
+        let mut process = std::process::Command::new("ssh");
+        // The first value of an option counts, so the configured options
+        // take precedence.
+        for option in &self.options {
+            process.arg("-o").arg(option);
+        }
+        // Prompting for passwords would hang the test.
+        process.args(["-o", "BatchMode=yes"]);
+        if let Some(port) = self.port {
+            process.arg("-p").arg(port.to_string());
+        }
+        if let Some(identity_file) = &self.identity_file {
+            process.arg("-i").arg(identity_file);
+        }
+        process.arg(if tty { "-tt" } else { "-T" });
+        match &self.user {
+            Some(user) => process.arg(format!("{}@{}", user, self.host)),
+            None => process.arg(&self.host),
+        };
+
+        // The remote command is interpreted by the login shell.
+        let mut remote = String::new();
+        if let Some(cwd) = &self.cwd {
+            remote.push_str(&format!("cd {} && ", runner::shell_quote(cwd)));
+        }
+        if !env.is_empty() {
+            remote.push_str("env ");
+            for (name, value) in env {
+                remote.push_str(&runner::shell_quote(&format!("{}={}", name, value)));
+                remote.push(' ');
+            }
+        }
+        remote.push_str(&runner::shell_words(command));
+        process.arg("--").arg(remote);
+        process
+    }
+}
+
+/// A Nix environment that the command of a test is executed in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Nix {
+    /// The development shell of a flake, entered with `nix develop`.
+    Develop(String),
+    /// Packages made available with `nix shell`. Names without a flake
+    /// reference are taken from `nixpkgs`.
+    Packages(Vec<String>),
+}
+
+impl Nix {
+    /// The arguments of `nix` that run `command` in the environment.
+    pub(crate) fn args(&self, command: &(String, Vec<String>)) -> Vec<String> {
+        let mut args = match self {
+            Nix::Develop(flake) => vec!["develop".to_string(), flake.clone()],
+            Nix::Packages(packages) => std::iter::once("shell".to_string())
+                .chain(packages.iter().map(|package| {
+                    if package.contains('#') {
+                        package.clone()
+                    } else {
+                        format!("nixpkgs#{}", package)
+                    }
+                }))
+                .collect(),
+        };
+        args.push("--command".to_string());
+        args.push(command.0.clone());
+        args.extend(command.1.iter().cloned());
+        args
+    }
+}
+
+/// Makes a flake reference to a relative path absolute, resolving it
+/// against `base`, so it doesn't depend on the working directory of the
+/// test.
+fn resolve_flake(flake: &str, base: &Path) -> String {
+    let (path, attribute) = flake.split_once('#').unwrap_or((flake, ""));
+    if !path.starts_with('.') {
+        return flake.to_string();
+    }
+    let path = absolute(&base.join(path));
+    match attribute {
+        "" => path.display().to_string(),
+        attribute => format!("{}#{}", path.display(), attribute),
+    }
+}
+
+/// Accepts a list of packages or a mapping with either `develop` or
+/// `packages`.
+fn deserialize_nix<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Option<Nix>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Settings {
+        develop: Option<String>,
+        #[serde(default, deserialize_with = "deserialize_one_or_many")]
+        packages: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Packages(Vec<String>),
+        Settings(Settings),
+    }
+
+    match Repr::deserialize(d)? {
+        Repr::Packages(packages)
+        | Repr::Settings(Settings {
+            develop: None,
+            packages,
+        }) if !packages.is_empty() => Ok(Some(Nix::Packages(packages))),
+        Repr::Settings(Settings {
+            develop: Some(flake),
+            packages,
+        }) if packages.is_empty() => Ok(Some(Nix::Develop(flake))),
+        _ => Err(D::Error::custom(
+            "nix needs either a flake to develop or a list of packages",
+        )),
+    }
+}
+
+fn default_engine() -> String {
+    "docker".to_string()
+}
+
+/// Makes `path` absolute, as container engines require it for mounts.
+pub(crate) fn absolute(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| {
+        std::env::current_dir()
+            .map(|dir| dir.join(path))
+            .unwrap_or_else(|_| path.to_path_buf())
+    })
+}
+
+/// Parses bind mounts given as `source:target[:options]`.
+fn deserialize_mounts<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Vec<Mount>, D::Error> {
+    Vec::<String>::deserialize(d)?
+        .into_iter()
+        .map(|mount| match mount.split_once(':') {
+            Some((source, target)) if !source.is_empty() && !target.is_empty() => Ok(Mount {
+                source: PathBuf::from(source),
+                target: target.to_string(),
+            }),
+            _ => Err(D::Error::custom(format!(
+                "Mounts must be given as source:target, not {}",
+                mount
+            ))),
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CompareMode {
+    /// The output has to match the expected text.
+    #[default]
+    Exact,
+    /// The output has to contain the expected lines, in any order.
+    UnorderedLines,
+    /// Runs of spaces and tabs are treated like a single space and
+    /// whitespace at the end of lines is ignored.
+    IgnoreWhitespace,
+}
+
+/// How line endings in the output are treated when comparing it with the
+/// expected output.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NewlineMode {
+    /// `\r\n` is treated like `\n` on Windows, elsewhere line endings are
+    /// compared as they are.
+    #[default]
+    Native,
+    /// `\r\n` is always treated like `\n`.
+    Lf,
+    /// Line endings are compared as they are.
+    Strict,
+}
+
+impl NewlineMode {
+    /// Converts `\r\n` line endings in `text` to `\n` unless the line
+    /// endings are to be compared as they are.
+    pub fn normalize<'a>(&self, text: &'a str) -> std::borrow::Cow<'a, str> {
+        match self {
+            NewlineMode::Lf => text.replace("\r\n", "\n").into(),
+            NewlineMode::Native if cfg!(windows) => text.replace("\r\n", "\n").into(),
+            NewlineMode::Native | NewlineMode::Strict => text.into(),
+        }
+    }
+}
+
+/// The exit code(s) a test case expects the process to exit with.
+///
+/// Can be given as a single integer, a list of integers, a range
+/// (`1..3` or `1..=3`), or one of the keywords `any` and `nonzero`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExitCode {
+    Code(i32),
+    List(Vec<i32>),
+    Range(std::ops::RangeInclusive<i32>),
+    Any,
+    NonZero,
+}
+
+impl Default for ExitCode {
+    fn default() -> Self {
+        ExitCode::Code(0)
+    }
+}
+
+impl ExitCode {
+    pub fn matches(&self, code: i32) -> bool {
+        match self {
+            ExitCode::Code(expected) => code == *expected,
+            ExitCode::List(expected) => expected.contains(&code),
+            ExitCode::Range(range) => range.contains(&code),
+            ExitCode::Any => true,
+            ExitCode::NonZero => code != 0,
+        }
+    }
+}
+
+impl std::fmt::Display for ExitCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitCode::Code(code) => write!(f, "{}", code),
+            ExitCode::List(codes) => write!(f, "one of {:?}", codes),
+            ExitCode::Range(range) => write!(f, "{}..={}", range.start(), range.end()),
+            ExitCode::Any => write!(f, "any"),
+            ExitCode::NonZero => write!(f, "nonzero"),
+        }
+    }
+}
+
+impl std::str::FromStr for ExitCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid exit code {:?}", s);
+        let s = s.trim();
+        match s {
+            "any" => return Ok(ExitCode::Any),
+            "nonzero" => return Ok(ExitCode::NonZero),
+            _ => {}
+        }
+
+        if let Some((start, end)) = s.split_once("..") {
+            let start: i32 = start.trim().parse().map_err(|_| invalid())?;
+            let range = match end.strip_prefix('=') {
+                Some(end) => start..=end.trim().parse().map_err(|_| invalid())?,
+                None => start..=end.trim().parse::<i32>().map_err(|_| invalid())? - 1,
+            };
+            if range.is_empty() {
+                return Err(format!("Empty exit code range {:?}", s));
+            }
+            return Ok(ExitCode::Range(range));
+        }
+
+        s.parse().map(ExitCode::Code).map_err(|_| invalid())
+    }
+}
+
+impl<'a> Deserialize<'a> for ExitCode {
+    fn deserialize<D: serde::Deserializer<'a>>(d: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Code(i32),
+            List(Vec<i32>),
+            String(String),
+        }
+
+        match Repr::deserialize(d)? {
+            Repr::Code(code) => Ok(ExitCode::Code(code)),
+            Repr::List(codes) if !codes.is_empty() => Ok(ExitCode::List(codes)),
+            Repr::List(_) => Err(D::Error::custom("Exit code list must not be empty")),
+            Repr::String(s) => s.parse().map_err(D::Error::custom),
+        }
+    }
+}
+
+fn deserialize_command<'a, D: serde::Deserializer<'a>>(
+    d: D,
+) -> Result<(String, Vec<String>), D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Command {
+        List(Vec<String>),
+        String(String),
+    }
+
+    let l = Command::deserialize(d)?;
+    match l {
+        Command::List(mut ls) if !ls.is_empty() => Ok((ls.remove(0), ls)),
+        Command::String(s) if s.trim().contains(' ') => Err(D::Error::custom(
+            "Please define a list instead of a string or set `shell: true`.",
+        )),
+        Command::String(s) if !s.is_empty() => Ok((s, vec![])),
+        _ => Err(D::Error::custom("Command needs at least one element")),
+    }
+}
+
+/// Deserializes a command in the format accepted by
+/// [`deserialize_command`] that may be omitted.
+fn deserialize_optional_command<'a, D: serde::Deserializer<'a>>(
+    d: D,
+) -> Result<Option<(String, Vec<String>)>, D::Error> {
+    deserialize_command(d).map(Some)
+}
+
+/// Deserializes a list of commands, each of them in the format accepted
+/// by [`deserialize_command`].
+fn deserialize_commands<'a, D: serde::Deserializer<'a>>(
+    d: D,
+) -> Result<Vec<(String, Vec<String>)>, D::Error> {
+    #[derive(Deserialize)]
+    struct Command(#[serde(deserialize_with = "deserialize_command")] (String, Vec<String>));
+
+    Ok(Vec::<Command>::deserialize(d)?
+        .into_iter()
+        .map(|c| c.0)
+        .collect())
+}
+
+/// The file format of a configuration file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum ConfigFormat {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Detects the format from the extension of `path`, defaulting to YAML.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+
+    /// Parses a configuration in this format into a YAML value.
+    fn parse(self, mut reader: impl std::io::Read) -> Result<serde_yaml::Value, Error> {
+        match self {
+            ConfigFormat::Yaml => Ok(serde_yaml::from_reader(reader)?),
+            ConfigFormat::Toml => {
+                let mut content = String::new();
+                reader.read_to_string(&mut content)?;
+                let value: toml::Value = toml::from_str(&content)?;
+                Ok(serde_yaml::to_value(value)?)
+            }
+            ConfigFormat::Json => Ok(serde_json::from_reader(reader)?),
+        }
+    }
+}
+
+/// The keys of a test case holding paths relative to the configuration
+/// file.
+const TEST_PATH_KEYS: &[&str] = &["cwd", "stdin-file", "stdout-file", "stderr-file"];
+
+/// Reads the configuration file at `path` and merges the files listed in
+/// its `include` key into it. Keys of the including file take precedence,
+/// the tests of included files are placed before its own tests.
+///
+/// The file is parsed as `format`, included files in the format matching
+/// their extension. `stack` holds the files currently being read to detect
+/// include cycles, every included file is added to `includes`.
+fn read_with_includes(
+    path: &Path,
+    format: ConfigFormat,
+    stack: &mut Vec<PathBuf>,
+    includes: &mut Vec<PathBuf>,
+) -> Result<serde_yaml::Value, Error> {
+    let canonical = path.canonicalize()?;
+    if stack.contains(&canonical) {
+        return Err(Error::IncludeCycle(path.to_path_buf()));
+    }
+    let mut value = format.parse(std::fs::File::open(path)?)?;
+    let include = value
+        .as_mapping_mut()
+        .and_then(|m| m.remove(&serde_yaml::Value::from("include")));
+    let include = match include {
+        None => return Ok(value),
+        Some(include) => {
+            #[derive(Deserialize)]
+            struct Include(#[serde(deserialize_with = "deserialize_one_or_many")] Vec<String>);
+            serde_yaml::from_value::<Include>(include)?.0
+        }
+    };
+
+    stack.push(canonical);
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut merged = serde_yaml::Value::Mapping(Default::default());
+    for file in include {
+        let included_path = base.join(&file);
+        let mut included = read_with_includes(
+            &included_path,
+            ConfigFormat::from_path(&included_path),
+            stack,
+            includes,
+        )
+        .map_err(|e| Error::Include(included_path.clone(), Box::new(e)))?;
+        rebase_paths(
+            &mut included,
+            Path::new(&file).parent().unwrap_or(Path::new("")),
+        );
+        merge(&mut merged, included)?;
+        includes.push(included_path);
+    }
+    stack.pop();
+    merge(&mut merged, value)?;
+    Ok(merged)
+}
+
+/// Makes the relative paths in the configuration `value` of an included
+/// file relative to the including file, `dir` being the directory of the
+/// included file relative to the including one.
+fn rebase_paths(value: &mut serde_yaml::Value, dir: &Path) {
+    fn rebase(value: Option<&mut serde_yaml::Value>, dir: &Path) {
+        match value {
+            Some(serde_yaml::Value::String(path)) => {
+                *path = dir.join(&path).to_string_lossy().into_owned();
+            }
+            Some(serde_yaml::Value::Sequence(paths)) => {
+                for path in paths {
+                    rebase(Some(path), dir);
+                }
+            }
+            _ => {}
+        }
+    }
+    fn rebase_flake(value: Option<&mut serde_yaml::Value>, dir: &Path) {
+        if let Some(serde_yaml::Value::String(flake)) = value {
+            let (path, attribute) = flake.split_once('#').unwrap_or((flake, ""));
+            if path.starts_with('.') {
+                // Keeps the leading `.` that marks the flake as local.
+                let path = Path::new(".").join(dir).join(path);
+                *flake = match attribute {
+                    "" => path.display().to_string(),
+                    attribute => format!("{}#{}", path.display(), attribute),
+                };
+            }
+        }
+    }
+    fn rebase_test(test: &mut serde_yaml::Value, dir: &Path) {
+        for key in TEST_PATH_KEYS {
+            rebase(test.get_mut(*key), dir);
+        }
+        if let Some(serde_yaml::Value::Mapping(files)) = test.get_mut("files") {
+            for (_, file) in files.iter_mut() {
+                rebase(file.get_mut("source"), dir);
+            }
+        }
+        if let Some(serde_yaml::Value::Mapping(files)) = test.get_mut("expect-files") {
+            for (_, file) in files.iter_mut() {
+                rebase(file.get_mut("content-file"), dir);
+            }
+        }
+        rebase(
+            test.get_mut("ssh").and_then(|s| s.get_mut("identity-file")),
+            dir,
+        );
+        match test.get_mut("nix") {
+            Some(serde_yaml::Value::Sequence(packages)) => {
+                packages.iter_mut().for_each(|p| rebase_flake(Some(p), dir))
+            }
+            Some(nix @ serde_yaml::Value::Mapping(_)) => {
+                rebase_flake(nix.get_mut("develop"), dir);
+                if let Some(serde_yaml::Value::Sequence(packages)) = nix.get_mut("packages") {
+                    packages.iter_mut().for_each(|p| rebase_flake(Some(p), dir))
+                }
+            }
+            _ => {}
+        }
+        let mounts = test.get_mut("container").and_then(|c| c.get_mut("mounts"));
+        if let Some(serde_yaml::Value::Sequence(mounts)) = mounts {
+            for mount in mounts {
+                if let serde_yaml::Value::String(mount) = mount {
+                    if let Some((source, target)) = mount.split_once(':') {
+                        *mount = format!("{}:{}", dir.join(source).display(), target);
+                    }
+                }
+            }
+        }
+    }
+
+    rebase_test(value, dir);
+    rebase(value.get_mut("watch-paths"), dir);
+    match value.get_mut("tests") {
+        Some(serde_yaml::Value::Sequence(tests)) => {
+            tests.iter_mut().for_each(|t| rebase_test(t, dir))
+        }
+        Some(serde_yaml::Value::Mapping(tests)) => {
+            tests.iter_mut().for_each(|(_, t)| rebase_test(t, dir))
+        }
+        _ => {}
+    }
+}
+
+/// Merges the configuration `overlay` into `base`. Keys of `overlay` replace
+/// those of `base`, except for the `tests` and `vars` which are combined.
+fn merge(
+    base: &mut serde_yaml::Value,
+    overlay: serde_yaml::Value,
+) -> Result<(), serde_yaml::Error> {
+    let (base, overlay) = match (base, overlay) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) => (base, overlay),
+        _ => return Err(serde_yaml::Error::custom("Included files must be mappings")),
+    };
+    let tests_key = serde_yaml::Value::from("tests");
+    let vars_key = serde_yaml::Value::from("vars");
+    for (key, value) in overlay {
+        if (key != tests_key && key != vars_key) || !base.contains_key(&key) {
+            base.insert(key, value);
+            continue;
+        }
+        match (base.get_mut(&key), value) {
+            (Some(serde_yaml::Value::Sequence(tests)), serde_yaml::Value::Sequence(more)) => {
+                tests.extend(more)
+            }
+            (Some(serde_yaml::Value::Mapping(entries)), serde_yaml::Value::Mapping(more)) => {
+                for (name, entry) in more {
+                    entries.insert(name, entry);
+                }
+            }
+            _ if key == vars_key => return Err(serde_yaml::Error::custom("vars must be a map")),
+            _ => {
+                return Err(serde_yaml::Error::custom(
+                    "Included tests must be of the same kind (list or map) as the including file",
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The keys of a test case in which matrix parameters are substituted.
+const MATRIX_KEYS: &[&str] = &["command", "stdout", "exit-code"];
+
+/// Expands a test case with a `matrix` into one test case per combination
+/// of the parameter values. `{{param}}` is replaced by the value of the
+/// parameter in the keys listed in [`MATRIX_KEYS`] and the name of each
+/// test case is suffixed with its parameters.
+fn expand_matrix(
+    mut value: serde_yaml::Value,
+) -> Result<Vec<serde_yaml::Value>, serde_yaml::Error> {
+    let matrix = match value
+        .as_mapping_mut()
+        .and_then(|m| m.remove(&serde_yaml::Value::from("matrix")))
+    {
+        None => return Ok(vec![value]),
+        Some(matrix) => serde_yaml::from_value::<serde_yaml::Mapping>(matrix)?,
+    };
+
+    let mut combinations: Vec<Vec<(String, String)>> = vec![vec![]];
+    for (param, values) in matrix {
+        let param = param
+            .as_str()
+            .ok_or_else(|| serde_yaml::Error::custom("Matrix parameters must be strings"))?
+            .to_string();
+        let values = match values {
+            serde_yaml::Value::Sequence(values) if !values.is_empty() => values,
+            _ => {
+                return Err(serde_yaml::Error::custom(format!(
+                    "Matrix parameter {} must be a non-empty list",
+                    param
+                )))
+            }
+        };
+        let values = values
+            .iter()
+            .map(|value| match value {
+                serde_yaml::Value::String(s) => Ok(s.clone()),
+                serde_yaml::Value::Number(n) => Ok(n.to_string()),
+                serde_yaml::Value::Bool(b) => Ok(b.to_string()),
+                _ => Err(serde_yaml::Error::custom(format!(
+                    "Values of matrix parameter {} must be scalars",
+                    param
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut expanded = vec![];
+        for combination in combinations {
+            for value in &values {
+                let mut combination = combination.clone();
+                combination.push((param.clone(), value.clone()));
+                expanded.push(combination);
+            }
+        }
+        combinations = expanded;
+    }
+
+    let name_key = serde_yaml::Value::from("name");
+    let base_name = value
+        .get("name")
+        .and_then(|name| name.as_str())
+        .map(String::from);
+    Ok(combinations
+        .into_iter()
+        .map(|combination| {
+            let mut test = value.clone();
+            for key in MATRIX_KEYS {
+                if let Some(value) = test.get_mut(*key) {
+                    substitute_parameters(value, &combination);
+                }
+            }
+            let parameters: Vec<String> = combination
+                .iter()
+                .map(|(param, value)| format!("{}={}", param, value))
+                .collect();
+            let name = format!(
+                "{}[{}]",
+                base_name.as_deref().unwrap_or(""),
+                parameters.join(",")
+            );
+            if let Some(test) = test.as_mapping_mut() {
+                test.insert(name_key.clone(), serde_yaml::Value::from(name));
+            }
+            test
+        })
+        .collect())
+}
+
+/// Replaces `{{param}}` by the value of the parameter in all strings
+/// contained in `value`.
+fn substitute_parameters(value: &mut serde_yaml::Value, parameters: &[(String, String)]) {
+    match value {
+        serde_yaml::Value::String(s) => {
+            for (param, value) in parameters {
+                *s = s.replace(&format!("{{{{{}}}}}", param), value);
+            }
+        }
+        serde_yaml::Value::Sequence(values) => {
+            for value in values {
+                substitute_parameters(value, parameters);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The shell used for scripts unless the test case names one.
+const DEFAULT_SHELL: &str = if cfg!(windows) { "cmd" } else { "sh" };
+
+/// Removes the shell script of a test case from its configuration, given
+/// either as `script` or as the `command` string of a test case with
+/// `shell` set, and returns the command running it.
+///
+/// `shell` is either a boolean or the name of the shell to use.
+fn take_script(
+    mapping: &mut serde_yaml::Mapping,
+) -> Result<Option<Vec<String>>, serde_yaml::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Shell {
+        Enabled(bool),
+        Named(String),
+    }
+
+    let shell = match mapping.remove(&serde_yaml::Value::from("shell")) {
+        None | Some(serde_yaml::Value::Null) => None,
+        Some(shell) => match serde_yaml::from_value(shell)? {
+            Shell::Enabled(false) => None,
+            Shell::Enabled(true) => Some(DEFAULT_SHELL.to_string()),
+            Shell::Named(name) => Some(name),
+        },
+    };
+    let command_key = serde_yaml::Value::from("command");
+    let script = match mapping.remove(&serde_yaml::Value::from("script")) {
+        Some(_) if mapping.contains_key(&command_key) => {
+            return Err(serde_yaml::Error::custom(
+                "Only one of command and script can be given",
+            ))
+        }
+        Some(script) => serde_yaml::from_value(script)?,
+        None if shell.is_some() => match mapping.remove(&command_key) {
+            Some(serde_yaml::Value::String(command)) => command,
+            _ => {
+                return Err(serde_yaml::Error::custom(
+                    "The command must be a string if shell is set",
+                ))
+            }
+        },
+        None => return Ok(None),
+    };
+    let shell = shell.unwrap_or_else(|| DEFAULT_SHELL.to_string());
+    let mut command: Vec<String> = match shell.as_str() {
+        "cmd" => vec!["cmd".into(), "/C".into()],
+        "powershell" | "pwsh" => vec![
+            shell.clone(),
+            "-NoProfile".into(),
+            "-NonInteractive".into(),
+            "-Command".into(),
+        ],
+        _ => vec![shell.clone(), "-c".into()],
+    };
+    command.push(script);
+    Ok(Some(command))
+}
+
+/// The keys of a test case whose string values are subject to environment
+/// variable substitution.
+const INTERPOLATED_KEYS: &[&str] = &["command", "stdout", "cwd", "env"];
+
+/// Substitutes environment variables in all strings contained in `value`.
+/// Mapping keys are left untouched.
+fn interpolate_strings(value: &mut serde_yaml::Value) -> Result<(), serde_yaml::Error> {
+    map_strings(value, &interpolate::interpolate_env)
+}
+
+/// Replaces all strings contained in `value` by the result of `f`.
+/// Mapping keys are left untouched.
+fn map_strings(
+    value: &mut serde_yaml::Value,
+    f: &impl Fn(&str) -> Result<String, String>,
+) -> Result<(), serde_yaml::Error> {
+    match value {
+        serde_yaml::Value::String(s) => {
+            *s = f(s).map_err(serde_yaml::Error::custom)?;
+        }
+        serde_yaml::Value::Sequence(values) => {
+            for value in values {
+                map_strings(value, f)?;
+            }
+        }
+        serde_yaml::Value::Mapping(mapping) => {
+            for (_, value) in mapping.iter_mut() {
+                map_strings(value, f)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Removes the `vars` of a suite from `value` and substitutes them in all
+/// the strings that remain.
+fn substitute_vars(value: &mut serde_yaml::Value) -> Result<(), serde_yaml::Error> {
+    let vars = match value
+        .as_mapping_mut()
+        .and_then(|m| m.remove(&serde_yaml::Value::from("vars")))
+    {
+        None => return Ok(()),
+        Some(vars) => serde_yaml::from_value::<BTreeMap<String, serde_yaml::Value>>(vars)?,
+    };
+    let vars = vars
+        .into_iter()
+        .map(|(name, value)| match value {
+            serde_yaml::Value::String(s) => Ok((name, s)),
+            serde_yaml::Value::Number(n) => Ok((name, n.to_string())),
+            serde_yaml::Value::Bool(b) => Ok((name, b.to_string())),
+            _ => Err(serde_yaml::Error::custom(format!(
+                "Variable {} must be a scalar",
+                name
+            ))),
+        })
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+    map_strings(value, &|s| interpolate::interpolate_vars(s, &vars))
+}
+
+/// A configuration file containing one or more test cases.
+///
+/// The file is either a single [`Configuration`] or a mapping with a
+/// `tests` key holding a list of test cases or a map from test names to
+/// test cases.
+#[derive(Debug, Default)]
+pub struct Suite {
+    /// The file the suite was loaded from.
+    pub path: Option<PathBuf>,
+    /// Commands executed once before the first test of the suite.
+    pub before_all: Vec<(String, Vec<String>)>,
+    /// Commands executed once after the last test of the suite.
+    pub after_all: Vec<(String, Vec<String>)>,
+    /// Commands executed before each test of the suite, ahead of its
+    /// setup commands.
+    pub before_each: Vec<(String, Vec<String>)>,
+    /// Commands executed after each test of the suite, following its
+    /// teardown commands.
+    pub after_each: Vec<(String, Vec<String>)>,
+    /// Background processes started once before the first test of the
+    /// suite, after the before-all commands, and stopped after the last
+    /// one.
+    pub services: Vec<(String, Service)>,
+    /// Additional files and directories that cause the suite to be re-run
+    /// in watch mode when they change.
+    pub watch_paths: Vec<PathBuf>,
+    /// The files included by the configuration file, directly or
+    /// indirectly.
+    pub includes: Vec<PathBuf>,
+    /// The test cases of the suite.
+    pub tests: Vec<Configuration>,
+}
+
+/// The top-level settings of a configuration file containing multiple
+/// test cases.
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+struct SuiteSettings {
+    #[serde(default, deserialize_with = "deserialize_commands")]
+    before_all: Vec<(String, Vec<String>)>,
+    #[serde(default, deserialize_with = "deserialize_commands")]
+    after_all: Vec<(String, Vec<String>)>,
+    #[serde(default, deserialize_with = "deserialize_commands")]
+    before_each: Vec<(String, Vec<String>)>,
+    #[serde(default, deserialize_with = "deserialize_commands")]
+    after_each: Vec<(String, Vec<String>)>,
+    #[serde(default, deserialize_with = "deserialize_services")]
+    services: Vec<(String, Service)>,
+    #[serde(default, deserialize_with = "deserialize_one_or_many")]
+    watch_paths: Vec<String>,
+}
+
+impl Suite {
+    /// Loads the suite from the given file, in the format matching its
+    /// extension. Relative paths in the configuration are resolved against
+    /// the directory of the file.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        Self::from_path_as(path, ConfigFormat::from_path(path))
+    }
+
+    /// Loads the suite from the given file in the given `format`.
+    pub fn from_path_as(path: impl AsRef<Path>, format: ConfigFormat) -> Result<Self, Error> {
+        Self::from_path_with_overrides(path, format, &[])
+    }
+
+    /// Loads the suite from the given file in the given `format`, with the
+    /// `overrides` applied to each of its test cases.
+    pub fn from_path_with_overrides(
+        path: impl AsRef<Path>,
+        format: ConfigFormat,
+        overrides: &[Override],
+    ) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let mut includes = vec![];
+        let value = read_with_includes(path, format, &mut vec![], &mut includes)?;
+        let mut suite = Self::from_value(value, overrides)?;
+        suite.includes = includes;
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        for test in &mut suite.tests {
+            let paths = [
+                &mut test.cwd,
+                &mut test.stdin_file,
+                &mut test.stdout_file,
+                &mut test.stderr_file,
+            ];
+            for path in paths.into_iter().flatten() {
+                *path = base.join(&path);
+            }
+            for mount in test.container.iter_mut().flat_map(|c| &mut c.mounts) {
+                mount.source = base.join(&mount.source);
+            }
+            if let Some(identity_file) = test.ssh.as_mut().and_then(|s| s.identity_file.as_mut()) {
+                *identity_file = base.join(&identity_file);
+            }
+            match &mut test.nix {
+                Some(Nix::Develop(flake)) => *flake = resolve_flake(flake, base),
+                Some(Nix::Packages(packages)) => {
+                    for package in packages {
+                        *package = resolve_flake(package, base);
+                    }
+                }
+                None => {}
+            }
+            for file in test.files.values_mut() {
+                if let FixtureFile::Source { source } = file {
+                    *source = base.join(&source);
+                }
+            }
+            for file in test.expect_files.values_mut() {
+                if let Some(content_file) = &mut file.content_file {
+                    *content_file = base.join(&content_file);
+                }
+            }
+        }
+        for watch_path in &mut suite.watch_paths {
+            *watch_path = base.join(&watch_path);
+        }
+        suite.path = Some(path.to_path_buf());
+        Ok(suite)
+    }
+
+    /// The files whose changes affect the outcome of the suite: the
+    /// configuration file itself, the included files, the stdin files, the
+    /// expected output and content files, the sources of fixture files and
+    /// the `watch-paths`.
+    pub fn watched_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.path.iter().cloned().collect();
+        paths.extend(self.includes.iter().cloned());
+        for test in &self.tests {
+            paths.extend(test.stdin_file.iter().cloned());
+            paths.extend(test.stdout_file.iter().cloned());
+            paths.extend(test.stderr_file.iter().cloned());
+            for file in test.files.values() {
+                if let FixtureFile::Source { source } = file {
+                    paths.push(source.clone());
+                }
+            }
+            for file in test.expect_files.values() {
+                paths.extend(file.content_file.iter().cloned());
+            }
+        }
+        paths.extend(self.watch_paths.iter().cloned());
+        paths
+    }
+
+    /// Whether any of the `changed` files affects the suite.
+    pub fn is_affected_by(&self, changed: &[PathBuf]) -> bool {
+        let watched = self.watched_paths();
+        changed
+            .iter()
+            .any(|path| watched.iter().any(|w| path.starts_with(w)))
+    }
+
+    /// The name of the test case at `index`. Unnamed test cases are
+    /// identified by their (1-based) position if the suite contains more
+    /// than one test case.
+    pub fn test_name(&self, index: usize) -> Option<String> {
+        match &self.tests[index].name {
+            Some(name) => Some(name.clone()),
+            None if self.tests.len() > 1 => Some(format!("#{}", index + 1)),
+            None => None,
+        }
+    }
+
+    /// The name of the test case at `index` qualified with the file the
+    /// suite was loaded from.
+    pub fn display_name(&self, index: usize) -> Option<String> {
+        match (&self.path, self.test_name(index)) {
+            (Some(file), Some(name)) => Some(format!("{}::{}", file.display(), name)),
+            (Some(file), None) => Some(file.display().to_string()),
+            (None, name) => name,
+        }
+    }
+
+    pub fn from_reader(reader: impl std::io::Read) -> Result<Self, serde_yaml::Error> {
+        let value: serde_yaml::Value = serde_yaml::from_reader(reader)?;
+        Self::from_value(value, &[])
+    }
+
+    fn from_value(
+        mut value: serde_yaml::Value,
+        overrides: &[Override],
+    ) -> Result<Self, serde_yaml::Error> {
+        substitute_vars(&mut value)?;
+        let tests = match value
+            .as_mapping_mut()
+            .and_then(|m| m.remove(&serde_yaml::Value::from("tests")))
+        {
+            None => {
+                return Ok(Self {
+                    tests: Self::tests_from_values(vec![value], overrides)?,
+                    ..Self::default()
+                })
+            }
+            Some(serde_yaml::Value::Sequence(tests)) => Self::tests_from_values(tests, overrides)?,
+            Some(serde_yaml::Value::Mapping(tests)) => {
+                let tests = tests
+                    .into_iter()
+                    .map(|(name, mut test)| {
+                        if !name.is_string() {
+                            return Err(serde_yaml::Error::custom("Test names must be strings"));
+                        }
+                        if let Some(test) = test.as_mapping_mut() {
+                            test.insert(serde_yaml::Value::from("name"), name);
+                        }
+                        Ok(test)
+                    })
+                    .collect::<Result<_, _>>()?;
+                Self::tests_from_values(tests, overrides)?
+            }
+            Some(_) => {
+                return Err(serde_yaml::Error::custom(
+                    "tests must be a list or a map of test cases",
+                ))
+            }
+        };
+
+        let settings: SuiteSettings = serde_yaml::from_value(value)?;
+        let suite = Self {
+            path: None,
+            before_all: settings.before_all,
+            after_all: settings.after_all,
+            before_each: settings.before_each,
+            after_each: settings.after_each,
+            services: settings.services,
+            watch_paths: settings
+                .watch_paths
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+            includes: vec![],
+            tests,
+        };
+        suite.check_dependencies()?;
+        Ok(suite)
+    }
+
+    /// The indices of the tests the test at `index` depends on.
+    pub fn dependencies(&self, index: usize) -> Vec<usize> {
+        let depends_on = &self.tests[index].depends_on;
+        (0..self.tests.len())
+            .filter(|&other| depends_on.iter().any(|name| self.is_named(other, name)))
+            .collect()
+    }
+
+    /// Whether the test at `index` is called `name`, ignoring the
+    /// parameters of matrix tests.
+    fn is_named(&self, index: usize, name: &str) -> bool {
+        self.tests[index].name.as_deref().is_some_and(|own| {
+            own == name || own.split_once('[').is_some_and(|(base, _)| base == name)
+        })
+    }
+
+    /// Rejects dependencies on unknown tests and dependency cycles.
+    fn check_dependencies(&self) -> Result<(), serde_yaml::Error> {
+        for (index, test) in self.tests.iter().enumerate() {
+            for name in &test.depends_on {
+                if !(0..self.tests.len()).any(|other| self.is_named(other, name)) {
+                    return Err(serde_yaml::Error::custom(format!(
+                        "Test {} depends on the unknown test {}",
+                        self.test_name(index).unwrap_or_default(),
+                        name
+                    )));
+                }
+            }
+        }
+        // Repeatedly remove tests whose dependencies were all removed, the
+        // tests that remain are part of a cycle.
+        let mut remaining: Vec<usize> = (0..self.tests.len()).collect();
+        while let Some(position) = remaining.iter().position(|&index| {
+            self.dependencies(index)
+                .iter()
+                .all(|d| !remaining.contains(d))
+        }) {
+            remaining.remove(position);
+        }
+        if let Some(&index) = remaining.first() {
+            return Err(serde_yaml::Error::custom(format!(
+                "Test {} has cyclic dependencies",
+                self.test_name(index).unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Deserializes the test cases, expanding their matrices and applying
+    /// the `overrides`.
+    fn tests_from_values(
+        values: Vec<serde_yaml::Value>,
+        overrides: &[Override],
+    ) -> Result<Vec<Configuration>, serde_yaml::Error> {
+        let mut tests = vec![];
+        for value in values {
+            for mut test in expand_matrix(value)? {
+                for r#override in overrides {
+                    r#override.apply(&mut test)?;
+                }
+                tests.push(Configuration::from_value(test)?);
+            }
+        }
+        Ok(tests)
+    }
+
+    /// Prepares a before-all, after-all, before-each or after-each command.
+    pub(crate) fn process(&self, command: &(String, Vec<String>)) -> std::process::Command {
+        let mut process = std::process::Command::new(&command.0);
+        process.args(&command.1);
+        process
+    }
+}
+
+impl Configuration {
+    /// Parses a single test case from YAML.
+    pub fn from_reader(reader: impl std::io::Read) -> Result<Self, Error> {
+        let value: serde_yaml::Value = serde_yaml::from_reader(reader)?;
+        Ok(Self::from_value(value)?)
+    }
+
+    /// Deserializes a test case after substituting environment variables in
+    /// the values that support it.
+    fn from_value(mut value: serde_yaml::Value) -> Result<Self, serde_yaml::Error> {
+        if let Some(mapping) = value.as_mapping_mut() {
+            // Shell scripts are left to the shell to expand variables in.
+            let shell_command = take_script(mapping)?;
+            for key in INTERPOLATED_KEYS {
+                if let Some(value) = mapping.get_mut(&serde_yaml::Value::from(*key)) {
+                    interpolate_strings(value)?;
+                }
+            }
+            if let Some(command) = shell_command {
+                mapping.insert(serde_yaml::Value::from("command"), command.into());
+            }
+        }
+        let mut config: Self = serde_yaml::from_value(value)?;
+        if config.dedent {
+            config.stdout = config.stdout.as_deref().map(dedent);
+            config.stderr = config.stderr.as_deref().map(dedent);
+        }
+        for path in config.files.keys() {
+            let relative = path
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+            if !relative || path.as_os_str().is_empty() {
+                return Err(serde_yaml::Error::custom(format!(
+                    "The path of the file {} must be relative to the working directory",
+                    path.display()
+                )));
+            }
+        }
+        if config.stdin.is_some() && config.stdin_file.is_some() {
+            return Err(serde_yaml::Error::custom(
+                "Only one of stdin and stdin-file can be given",
+            ));
+        }
+        if config.runner == Runner::Container && config.container.is_none() {
+            return Err(serde_yaml::Error::custom(
+                "runner: container requires the container settings",
+            ));
+        }
+        if config.runner == Runner::Ssh && config.ssh.is_none() {
+            return Err(serde_yaml::Error::custom(
+                "runner: ssh requires the ssh settings",
+            ));
+        }
+        if config.runner != Runner::Local && config.nix.is_some() {
+            return Err(serde_yaml::Error::custom(
+                "nix can only be used with the local runner",
+            ));
+        }
+        // Only the usage of the container or ssh client could be measured.
+        let measures_usage = config.max_memory.is_some() || config.max_cpu_time.is_some();
+        if config.runner != Runner::Local && measures_usage {
+            return Err(serde_yaml::Error::custom(
+                "max-memory and max-cpu-time can only be used with the local runner",
+            ));
+        }
+        if config.sandbox && config.cwd.is_some() {
+            return Err(serde_yaml::Error::custom(
+                "Only one of cwd and sandbox can be given",
+            ));
+        }
+        if let (Some(min), Some(max)) = (config.min_duration, config.max_duration) {
+            if min > max {
+                return Err(serde_yaml::Error::custom(
+                    "min-duration must not exceed max-duration",
+                ));
+            }
+        }
+        Ok(config)
+    }
+
+    /// Why the test is skipped, if it is.
+    pub fn skip_reason(&self) -> Option<String> {
+        self.skip.clone().or_else(|| self.skip_if.reason())
+    }
+
+    /// Prepares the command of the test case to run in `dir` with its
+    /// runner.
+    pub(crate) fn command_process(&self, dir: &Path) -> std::process::Command {
+        match (self.runner, &self.ssh, &self.container) {
+            (Runner::Container, _, Some(container)) => {
+                container.process(&self.command, &self.env, dir, self.pty)
+            }
+            (Runner::Ssh, Some(ssh), _) => ssh.process(&self.command, &self.env, self.pty),
+            _ => {
+                let mut process = match &self.nix {
+                    Some(nix) => self.process(&("nix".to_string(), nix.args(&self.command))),
+                    None => self.process(&self.command),
+                };
+                process.current_dir(dir);
+                process
+            }
+        }
+    }
+
+    /// Prepares the given command to run with the environment and working
+    /// directory of the test case.
+    pub(crate) fn process(&self, command: &(String, Vec<String>)) -> std::process::Command {
+        let mut process = std::process::Command::new(&command.0);
+        if self.clean_env {
+            process.env_clear();
+            for name in &self.pass_env {
+                if let Some(value) = std::env::var_os(name) {
+                    process.env(name, value);
+                }
+            }
+        }
+        process
+            .args(&command.1)
+            .envs(&self.env)
+            .current_dir(self.cwd.as_deref().unwrap_or_else(|| Path::new(".")));
+        process
+    }
+
+    pub(crate) fn stdout_expectation(&self) -> OutputExpectation<'_> {
+        OutputExpectation {
+            exact: self.stdout.as_deref(),
+            file: self.stdout_file.as_deref(),
+            lines: self.stdout_lines.as_deref(),
+            line_count: self.stdout_line_count,
+            empty: self.stdout_empty,
+            line_assertions: &self.assert_lines,
+            json: self.stdout_json.as_ref(),
+            json_assertions: &self.assert_json,
+            contains: &self.stdout_contains,
+            not_contains: &self.stdout_not_contains,
+            regex: self.stdout_regex.as_ref(),
+            bytes: [&self.stdout_base64, &self.stdout_hex]
+                .into_iter()
+                .flatten()
+                .map(Vec::as_slice)
+                .collect(),
+            trim_trailing_newline: false,
+            newline_mode: NewlineMode::default(),
+            tolerance: None,
+            compare: CompareMode::default(),
+            case_insensitive: false,
+            strip_ansi: false,
+            diffs: None,
+            mask: &self.mask,
+        }
+    }
+
+    pub(crate) fn stderr_expectation(&self) -> OutputExpectation<'_> {
+        OutputExpectation {
+            exact: self.stderr.as_deref(),
+            file: self.stderr_file.as_deref(),
+            lines: self.stderr_lines.as_deref(),
+            line_count: self.stderr_line_count,
+            empty: self.stderr_empty,
+            line_assertions: &[],
+            json: None,
+            json_assertions: &[],
+            contains: &self.stderr_contains,
+            not_contains: &self.stderr_not_contains,
+            regex: self.stderr_regex.as_ref(),
+            bytes: vec![],
+            trim_trailing_newline: false,
+            newline_mode: NewlineMode::default(),
+            tolerance: None,
+            compare: CompareMode::default(),
+            case_insensitive: false,
+            strip_ansi: false,
+            diffs: None,
+            mask: &self.mask,
+        }
+    }
+}
+
+/// The expectations of a test case on one of the output streams.
+pub(crate) struct OutputExpectation<'a> {
+    pub exact: Option<&'a str>,
+    pub file: Option<&'a Path>,
+    pub lines: Option<&'a [String]>,
+    pub line_count: Option<usize>,
+    pub empty: bool,
+    pub line_assertions: &'a [(i64, String)],
+    pub json: Option<&'a serde_json::Value>,
+    pub json_assertions: &'a [(json::Query, serde_json::Value)],
+    pub contains: &'a [String],
+    pub not_contains: &'a [String],
+    pub regex: Option<&'a Regex>,
+    pub bytes: Vec<&'a [u8]>,
+    pub trim_trailing_newline: bool,
+    pub newline_mode: NewlineMode,
+    pub tolerance: Option<pattern::Tolerance>,
+    pub compare: CompareMode,
+    pub case_insensitive: bool,
+    pub strip_ansi: bool,
+    pub mask: &'a [(Regex, String)],
+    /// Where mismatching texts are collected to show them with the diff
+    /// tool, besides the diff in the report.
+    pub diffs: Option<&'a RefCell<Vec<TextDiff>>>,
+}
+
+/// Removes the leading whitespace common to all lines of `text` that
+/// aren't blank. Blank lines are emptied.
+fn dedent(text: &str) -> String {
+    let mut common: Option<&str> = None;
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        common = Some(match common {
+            None => indent,
+            Some(common) => {
+                let shared = common
+                    .bytes()
+                    .zip(indent.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &common[..shared]
+            }
+        });
+    }
+    let common = common.unwrap_or_default().len();
+    text.split_inclusive('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                &line[line.trim_end_matches(['\n', '\r']).len()..]
+            } else {
+                &line[common..]
+            }
+        })
+        .collect()
+}
+
+/// Accepts either a single string or a list of strings.
+fn deserialize_one_or_many<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(d)? {
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    })
+}
+
+/// Accepts one or more ports or `host:port` addresses.
+fn deserialize_addresses<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Address {
+        Port(u16),
+        Address(String),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Address),
+        Many(Vec<Address>),
+    }
+
+    let addresses = match OneOrMany::deserialize(d)? {
+        OneOrMany::One(address) => vec![address],
+        OneOrMany::Many(addresses) => addresses,
+    };
+    Ok(addresses
+        .into_iter()
+        .map(|address| match address {
+            Address::Port(port) => format!("localhost:{}", port),
+            Address::Address(address) => address,
+        })
+        .collect())
+}
+
+/// Deserializes a map from names to services, keeping their order.
+fn deserialize_services<'a, D: serde::Deserializer<'a>>(
+    d: D,
+) -> Result<Vec<(String, Service)>, D::Error> {
+    serde_yaml::Mapping::deserialize(d)?
+        .into_iter()
+        .map(|(name, service)| {
+            let name = match name {
+                serde_yaml::Value::String(name) => name,
+                _ => return Err(D::Error::custom("Service names must be strings")),
+            };
+            let service = serde_yaml::from_value(service).map_err(D::Error::custom)?;
+            Ok((name, service))
+        })
+        .collect()
+}
+
+/// Accepts the name or the numeric id of a user or group.
+fn deserialize_id<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Id {
+        Number(u32),
+        Name(String),
+    }
+
+    Ok(Some(match Id::deserialize(d)? {
+        Id::Number(id) => id.to_string(),
+        Id::Name(name) => name,
+    }))
+}
+
+/// Accepts a file mode creation mask in octal like `"022"`, or its value.
+fn deserialize_umask<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Option<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Mask {
+        Value(u32),
+        Octal(String),
+    }
+
+    let mask = match Mask::deserialize(d)? {
+        Mask::Value(mask) => mask,
+        Mask::Octal(s) => u32::from_str_radix(s.trim_start_matches("0o"), 8).map_err(|_| {
+            D::Error::custom(format!("Invalid umask {:?}, expected octal digits", s))
+        })?,
+    };
+    if mask > 0o777 {
+        return Err(D::Error::custom(format!(
+            "Invalid umask {:o}, it can't exceed 777",
+            mask
+        )));
+    }
+    Ok(Some(mask))
+}
+
+/// Accepts one or more paths.
+fn deserialize_paths<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Vec<PathBuf>, D::Error> {
+    Ok(deserialize_one_or_many(d)?
+        .into_iter()
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Accepts one or more `http://` URLs.
+fn deserialize_urls<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Vec<String>, D::Error> {
+    let urls = deserialize_one_or_many(d)?;
+    if let Some(url) = urls.iter().find(|url| !url.starts_with("http://")) {
+        return Err(D::Error::custom(format!(
+            "Only http:// URLs can be waited for, not {}",
+            url
+        )));
+    }
+    Ok(urls)
+}
+
+/// Accepts a boolean or the reason for skipping a test.
+fn deserialize_skip<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Option<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Skip {
+        Flag(bool),
+        Reason(String),
+    }
+
+    Ok(match Skip::deserialize(d)? {
+        Skip::Flag(false) => None,
+        Skip::Flag(true) => Some("skipped in the configuration".to_string()),
+        Skip::Reason(reason) => Some(reason),
+    })
+}
+
+/// Accepts a list of `regex: replacement` pairs.
+fn deserialize_mask<'a, D: serde::Deserializer<'a>>(
+    d: D,
+) -> Result<Vec<(Regex, String)>, D::Error> {
+    let pairs = Vec::<BTreeMap<String, String>>::deserialize(d)?;
+    let mut mask = vec![];
+    for (regex, replacement) in pairs.into_iter().flatten() {
+        mask.push((Regex::new(&regex).map_err(D::Error::custom)?, replacement));
+    }
+    Ok(mask)
+}
+
+/// Accepts a list of `query: expected` pairs.
+fn deserialize_json_assertions<'a, D: serde::Deserializer<'a>>(
+    d: D,
+) -> Result<Vec<(json::Query, serde_json::Value)>, D::Error> {
+    let pairs = Vec::<BTreeMap<String, serde_json::Value>>::deserialize(d)?;
+    let mut assertions = vec![];
+    for (query, expected) in pairs.into_iter().flatten() {
+        let query = json::Query::parse(&query).map_err(D::Error::custom)?;
+        assertions.push((query, expected));
+    }
+    Ok(assertions)
+}
+
+/// Accepts a map of non-zero line numbers to the expected lines. The
+/// numbers may be strings, as TOML and JSON keys are.
+fn deserialize_line_assertions<'a, D: serde::Deserializer<'a>>(
+    d: D,
+) -> Result<Vec<(i64, String)>, D::Error> {
+    serde_yaml::Mapping::deserialize(d)?
+        .into_iter()
+        .map(|(line, expected)| {
+            let line = match &line {
+                serde_yaml::Value::Number(n) => n.as_i64(),
+                serde_yaml::Value::String(s) => s.trim().parse().ok(),
+                _ => None,
+            }
+            .filter(|&line| line != 0)
+            .ok_or_else(|| {
+                D::Error::custom("Line numbers must be non-zero integers, like 1 or -1")
+            })?;
+            let expected = serde_yaml::from_value(expected).map_err(D::Error::custom)?;
+            Ok((line, expected))
+        })
+        .collect()
+}
+
+fn deserialize_regex<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Option<Regex>, D::Error> {
+    let s = String::deserialize(d)?;
+    Regex::new(&s).map(Some).map_err(D::Error::custom)
+}
+
+fn deserialize_base64<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+    use base64::Engine;
+    let s = String::deserialize(d)?;
+    let s: String = s.split_whitespace().collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(s)
+        .map(Some)
+        .map_err(D::Error::custom)
+}
+
+fn deserialize_hex<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+    let s = String::deserialize(d)?;
+    let s: String = s.split_whitespace().collect();
+    hex::decode(s).map(Some).map_err(D::Error::custom)
+}
+
+/// A setting of all test cases replaced from the command line, like
+/// `exit-code=2` or `env.RUST_LOG=debug`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    /// The key of the setting, the keys of nested maps separated by dots.
+    pub key: Vec<String>,
+    pub value: serde_yaml::Value,
+}
+
+impl Override {
+    /// Sets the value in the test case `test`, creating the maps it is
+    /// nested in.
+    fn apply(&self, test: &mut serde_yaml::Value) -> Result<(), serde_yaml::Error> {
+        let (last, parents) = self.key.split_last().expect("keys aren't empty");
+        let Some(mut mapping) = test.as_mapping_mut() else {
+            // Deserializing the test case reports it.
+            return Ok(());
+        };
+        for (depth, key) in parents.iter().enumerate() {
+            let key = serde_yaml::Value::from(key.as_str());
+            if !mapping.contains_key(&key) {
+                mapping.insert(key.clone(), serde_yaml::Mapping::new().into());
+            }
+            mapping = match mapping.get_mut(&key) {
+                Some(serde_yaml::Value::Mapping(nested)) => nested,
+                _ => {
+                    return Err(serde_yaml::Error::custom(format!(
+                        "Cannot set {}, {} is not a map",
+                        self.key.join("."),
+                        self.key[..=depth].join(".")
+                    )))
+                }
+            };
+        }
+        mapping.insert(last.as_str().into(), self.value.clone());
+        Ok(())
+    }
+}
+
+/// Parses an [`Override`] given as `key=value`. The value is parsed as
+/// YAML, so that numbers, booleans and lists keep their type; values that
+/// aren't valid YAML and empty values are strings.
+pub fn parse_override(s: &str) -> Result<Override, String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Expected key=value, got {:?}", s))?;
+    let key: Vec<String> = key.split('.').map(String::from).collect();
+    if key.iter().any(String::is_empty) {
+        return Err(format!("Invalid key {:?}", key.join(".")));
+    }
+    let value = match serde_yaml::from_str(value) {
+        Ok(serde_yaml::Value::Null) | Err(_) => serde_yaml::Value::from(value),
+        Ok(value) => value,
+    };
+    Ok(Override { key, value })
+}
+
+/// Parses a duration like `500ms`, `30s`, `5m` or `1h`. Numbers without a
+/// unit are seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid duration {:?}", s))?;
+    let seconds = match unit.trim() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        unit => return Err(format!("Unknown unit {:?} in duration {:?}", unit, s)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|e| format!("Invalid duration {:?}: {}", s, e))
+}
+
+/// Parses a size in bytes like `4096`, `64K`, `512M` or `2G`. The units are
+/// powers of 1024.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("Invalid size {:?}", s))?;
+    let factor: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "K" | "KB" | "KiB" => 1 << 10,
+        "M" | "MB" | "MiB" => 1 << 20,
+        "G" | "GB" | "GiB" => 1 << 30,
+        unit => return Err(format!("Unknown unit {:?} in size {:?}", unit, s)),
+    };
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| format!("Size {:?} is too large", s))
+}
+
+fn deserialize_size<'a, D: serde::Deserializer<'a>>(d: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Bytes(u64),
+        Text(String),
+    }
+    match Value::deserialize(d)? {
+        Value::Bytes(bytes) => Ok(Some(bytes)),
+        Value::Text(s) => parse_size(&s).map(Some).map_err(D::Error::custom),
+    }
+}
+
+fn deserialize_duration<'a, D: serde::Deserializer<'a>>(
+    d: D,
+) -> Result<Option<Duration>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Seconds(f64),
+        Text(String),
+    }
+    match Value::deserialize(d)? {
+        Value::Seconds(seconds) => Duration::try_from_secs_f64(seconds)
+            .map(Some)
+            .map_err(D::Error::custom),
+        Value::Text(s) => parse_duration(&s).map(Some).map_err(D::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_configuration() {
+        let config = r#"
+command:
+  - echo
+  - foo
+
+exit-code: 0
+stdout: foo
+"#;
+        let config: Configuration = serde_yaml::from_str(config).unwrap();
+        assert_eq!(&config.command.0, "echo");
+        assert_eq!(&config.command.1, &["foo"]);
+        assert_eq!(config.stdout, Some("foo".to_string()));
+        assert_eq!(config.exit_code, ExitCode::Code(0));
+    }
+
+    #[test]
+    fn test_parse_configuration_command_single_string() {
+        let input = "command: foo bar baz";
+        let result: Result<Configuration, _> = serde_yaml::from_str(input);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert!(e
+                .to_string()
+                .starts_with("Please define a list instead of a string"));
+        }
+    }
+
+    #[test]
+    fn test_parse_configuration_command_empty_string() {
+        let input = r#"command: """#;
+        let result: Result<Configuration, _> = serde_yaml::from_str(input);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            assert_eq!(
+                e.to_string(),
+                "Command needs at least one element at line 1 column 8"
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_configuration_env_substitution() {
+        std::env::set_var("SMOKERS_TEST_GREETING", "hello");
+        let input = r#"
+command: [echo, "${SMOKERS_TEST_GREETING}"]
+stdout: "${SMOKERS_TEST_GREETING} ${SMOKERS_TEST_UNSET:-world}\n"
+stderr: "${SMOKERS_TEST_GREETING}"
+cwd: "${SMOKERS_TEST_UNSET:-/tmp}"
+env:
+  ${KEY}: "$${SMOKERS_TEST_GREETING}"
+"#;
+        let config = Configuration::from_reader(input.as_bytes()).unwrap();
+        assert_eq!(config.command.1, vec!["hello"]);
+        assert_eq!(config.stdout.as_deref(), Some("hello world\n"));
+        assert_eq!(config.stderr.as_deref(), Some("${SMOKERS_TEST_GREETING}"));
+        assert_eq!(config.cwd, Some(PathBuf::from("/tmp")));
+        assert_eq!(config.env["${KEY}"], "${SMOKERS_TEST_GREETING}");
+
+        let input = "command: [echo, \"${SMOKERS_TEST_UNSET}\"]";
+        let err = Configuration::from_reader(input.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("SMOKERS_TEST_UNSET is not set"));
+    }
+
+    #[test]
+    fn test_parse_configuration_shell() {
+        let parse = |s: &str| Configuration::from_reader(s.as_bytes());
+        let config = parse("command: ls | wc -l\nshell: true").unwrap();
+        assert_eq!(config.command.0, DEFAULT_SHELL);
+        assert_eq!(config.command.1.last().unwrap(), "ls | wc -l");
+        let config = parse("script: echo ${HOME}\nshell: bash").unwrap();
+        assert_eq!(config.command.0, "bash");
+        assert_eq!(config.command.1, vec!["-c", "echo ${HOME}"]);
+        let config = parse("script: dir\nshell: cmd").unwrap();
+        assert_eq!(config.command.1, vec!["/C", "dir"]);
+        let config = parse("script: Get-Date\nshell: pwsh").unwrap();
+        assert_eq!(
+            config.command.1,
+            vec!["-NoProfile", "-NonInteractive", "-Command", "Get-Date"]
+        );
+        assert!(parse("command: [ls]\nshell: false").is_ok());
+        assert!(parse("command: [ls]\nshell: true").is_err());
+        assert!(parse("command: ls\nscript: ls").is_err());
+    }
+
+    #[test]
+    fn test_parse_configuration_stdout_bytes_invalid() {
+        let parse = |s: &str| Configuration::from_reader(s.as_bytes());
+        assert!(parse("command: ls\nstdout-base64: '!!'").is_err());
+        assert!(parse("command: ls\nstdout-hex: abc").is_err());
+    }
+
+    #[test]
+    fn test_parse_configuration_stderr() {
+        let config = r#"
+command: foo
+stderr: "oops\n"
+stderr-contains: oops
+stderr-regex: "^o+ps$"
+"#;
+        let config: Configuration = serde_yaml::from_str(config).unwrap();
+        assert_eq!(config.stderr, Some("oops\n".to_string()));
+        assert_eq!(config.stderr_contains, vec!["oops".to_string()]);
+        assert_eq!(config.stderr_regex.unwrap().as_str(), "^o+ps$");
+    }
+
+    #[test]
+    fn test_parse_configuration_stderr_regex_invalid() {
+        let input = r#"
+command: foo
+stderr-regex: "(""#;
+        let result: Result<Configuration, _> = serde_yaml::from_str(input);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_suite_single() {
+        let suite = Suite::from_reader("command: [echo, foo]".as_bytes()).unwrap();
+        assert_eq!(suite.tests.len(), 1);
+        assert_eq!(suite.tests[0].name, None);
+        assert_eq!(&suite.tests[0].command.0, "echo");
+    }
+
+    #[test]
+    fn test_parse_suite_list() {
+        let input = r#"
+tests:
+  - name: first
+    command: [echo, foo]
+  - command: "true"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        assert_eq!(suite.tests.len(), 2);
+        assert_eq!(suite.tests[0].name, Some("first".to_string()));
+        assert_eq!(suite.tests[1].name, None);
+        assert_eq!(&suite.tests[1].command.0, "true");
+    }
+
+    #[test]
+    fn test_parse_suite_map() {
+        let input = r#"
+tests:
+  second:
+    command: [echo, foo]
+  first:
+    command: "true"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let names: Vec<_> = suite.tests.iter().map(|t| t.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("second"), Some("first")]);
+    }
+
+    #[test]
+    fn test_parse_suite_matrix() {
+        let input = r#"
+tests:
+  convert:
+    matrix:
+      format: [json, yaml]
+      code: [0, 1]
+    command: [convert, --format, "{{format}}", "--exit={{code}}"]
+    stdout: "{{format}} {{other}}\n"
+    stderr: "{{format}}"
+    exit-code: "{{code}}"
+  plain:
+    command: "true"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let names: Vec<_> = (0..suite.tests.len())
+            .map(|i| suite.test_name(i).unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "convert[format=json,code=0]",
+                "convert[format=json,code=1]",
+                "convert[format=yaml,code=0]",
+                "convert[format=yaml,code=1]",
+                "plain",
+            ]
+        );
+        let test = &suite.tests[1];
+        assert_eq!(test.command.1, vec!["--format", "json", "--exit=1"]);
+        assert_eq!(test.stdout.as_deref(), Some("json {{other}}\n"));
+        assert_eq!(test.stderr.as_deref(), Some("{{format}}"));
+        assert_eq!(test.exit_code, ExitCode::Code(1));
+
+        let input = "matrix: {n: [1, 2]}\ncommand: [echo, \"{{n}}\"]";
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        assert_eq!(suite.test_name(0).as_deref(), Some("[n=1]"));
+        assert_eq!(suite.tests[1].command.1, vec!["2"]);
+
+        let input = "matrix: {n: []}\ncommand: \"true\"";
+        assert!(Suite::from_reader(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_parse_suite_vars() {
+        let input = r#"
+vars:
+  bin: ./target/app
+  port: 8080
+before-all: [["${vars.bin}", init]]
+tests:
+  serve:
+    command: ["${vars.bin}", "--port=${vars.port}"]
+    stdout: "listening on ${vars.port}\n"
+    env:
+      HOME: "${HOME:-/}"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        assert_eq!(suite.before_all[0].0, "./target/app");
+        let test = &suite.tests[0];
+        assert_eq!(test.command.0, "./target/app");
+        assert_eq!(test.command.1, vec!["--port=8080"]);
+        assert_eq!(test.stdout.as_deref(), Some("listening on 8080\n"));
+        assert!(!test.env["HOME"].contains('$'));
+
+        let input = "vars: {a: x}
+command: [echo, \"${vars.b}\"]";
+        assert!(Suite::from_reader(input.as_bytes()).is_err());
+        let input = "vars: {a: [x]}
+command: \"true\"";
+        assert!(Suite::from_reader(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_parse_override() {
+        let parsed = parse_override("env.RUST_LOG=debug").unwrap();
+        assert_eq!(parsed.key, vec!["env", "RUST_LOG"]);
+        assert_eq!(parsed.value, serde_yaml::Value::from("debug"));
+        assert_eq!(
+            parse_override("exit-code=2").unwrap().value,
+            serde_yaml::Value::from(2)
+        );
+        assert_eq!(
+            parse_override("stdout=").unwrap().value,
+            serde_yaml::Value::from("")
+        );
+        assert_eq!(
+            parse_override("stdout=a: b=c").unwrap().value,
+            serde_yaml::from_str::<serde_yaml::Value>("{a: b=c}").unwrap()
+        );
+        assert!(parse_override("exit-code").is_err());
+        assert!(parse_override("env..X=1").is_err());
+    }
+
+    #[test]
+    fn test_parse_suite_overrides() {
+        let input = r#"
+tests:
+  - command: "false"
+    exit-code: 1
+    env: {A: a}
+  - command: "true"
+"#;
+        let overrides = ["exit-code=2", "env.RUST_LOG=debug", "tags=[slow]"]
+            .map(|s| parse_override(s).unwrap());
+        let value = serde_yaml::from_str(input).unwrap();
+        let suite = Suite::from_value(value, &overrides).unwrap();
+        for test in &suite.tests {
+            assert_eq!(test.exit_code, ExitCode::Code(2));
+            assert_eq!(test.env["RUST_LOG"], "debug");
+            assert_eq!(test.tags, vec!["slow"]);
+        }
+        assert_eq!(suite.tests[0].env["A"], "a");
+
+        let value = serde_yaml::from_str(input).unwrap();
+        let overrides = [parse_override("exit-code.code=2").unwrap()];
+        let error = Suite::from_value(value, &overrides).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Cannot set exit-code.code, exit-code is not a map"
+        );
+    }
+
+    #[test]
+    fn test_parse_suite_invalid_tests() {
+        let result = Suite::from_reader("tests: foo".as_bytes());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_suite_from_path_resolves_cwd() {
+        let dir = std::env::temp_dir().join(format!("smokers-cwd-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let path = dir.join("test.yaml");
+        std::fs::write(&path, "command: pwd\ncwd: sub\n").unwrap();
+        let suite = Suite::from_path(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(suite.tests[0].cwd, Some(dir.join("sub")));
+    }
+
+    #[test]
+    fn test_suite_from_path_includes() {
+        let dir = std::env::temp_dir().join(format!("smokers-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("common")).unwrap();
+        let path = dir.join("test.yaml");
+        let yaml = "include: common/shared.yaml\nafter-all: [[own]]\nvars: {shared: shared}\n\
+                    tests:\n  - command: \"${vars.own}\"\n";
+        std::fs::write(&path, yaml).unwrap();
+        let shared = "include: [base.yaml]\nbefore-all: [[shared]]\nafter-all: [[shared]]\n\
+                      vars: {own: own, shared: overridden}\n\
+                      tests:\n  - command: \"${vars.shared}\"\n    stdout-file: shared.out\n\
+                      \x20   files: {in.txt: {source: data/in.txt}, x: inline}\n\
+                      \x20   container: {image: alpine, mounts: [\"data:/data:ro\"]}\n\
+                      \x20   ssh: {host: example.com, identity-file: keys/id}\n";
+        std::fs::write(dir.join("common/shared.yaml"), shared).unwrap();
+        std::fs::write(
+            dir.join("common/base.yaml"),
+            "tests: [{command: base, nix: {packages: [jq, ./pkgs#tool]}}]",
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("common/pkgs")).unwrap();
+        let pkgs = std::fs::canonicalize(dir.join("common/pkgs")).unwrap();
+        let suite = Suite::from_path(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let commands: Vec<&str> = suite.tests.iter().map(|t| t.command.0.as_str()).collect();
+        assert_eq!(commands, vec!["base", "shared", "own"]);
+        assert_eq!(
+            suite.tests[0].nix,
+            Some(Nix::Packages(vec![
+                "jq".to_string(),
+                format!("{}#tool", pkgs.display())
+            ]))
+        );
+        assert_eq!(suite.before_all[0].0, "shared");
+        assert_eq!(suite.after_all[0].0, "own");
+        assert_eq!(
+            suite.tests[1].stdout_file,
+            Some(dir.join("common/shared.out"))
+        );
+        assert_eq!(
+            suite.tests[1].files[Path::new("in.txt")],
+            FixtureFile::Source {
+                source: dir.join("common/data/in.txt")
+            }
+        );
+        assert_eq!(
+            suite.tests[1].container.as_ref().unwrap().mounts,
+            [Mount {
+                source: dir.join("common/data"),
+                target: "/data:ro".to_string()
+            }]
+        );
+        assert_eq!(
+            suite.tests[1].ssh.as_ref().unwrap().identity_file,
+            Some(dir.join("common/keys/id"))
+        );
+        assert_eq!(
+            suite.tests[1].files[Path::new("x")],
+            FixtureFile::Content("inline".to_string())
+        );
+        assert_eq!(
+            suite.includes,
+            vec![dir.join("common/base.yaml"), dir.join("common/shared.yaml")]
+        );
+    }
+
+    #[test]
+    fn test_suite_from_path_toml() {
+        let dir = std::env::temp_dir().join(format!("smokers-toml-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let toml = r#"
+include = "shared.yaml"
+
+[tests.hello]
+command = ["echo", "hello"]
+stdout = "hello\n"
+exit-code = "0..=1"
+"#;
+        std::fs::write(dir.join("test.toml"), toml).unwrap();
+        std::fs::write(
+            dir.join("shared.yaml"),
+            "tests: {shared: {command: \"true\"}}",
+        )
+        .unwrap();
+        let suite = Suite::from_path(dir.join("test.toml")).unwrap();
+        let invalid = Suite::from_path_as(dir.join("test.toml"), ConfigFormat::Yaml);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(suite.tests.len(), 2);
+        assert_eq!(suite.tests[0].name.as_deref(), Some("shared"));
+        let hello = &suite.tests[1];
+        assert_eq!(
+            hello.command,
+            ("echo".to_string(), vec!["hello".to_string()])
+        );
+        assert_eq!(hello.stdout.as_deref(), Some("hello\n"));
+        assert_eq!(hello.exit_code, ExitCode::Range(0..=1));
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_suite_from_path_json() {
+        let dir = std::env::temp_dir().join(format!("smokers-json-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let json =
+            r#"{"tests": [{"command": ["echo", "hi"], "stdout": "hi\n", "exit-code": [0, 1]}]}"#;
+        std::fs::write(dir.join("test.json"), json).unwrap();
+        let suite = Suite::from_path(dir.join("test.json")).unwrap();
+        let invalid = Suite::from_path_as(dir.join("test.json"), ConfigFormat::Toml);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(suite.tests.len(), 1);
+        assert_eq!(suite.tests[0].stdout.as_deref(), Some("hi\n"));
+        assert_eq!(suite.tests[0].exit_code, ExitCode::List(vec![0, 1]));
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_suite_from_path_include_cycle() {
+        let dir = std::env::temp_dir().join(format!("smokers-cycle-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.yaml"), "include: b.yaml\ntests: []").unwrap();
+        std::fs::write(dir.join("b.yaml"), "include: a.yaml\ntests: []").unwrap();
+        let err = Suite::from_path(dir.join("a.yaml")).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(err.to_string().contains("includes itself"), "{}", err);
+    }
+
+    #[test]
+    fn test_suite_dependencies() {
+        let suite = Suite::from_reader(
+            r#"
+tests:
+  build:
+    command: "true"
+  convert:
+    command: "true"
+    matrix:
+      format: [json, yaml]
+  check:
+    command: "true"
+    depends-on: [build, convert]
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(suite.dependencies(3), vec![0, 1, 2]);
+        assert!(suite.dependencies(0).is_empty());
+
+        let unknown = "tests:\n  a:\n    command: \"true\"\n    depends-on: b\n";
+        let e = Suite::from_reader(unknown.as_bytes()).unwrap_err();
+        assert_eq!(e.to_string(), "Test a depends on the unknown test b");
+        let cycle = "tests:\n  a:\n    command: \"true\"\n    depends-on: b\n  b:\n    command: \"true\"\n    depends-on: a\n";
+        let e = Suite::from_reader(cycle.as_bytes()).unwrap_err();
+        assert_eq!(e.to_string(), "Test a has cyclic dependencies");
+    }
+
+    #[test]
+    fn test_suite_watched_paths() {
+        let dir = std::env::temp_dir().join(format!("smokers-watched-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.yaml");
+        let yaml = "watch-paths: [src]\ntests:\n  - command: ls\n    stdout-file: ls.out\n";
+        std::fs::write(&path, yaml).unwrap();
+        let suite = Suite::from_path(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            suite.watched_paths(),
+            vec![path.clone(), dir.join("ls.out"), dir.join("src")]
+        );
+        assert!(suite.is_affected_by(&[dir.join("src/main.rs")]));
+        assert!(suite.is_affected_by(&[path]));
+        assert!(!suite.is_affected_by(&[dir.join("other.yaml")]));
+    }
+
+    #[test]
+    fn test_parse_exit_code() {
+        let parse = |s: &str| -> ExitCode {
+            let config: Configuration =
+                serde_yaml::from_str(&format!("command: foo\nexit-code: {}", s)).unwrap();
+            config.exit_code
+        };
+        assert_eq!(parse("2"), ExitCode::Code(2));
+        assert_eq!(parse("[0, 1]"), ExitCode::List(vec![0, 1]));
+        assert_eq!(parse("1..3"), ExitCode::Range(1..=2));
+        assert_eq!(parse("1..=3"), ExitCode::Range(1..=3));
+        assert_eq!(parse("any"), ExitCode::Any);
+        assert_eq!(parse("nonzero"), ExitCode::NonZero);
+    }
+
+    #[test]
+    fn test_parse_exit_code_invalid() {
+        for s in &["foo", "[]", "3..1", "1..x"] {
+            let result: Result<Configuration, _> =
+                serde_yaml::from_str(&format!("command: foo\nexit-code: {}", s));
+            assert!(result.is_err(), "{} should be invalid", s);
+        }
+    }
+
+    #[test]
+    fn test_exit_code_matches() {
+        assert!(ExitCode::List(vec![0, 1]).matches(1));
+        assert!(!ExitCode::List(vec![0, 1]).matches(2));
+        assert!(ExitCode::Range(1..=3).matches(3));
+        assert!(!ExitCode::Range(1..=3).matches(0));
+        assert!(ExitCode::Any.matches(42));
+        assert!(ExitCode::NonZero.matches(42));
+        assert!(!ExitCode::NonZero.matches(0));
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+        assert!(parse_duration("5 days").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("").is_err());
+
+        let config: Configuration = serde_yaml::from_str("command: foo\ntimeout: 2").unwrap();
+        assert_eq!(config.timeout, Some(Duration::from_secs(2)));
+        let config: Configuration = serde_yaml::from_str("command: foo\ntimeout: 100ms").unwrap();
+        assert_eq!(config.timeout, Some(Duration::from_millis(100)));
+
+        let config = Configuration::from_reader(
+            "command: foo
+min-duration: 1s
+max-duration: 2s"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(config.min_duration, Some(Duration::from_secs(1)));
+        assert_eq!(config.max_duration, Some(Duration::from_secs(2)));
+        assert!(Configuration::from_reader(
+            "command: foo\nmin-duration: 2s\nmax-duration: 1s".as_bytes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_dedent() {
+        let input = "command: [x]\ndedent: true\nstdout: |2\n    usage:\n  \n      -h  help\n    done\nstderr: \"  a\\n   b\\n\\t c\"\n";
+        let config = Configuration::from_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            config.stdout.as_deref(),
+            Some("usage:\n\n  -h  help\ndone\n")
+        );
+        // Tabs and spaces are different indentation.
+        assert_eq!(config.stderr.as_deref(), Some("  a\n   b\n\t c"));
+        let config =
+            Configuration::from_reader("command: [x]\nstdout: \"  a\"\n".as_bytes()).unwrap();
+        assert_eq!(config.stdout.as_deref(), Some("  a"));
+    }
+
+    #[test]
+    fn test_parse_wait_for() {
+        let config: Configuration = serde_yaml::from_str(
+            "command: foo\nwait-for:\n  port: [8080, 'db:5432']\n  url: http://localhost:8080/health\n  timeout: 5s",
+        )
+        .unwrap();
+        assert_eq!(config.wait_for.port, ["localhost:8080", "db:5432"]);
+        assert_eq!(config.wait_for.url, ["http://localhost:8080/health"]);
+        assert_eq!(config.wait_for.timeout, Some(Duration::from_secs(5)));
+        assert!(serde_yaml::from_str::<Configuration>(
+            "command: foo\nwait-for:\n  url: https://localhost/"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_container_process() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [cat, /etc/os-release]
+runner: container
+env:
+  LC_ALL: C
+container:
+  image: debian:12
+  engine: podman
+  mounts: ["/srv/data:/data:ro"]
+  env:
+    DEBIAN_FRONTEND: noninteractive
+"#,
+        )
+        .unwrap();
+        let dir = std::env::temp_dir();
+        let dir = std::fs::canonicalize(&dir).unwrap();
+        let process = config.command_process(&dir);
+        assert_eq!(process.get_program(), "podman");
+        let args: Vec<_> = process.get_args().map(|a| a.to_str().unwrap()).collect();
+        let volume = format!("{0}:{0}", dir.display());
+        assert_eq!(
+            args,
+            [
+                "run",
+                "--rm",
+                "--interactive",
+                "--volume",
+                "/srv/data:/data:ro",
+                "--volume",
+                &volume,
+                "--workdir",
+                dir.to_str().unwrap(),
+                "--env",
+                "LC_ALL=C",
+                "--env",
+                "DEBIAN_FRONTEND=noninteractive",
+                "debian:12",
+                "cat",
+                "/etc/os-release",
+            ]
+        );
+
+        assert!(Configuration::from_reader("command: ls\nrunner: container".as_bytes()).is_err());
+        assert!(Configuration::from_reader(
+            "command: ls\nrunner: container\ncontainer:\n  image: alpine\n  mounts: [data]"
+                .as_bytes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_ssh_process() {
+        let config = Configuration::from_reader(
+            r#"
+command: [cat, "/etc/my app.conf"]
+runner: ssh
+env:
+  LC_ALL: C
+ssh:
+  host: staging.example.com
+  user: deploy
+  port: 2222
+  options: StrictHostKeyChecking=no
+  cwd: /srv/app
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let process = config.command_process(Path::new("."));
+        assert_eq!(process.get_program(), "ssh");
+        let args: Vec<_> = process.get_args().map(|a| a.to_str().unwrap()).collect();
+        assert_eq!(
+            args,
+            [
+                "-o",
+                "StrictHostKeyChecking=no",
+                "-o",
+                "BatchMode=yes",
+                "-p",
+                "2222",
+                "-T",
+                "deploy@staging.example.com",
+                "--",
+                "cd /srv/app && env LC_ALL=C cat '/etc/my app.conf'",
+            ]
+        );
+        assert!(Configuration::from_reader("command: ls\nrunner: ssh".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_nix_process() {
+        let args = |input: &str| {
+            let config = Configuration::from_reader(input.as_bytes()).unwrap();
+            let process = config.command_process(Path::new("."));
+            assert_eq!(process.get_program(), "nix");
+            process
+                .get_args()
+                .map(|a| a.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            args("command: [jq, --version]\nnix: [jq, 'github:owner/repo#tool']"),
+            [
+                "shell",
+                "nixpkgs#jq",
+                "github:owner/repo#tool",
+                "--command",
+                "jq",
+                "--version"
+            ]
+        );
+        assert_eq!(
+            args("command: [make, test]\nnix:\n  develop: .#ci"),
+            ["develop", ".#ci", "--command", "make", "test"]
+        );
+        assert!(Configuration::from_reader("command: ls\nnix: {}".as_bytes()).is_err());
+        assert!(Configuration::from_reader(
+            "command: ls\nnix:\n  develop: .#ci\n  packages: [jq]".as_bytes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_skip_reason() {
+        let config: Configuration = serde_yaml::from_str("command: foo").unwrap();
+        assert_eq!(config.skip_reason(), None);
+        let config: Configuration = serde_yaml::from_str("command: foo\nskip: true").unwrap();
+        assert_eq!(
+            config.skip_reason(),
+            Some("skipped in the configuration".to_string())
+        );
+        let config: Configuration =
+            serde_yaml::from_str("command: foo\nskip: broken on CI").unwrap();
+        assert_eq!(config.skip_reason(), Some("broken on CI".to_string()));
+
+        let os = std::env::consts::OS;
+        let config: Configuration =
+            serde_yaml::from_str(&format!("command: foo\nskip-if:\n  os: [plan9, {}]", os))
+                .unwrap();
+        assert_eq!(config.skip_reason(), Some(format!("running on {}", os)));
+        let config: Configuration = serde_yaml::from_str(
+            "command: foo\nskip-if:\n  env-set: SMOKERS_UNSET_VARIABLE\n  missing-binary: [sh, smokers-missing-binary]",
+        )
+        .unwrap();
+        assert_eq!(
+            config.skip_reason(),
+            Some("smokers-missing-binary is not installed".to_string())
+        );
+        assert!(
+            serde_yaml::from_str::<Configuration>("command: foo\nskip-if:\n  arch: x86").is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let config: Configuration = serde_yaml::from_str(
+            "command: foo\nlimits: {memory: 512M, cpu: 1.5, file-size: 4096, open-files: 64}",
+        )
+        .unwrap();
+        assert_eq!(
+            config.limits,
+            Limits {
+                memory: Some(512 << 20),
+                cpu: Some(Duration::from_millis(1500)),
+                file_size: Some(4096),
+                open_files: Some(64),
+                processes: None,
+            }
+        );
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert!(parse_size("1T").is_err());
+        assert!(serde_yaml::from_str::<Configuration>("command: foo\nlimits: {stack: 1}").is_err());
+    }
+
+    #[test]
+    fn test_parse_files_invalid_path() {
+        for path in &["/etc/passwd", "../x", "a/../../x"] {
+            let input = format!("command: foo\nfiles: {{{}: x}}", path);
+            let result = Configuration::from_reader(input.as_bytes());
+            assert!(result.is_err(), "{} should be invalid", path);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Rendering of differences between expected and actual output.
+
+use similar::TextDiff;
+
+/// Renders a line based unified diff turning `expected` into `actual`.
+pub fn unified_diff(
+    expected_label: &str,
+    actual_label: &str,
+    expected: &str,
+    actual: &str,
+) -> String {
+    TextDiff::from_lines(expected, actual)
+        .unified_diff()
+        .context_radius(3)
+        .header(expected_label, actual_label)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unified_diff() {
+        let diff = unified_diff("expected", "actual", "foo\nbar\nbaz\n", "foo\nqux\nbaz\n");
+        assert_eq!(
+            diff,
+            "--- expected\n+++ actual\n@@ -1,3 +1,3 @@\n foo\n-bar\n+qux\n baz\n"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_missing_newline() {
+        let diff = unified_diff("expected", "actual", "foo", "foo\n");
+        assert!(diff.contains("\\ No newline at end of file"), "{}", diff);
+    }
+}
@@ -0,0 +1,251 @@
+//! Discovery of configuration files in directories.
+
+use crate::Error;
+use std::path::{Path, PathBuf};
+
+/// The file name suffixes of configuration files picked up during
+/// discovery.
+const SUFFIXES: &[&str] = &[".smoke.yaml", ".smoke.yml", ".smoke.toml", ".smoke.json"];
+
+/// Whether the file name marks the path as a configuration file.
+pub fn is_config_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| SUFFIXES.iter().any(|s| name.ends_with(s)))
+}
+
+/// The name of the files listing paths that are skipped during discovery,
+/// in the syntax of `.gitignore`.
+pub const IGNORE_FILE: &str = ".smokersignore";
+
+/// Recursively collects all configuration files below `dir`, sorted by
+/// path. Paths matched by an [`IGNORE_FILE`] in `dir` or the directories
+/// below it are skipped.
+pub fn discover(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    walk(dir, &mut vec![], &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// Expands a command line argument into configuration files.
+///
+/// Arguments containing glob characters are expanded, directories are
+/// searched recursively using [`discover`], anything else is taken as a
+/// configuration file.
+pub fn expand(arg: &str) -> Result<Vec<PathBuf>, Error> {
+    let paths = if arg.contains(['*', '?', '[']) {
+        let paths = glob::glob(arg)?.collect::<Result<Vec<_>, _>>()?;
+        if paths.is_empty() {
+            return Err(Error::NoMatches(arg.to_string()));
+        }
+        paths
+    } else {
+        vec![PathBuf::from(arg)]
+    };
+
+    let mut files = vec![];
+    for path in paths {
+        if path.is_dir() {
+            let found = discover(&path)?;
+            if found.is_empty() {
+                return Err(Error::NoMatches(path.display().to_string()));
+            }
+            files.extend(found);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+fn walk(dir: &Path, ignores: &mut Vec<Ignore>, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let ignore_file = dir.join(IGNORE_FILE);
+    let pushed = ignore_file.is_file();
+    if pushed {
+        ignores.push(Ignore::parse(dir, &std::fs::read_to_string(ignore_file)?));
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_dir = entry.file_type()?.is_dir();
+        if is_ignored(ignores, &path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            walk(&path, ignores, files)?;
+        } else if is_config_file(&path) {
+            files.push(path);
+        }
+    }
+    if pushed {
+        ignores.pop();
+    }
+    Ok(())
+}
+
+/// Whether the last rule matching `path` ignores it. Rules of deeper
+/// ignore files take precedence.
+fn is_ignored(ignores: &[Ignore], path: &Path, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .find_map(|ignore| ignore.matches(path, is_dir))
+        .unwrap_or(false)
+}
+
+/// The rules of an ignore file.
+struct Ignore {
+    /// The directory containing the ignore file, anchored patterns are
+    /// relative to it.
+    base: PathBuf,
+    rules: Vec<Rule>,
+}
+
+struct Rule {
+    pattern: glob::Pattern,
+    /// Re-include paths excluded by earlier rules (`!pattern`).
+    negated: bool,
+    /// Only match directories (`pattern/`).
+    dir_only: bool,
+    /// Match the path relative to the base instead of the file name, for
+    /// patterns containing a slash.
+    anchored: bool,
+}
+
+impl Ignore {
+    /// Parses the rules in `source`. Invalid patterns are skipped, like git
+    /// does.
+    fn parse(base: &Path, source: &str) -> Self {
+        let rules = source
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim_end();
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line.strip_prefix('\\').unwrap_or(line)),
+                };
+                let (dir_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                let line = line.strip_prefix('/').unwrap_or(line);
+                Some(Rule {
+                    pattern: glob::Pattern::new(line).ok()?,
+                    negated,
+                    dir_only,
+                    anchored,
+                })
+            })
+            .collect();
+        Self {
+            base: base.to_path_buf(),
+            rules,
+        }
+    }
+
+    /// Whether `path` is ignored, or `None` if no rule matches it.
+    fn matches(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = path.strip_prefix(&self.base).ok()?;
+        let name = Path::new(path.file_name()?);
+        let options = glob::MatchOptions {
+            require_literal_separator: true,
+            ..glob::MatchOptions::new()
+        };
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| {
+                let target = if rule.anchored { relative } else { name };
+                (is_dir || !rule.dir_only) && rule.pattern.matches_path_with(target, options)
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand() {
+        let dir = std::env::temp_dir().join(format!("smokers-expand-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        for file in &["a.yaml", "b.yaml", "c.yml", "sub/d.smoke.yaml"] {
+            std::fs::write(dir.join(file), "command: \"true\"").unwrap();
+        }
+        let pattern = format!("{}/*.yaml", dir.display());
+        let globbed = expand(&pattern).unwrap();
+        let plain = expand(dir.join("c.yml").to_str().unwrap()).unwrap();
+        let directory = expand(dir.join("sub").to_str().unwrap()).unwrap();
+        let nothing = expand(&format!("{}/*.toml", dir.display()));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(globbed, vec![dir.join("a.yaml"), dir.join("b.yaml")]);
+        assert_eq!(plain, vec![dir.join("c.yml")]);
+        assert_eq!(directory, vec![dir.join("sub/d.smoke.yaml")]);
+        assert!(matches!(nothing, Err(Error::NoMatches(_))));
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = std::env::temp_dir().join(format!("smokers-discover-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("b/c")).unwrap();
+        for file in &[
+            "a.smoke.yaml",
+            "b/c/d.smoke.yml",
+            "b/e.yaml",
+            "b/f.smoke.yaml.orig",
+            "b/g.smoke.toml",
+        ] {
+            std::fs::write(dir.join(file), "command: \"true\"").unwrap();
+        }
+        let files = discover(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.join("a.smoke.yaml"),
+                dir.join("b/c/d.smoke.yml"),
+                dir.join("b/g.smoke.toml")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_discover_ignore_file() {
+        let dir = std::env::temp_dir().join(format!("smokers-ignore-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("vendor/x")).unwrap();
+        std::fs::create_dir_all(dir.join("b/generated")).unwrap();
+        for file in &[
+            "a.smoke.yaml",
+            "slow.smoke.yaml",
+            "keep.slow.smoke.yaml",
+            "vendor/x/v.smoke.yaml",
+            "b/generated/g.smoke.yaml",
+            "b/c.smoke.yaml",
+            "b/d.smoke.yaml",
+        ] {
+            std::fs::write(dir.join(file), "command: \"true\"").unwrap();
+        }
+        std::fs::write(
+            dir.join(IGNORE_FILE),
+            "# vendored tests\n/vendor/\n*slow.smoke.yaml\n!keep.*\ngenerated/\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("b").join(IGNORE_FILE), "c.smoke.yaml\n").unwrap();
+        let files = discover(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                dir.join("a.smoke.yaml"),
+                dir.join("b/d.smoke.yaml"),
+                dir.join("keep.slow.smoke.yaml"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! A stream of JSON objects describing the progress of a test run, one per
+//! line, for dashboards and wrappers that track the tests in real time.
+
+use crate::report::{Reporter, TestResult};
+use serde_json::{json, Value};
+use std::io::Write;
+
+/// The format of the event stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum EventFormat {
+    /// One JSON object per line.
+    Jsonl,
+}
+
+/// Writes an event for each call to `out` and forwards it to another
+/// reporter, if any.
+///
+/// The events are `run_started`, `test_started`, `output` with the
+/// captured output of a finished test, `test_finished` and `run_finished`.
+pub struct EventReporter<'a, W: Write> {
+    inner: Option<&'a mut dyn Reporter>,
+    out: W,
+}
+
+impl<'a, W: Write> EventReporter<'a, W> {
+    pub fn new(inner: Option<&'a mut dyn Reporter>, out: W) -> Self {
+        Self { inner, out }
+    }
+
+    fn emit(&mut self, event: Value) -> std::io::Result<()> {
+        serde_json::to_writer(&mut self.out, &event)?;
+        writeln!(self.out)?;
+        // Consumers follow the stream as it is written.
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Reporter for EventReporter<'_, W> {
+    fn start(&mut self, total: usize) -> std::io::Result<()> {
+        self.emit(json!({"event": "run_started", "total": total}))?;
+        match &mut self.inner {
+            Some(inner) => inner.start(total),
+            None => Ok(()),
+        }
+    }
+
+    fn test_started(&mut self, index: usize, name: &str) -> std::io::Result<()> {
+        self.emit(json!({"event": "test_started", "index": index, "name": name}))?;
+        match &mut self.inner {
+            Some(inner) => inner.test_started(index, name),
+            None => Ok(()),
+        }
+    }
+
+    fn test_finished(&mut self, index: usize, report: &TestResult) -> std::io::Result<()> {
+        self.emit(json!({
+            "event": "output",
+            "index": index,
+            "stdout": report.stdout,
+            "stderr": report.stderr,
+        }))?;
+        let mut event = serde_json::to_value(report)?;
+        if let Value::Object(fields) = &mut event {
+            fields.remove("stdout");
+            fields.remove("stderr");
+            fields.insert("event".to_string(), json!("test_finished"));
+            fields.insert("index".to_string(), json!(index));
+        }
+        self.emit(event)?;
+        match &mut self.inner {
+            Some(inner) => inner.test_finished(index, report),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self, reports: &[TestResult]) -> std::io::Result<()> {
+        let passed = reports.iter().filter(|r| r.passed).count();
+        self.emit(json!({
+            "event": "run_finished",
+            "passed": passed,
+            "failed": reports.len() - passed,
+        }))?;
+        match &mut self.inner {
+            Some(inner) => inner.finish(reports),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events() {
+        let mut out = vec![];
+        let report = TestResult {
+            name: Some("hello".to_string()),
+            passed: true,
+            stdout: "Hello\n".to_string(),
+            ..TestResult::default()
+        };
+        {
+            let mut events = EventReporter::new(None, &mut out);
+            events.start(1).unwrap();
+            events.test_started(0, "hello").unwrap();
+            events.test_finished(0, &report).unwrap();
+            events.finish(std::slice::from_ref(&report)).unwrap();
+        }
+        let events: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 5);
+        assert_eq!(events[0], json!({"event": "run_started", "total": 1}));
+        assert_eq!(
+            events[1],
+            json!({"event": "test_started", "index": 0, "name": "hello"})
+        );
+        assert_eq!(events[2]["event"], "output");
+        assert_eq!(events[2]["stdout"], "Hello\n");
+        assert_eq!(events[3]["event"], "test_finished");
+        assert_eq!(events[3]["name"], "hello");
+        assert_eq!(events[3]["passed"], true);
+        assert!(events[3].get("stdout").is_none());
+        assert_eq!(
+            events[4],
+            json!({"event": "run_finished", "passed": 1, "failed": 0})
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Starter configuration files, for `smokers init`.
+
+/// Characters that make a command line need a shell to run as intended.
+const SHELL_CHARACTERS: &[char] = &[
+    '|', '&', ';', '<', '>', '(', ')', '$', '`', '\\', '"', '\'', '*', '?', '[', ']', '#', '~',
+    '{', '}',
+];
+
+/// Settings of the starter configuration that are commented out.
+const OPTIONAL_SETTINGS: &str = r#"    # (optional) text stdout has to contain, instead of the exact text.
+    # stdout-contains: [hello]
+    # (optional) nothing may be written to stderr.
+    # stderr-empty: true
+    # (optional) text written to the stdin of the process.
+    # stdin: "some input\n"
+    # (optional) environment variables set for the process.
+    # env:
+    #   LC_ALL: C
+    # (optional) kill the process and fail if it runs longer than this.
+    # timeout: 10s
+    # (optional) run the process in a fresh temporary directory.
+    # sandbox: true
+    # (optional) tags to select tests with `--tag`.
+    # tags: [smoke]
+"#;
+
+/// A configuration file with a single test called `name`, demonstrating
+/// the main settings in comments. `command` is run by the test if given,
+/// split at whitespace or run with a shell if it needs one.
+pub fn starter_config(name: &str, command: Option<&str>) -> String {
+    let mut config = String::new();
+    let file = format!("{}.smoke.yaml", name);
+    config.push_str(&format!(
+        "# Smoke tests, run them with `smokers {}`.\ntests:\n  {}:\n",
+        file,
+        yaml_word(name)
+    ));
+    match command.map(str::trim) {
+        Some(command) if command.contains(SHELL_CHARACTERS) => {
+            config.push_str("    # The script that is run with `sh -c`.\n");
+            config.push_str(&format!("    script: {}\n", yaml_word(command)));
+        }
+        Some(command) => {
+            let words: Vec<String> = command.split_whitespace().map(yaml_word).collect();
+            config.push_str("    # The program that is run and its arguments.\n");
+            config.push_str(&format!("    command: [{}]\n", words.join(", ")));
+        }
+        None => {
+            config.push_str("    # The program that is run and its arguments.\n");
+            config.push_str("    command: [echo, hello world]\n");
+        }
+    }
+    config.push_str("    # The expected exit code, 0 by default.\n    exit-code: 0\n");
+    match command {
+        Some(_) => config.push_str(&format!(
+            "    # The exact text expected on stdout. Fill it in with\n    \
+             # `smokers {} --update-expected`.\n    stdout: \"\"\n",
+            file
+        )),
+        None => config
+            .push_str("    # The exact text expected on stdout.\n    stdout: \"hello world\\n\"\n"),
+    }
+    config.push_str(OPTIONAL_SETTINGS);
+    config
+}
+
+/// Quotes `word` unless YAML reads it as the same string in a flow list.
+fn yaml_word(word: &str) -> String {
+    let plain = serde_yaml::from_str::<Vec<serde_yaml::Value>>(&format!("[{}]", word));
+    match plain.as_deref() {
+        Ok([serde_yaml::Value::String(s)]) if s == word => word.to_string(),
+        _ => serde_json::Value::from(word).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Suite;
+
+    #[test]
+    fn test_starter_config() {
+        let suite = Suite::from_reader(starter_config("example", None).as_bytes()).unwrap();
+        let test = &suite.tests[0];
+        assert_eq!(test.name.as_deref(), Some("example"));
+        assert_eq!(test.command.0, "echo");
+        assert_eq!(test.stdout.as_deref(), Some("hello world\n"));
+
+        let config = starter_config("mytool", Some("mytool --version 1 true"));
+        assert!(config.contains("command: [mytool, --version, \"1\", \"true\"]\n"));
+        assert!(config.contains("`smokers mytool.smoke.yaml --update-expected`"));
+        let suite = Suite::from_reader(config.as_bytes()).unwrap();
+        let test = &suite.tests[0];
+        assert_eq!(test.command.1, vec!["--version", "1", "true"]);
+        assert_eq!(test.stdout.as_deref(), Some(""));
+
+        let config = starter_config("pipe", Some("mytool | grep \"x: y\""));
+        let suite = Suite::from_reader(config.as_bytes()).unwrap();
+        assert_eq!(
+            suite.tests[0].command.1,
+            vec!["-c", "mytool | grep \"x: y\""]
+        );
+    }
+}
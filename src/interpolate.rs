@@ -0,0 +1,129 @@
+//! Substitution of environment variables and suite variables in
+//! configuration values.
+
+use std::collections::BTreeMap;
+
+/// Replaces `${VAR}` and `${VAR:-default}` in `s` with the values returned
+/// by `lookup`. The default is used if the variable is unset or empty, `$$`
+/// produces a literal `$`.
+pub fn interpolate(s: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(tail) = rest.strip_prefix("$$") {
+            result.push('$');
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix("${") {
+            let end = tail
+                .find('}')
+                .ok_or_else(|| format!("Unterminated variable reference in {:?}", s))?;
+            let (name, default) = match tail[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&tail[..end], None),
+            };
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(format!("Invalid variable name {:?} in {:?}", name, s));
+            }
+            match (lookup(name), default) {
+                (Some(value), Some(default)) if value.is_empty() => result.push_str(default),
+                (Some(value), _) => result.push_str(&value),
+                (None, Some(default)) => result.push_str(default),
+                (None, None) => return Err(format!("Environment variable {} is not set", name)),
+            }
+            rest = &tail[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Substitutes environment variables of the process in `s`.
+pub fn interpolate_env(s: &str) -> Result<String, String> {
+    interpolate(s, |name| std::env::var(name).ok())
+}
+
+/// Replaces `${vars.NAME}` in `s` with the value of `NAME` among `vars`.
+/// Environment variables and `$$` are left as they are.
+pub fn interpolate_vars(s: &str, vars: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(tail) = rest.strip_prefix("$$") {
+            result.push_str("$$");
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix("${vars.") {
+            let end = tail
+                .find('}')
+                .ok_or_else(|| format!("Unterminated variable reference in {:?}", s))?;
+            let name = &tail[..end];
+            let value = vars
+                .get(name)
+                .ok_or_else(|| format!("Unknown variable vars.{} in {:?}", name, s))?;
+            result.push_str(value);
+            rest = &tail[end + 1..];
+        } else {
+            result.push('$');
+            rest = &rest[1..];
+        }
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/user".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let i = |s: &str| interpolate(s, lookup);
+        assert_eq!(i("plain"), Ok("plain".to_string()));
+        assert_eq!(i("${HOME}/bin"), Ok("/home/user/bin".to_string()));
+        assert_eq!(i("${PORT:-8080}"), Ok("8080".to_string()));
+        assert_eq!(i("${HOME:-/}"), Ok("/home/user".to_string()));
+        assert_eq!(i("${EMPTY:-x}"), Ok("x".to_string()));
+        assert_eq!(i("a${EMPTY}b"), Ok("ab".to_string()));
+        assert_eq!(i("$$HOME $HOME"), Ok("$HOME $HOME".to_string()));
+        assert_eq!(i("${URL:-http://a:1}"), Ok("http://a:1".to_string()));
+    }
+
+    #[test]
+    fn test_interpolate_errors() {
+        let i = |s: &str| interpolate(s, lookup);
+        assert_eq!(
+            i("${PORT}"),
+            Err("Environment variable PORT is not set".to_string())
+        );
+        assert!(i("${HOME").is_err());
+        assert!(i("${}").is_err());
+        assert!(i("${A B}").is_err());
+    }
+
+    #[test]
+    fn test_interpolate_vars() {
+        let vars = BTreeMap::from([("bin".to_string(), "./target/app".to_string())]);
+        let i = |s: &str| interpolate_vars(s, &vars);
+        assert_eq!(i("${vars.bin} -v"), Ok("./target/app -v".to_string()));
+        assert_eq!(i("${HOME} $$ $x"), Ok("${HOME} $$ $x".to_string()));
+        assert_eq!(
+            i("${vars.lib}"),
+            Err("Unknown variable vars.lib in \"${vars.lib}\"".to_string())
+        );
+        assert!(i("${vars.bin").is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! Structural comparison of JSON output and queries on it.
+
+use serde_json::Value;
+
+/// Whether `a` and `b` are the same JSON value. Objects are compared
+/// regardless of the order of their keys and numbers by their value, so
+/// `1` equals `1.0`.
+pub fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => a == b,
+            _ => a.as_f64() == b.as_f64(),
+        },
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| equal(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(key, a)| b.get(key).is_some_and(|b| equal(a, b)))
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// Formats `value` for diffs, with one line per array element and object
+/// member and the keys of objects in sorted order.
+pub fn pretty(value: &Value) -> String {
+    let mut text = serde_json::to_string_pretty(value).expect("JSON values can be serialized");
+    text.push('\n');
+    text
+}
+
+/// A jq-style query selecting a value from a JSON document, like
+/// `.items[0].name` or `.items | length`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    text: String,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    Key(String),
+    Index(i64),
+    Length,
+    Keys,
+    Type,
+}
+
+impl Query {
+    /// Parses a query made of paths like `.a.b`, `.["a b"]` and `.[0]`,
+    /// optionally piped into `length`, `keys` or `type`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = |reason: &str| format!("Invalid JSON query {:?}: {}", text, reason);
+        let mut steps = vec![];
+        for part in text.split('|').map(str::trim) {
+            match part {
+                "length" => steps.push(Step::Length),
+                "keys" => steps.push(Step::Keys),
+                "type" => steps.push(Step::Type),
+                "." => {}
+                _ if part.starts_with('.') => parse_path(part, &mut steps).map_err(invalid)?,
+                _ => return Err(invalid("expected a path starting with `.`")),
+            }
+        }
+        Ok(Self {
+            text: text.to_string(),
+            steps,
+        })
+    }
+
+    /// Selects the value from `document`.
+    pub fn evaluate(&self, document: &Value) -> Result<Value, String> {
+        let mut value = document.clone();
+        for step in &self.steps {
+            value = match (step, value) {
+                (Step::Key(key), Value::Object(mut object)) => object
+                    .remove(key)
+                    .ok_or_else(|| format!("no key {:?}", key))?,
+                (Step::Index(index), Value::Array(mut array)) => {
+                    let len = array.len() as i64;
+                    let position = if *index < 0 { len + index } else { *index };
+                    if !(0..len).contains(&position) {
+                        return Err(format!("index {} out of range for {} elements", index, len));
+                    }
+                    array.swap_remove(position as usize)
+                }
+                (Step::Length, Value::Array(array)) => array.len().into(),
+                (Step::Length, Value::Object(object)) => object.len().into(),
+                (Step::Length, Value::String(s)) => s.chars().count().into(),
+                (Step::Length, Value::Null) => 0.into(),
+                (Step::Keys, Value::Object(object)) => {
+                    object.keys().cloned().map(Value::from).collect()
+                }
+                (Step::Type, value) => type_name(&value).into(),
+                (step, value) => {
+                    return Err(format!("can't apply {} to {}", step, type_name(&value)))
+                }
+            };
+        }
+        Ok(value)
+    }
+}
+
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Key(key) => write!(f, "key {:?}", key),
+            Step::Index(index) => write!(f, "index {}", index),
+            Step::Length => f.write_str("length"),
+            Step::Keys => f.write_str("keys"),
+            Step::Type => f.write_str("type"),
+        }
+    }
+}
+
+fn parse_path(path: &str, steps: &mut Vec<Step>) -> Result<(), &'static str> {
+    let mut rest = path;
+    while !rest.is_empty() {
+        rest = rest.strip_prefix('.').unwrap_or(rest);
+        if let Some(bracket) = rest.strip_prefix('[') {
+            let end = bracket.find(']').ok_or("unterminated `[`")?;
+            let inner = bracket[..end].trim();
+            match inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+                Some(key) => steps.push(Step::Key(key.to_string())),
+                None => steps.push(Step::Index(
+                    inner
+                        .parse()
+                        .map_err(|_| "expected an index or a quoted key")?,
+                )),
+            }
+            rest = &bracket[end + 1..];
+        } else if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').ok_or("unterminated quoted key")?;
+            steps.push(Step::Key(quoted[..end].to_string()));
+            rest = &quoted[end + 1..];
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_' && c != '-')
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return Err("expected a key");
+            }
+            steps.push(Step::Key(rest[..end].to_string()));
+            rest = &rest[end..];
+        }
+        if !rest.is_empty() && !rest.starts_with(['.', '[']) {
+            return Err("expected `.` or `[`");
+        }
+    }
+    Ok(())
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_equal() {
+        assert!(equal(
+            &json!({"a": 1, "b": [1, 2.0, {"c": null}]}),
+            &json!({"b": [1.0, 2, {"c": null}], "a": 1})
+        ));
+        assert!(!equal(&json!({"a": 1}), &json!({"a": 1, "b": 2})));
+        assert!(!equal(&json!([1, 2]), &json!([2, 1])));
+        assert!(!equal(&json!("1"), &json!(1)));
+    }
+
+    #[test]
+    fn test_query() {
+        let document = json!({
+            "version": "1.2.3",
+            "items": [{"name": "a"}, {"name": "b"}],
+            "key with spaces": true,
+        });
+        let query = |q: &str| Query::parse(q).unwrap().evaluate(&document);
+        assert_eq!(query("."), Ok(document.clone()));
+        assert_eq!(query(".version"), Ok(json!("1.2.3")));
+        assert_eq!(query(".items[1].name"), Ok(json!("b")));
+        assert_eq!(query(".items.[-1].name"), Ok(json!("b")));
+        assert_eq!(query(".[\"key with spaces\"]"), Ok(json!(true)));
+        assert_eq!(query(".items | length"), Ok(json!(2)));
+        assert_eq!(query(".items[0] | keys"), Ok(json!(["name"])));
+        assert_eq!(query(".version | type"), Ok(json!("string")));
+        assert_eq!(query(".items | .[0] | .name"), Ok(json!("a")));
+        assert_eq!(query(".missing"), Err("no key \"missing\"".to_string()));
+        assert_eq!(
+            query(".items[2]"),
+            Err("index 2 out of range for 2 elements".to_string())
+        );
+        assert_eq!(
+            query(".version.major"),
+            Err("can't apply key \"major\" to string".to_string())
+        );
+    }
+
+    #[test]
+    fn test_query_invalid() {
+        for query in &[
+            "version",
+            ".items[",
+            ".items[x]",
+            ".a..b",
+            ".a b",
+            "| length",
+        ] {
+            assert!(Query::parse(query).is_err(), "{} should be invalid", query);
+        }
+    }
+}
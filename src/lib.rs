@@ -0,0 +1,71 @@
+//! smokers runs commands and checks their exit code and output against
+//! the expectations defined in YAML configuration files.
+//!
+//! Besides the command line interface the crate can be used to run test
+//! cases programmatically:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), smokers::Error> {
+//! let config = smokers::Configuration::from_reader(
+//!     "command: [echo, hello]\nstdout: \"hello\\n\"".as_bytes(),
+//! )?;
+//! let result = smokers::run_test(&config)?;
+//! assert!(result.passed);
+//! # Ok(())
+//! # }
+//! ```
+
+mod cache;
+pub mod config;
+mod diff;
+pub mod discover;
+pub mod events;
+pub mod init;
+mod interpolate;
+pub mod json;
+pub mod manifest;
+pub mod pattern;
+mod process;
+pub mod progress;
+mod pty;
+pub mod report;
+pub mod review;
+pub mod runner;
+mod sandbox;
+mod service;
+pub mod signal;
+mod update;
+pub mod validate;
+mod wait;
+pub mod watch;
+
+pub use config::{CompareMode, ConfigFormat, Configuration, ExitCode, NewlineMode, Suite};
+pub use report::TestResult;
+pub use runner::{run_suites, run_test, run_test_with_options, RunOptions};
+
+use thiserror::Error;
+
+/// The errors that prevent smokers from running a test.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("Parse error: {0}")]
+    Parse(#[from] serde_yaml::Error),
+    #[error("Parse error: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("Parse error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid pattern: {0}")]
+    Pattern(#[from] glob::PatternError),
+    #[error("Failed to expand pattern: {0}")]
+    Glob(#[from] glob::GlobError),
+    #[error("No configuration files found for {0}")]
+    NoMatches(String),
+    #[error("Failed to include {0}: {1}")]
+    Include(std::path::PathBuf, Box<Error>),
+    #[error("{0} includes itself")]
+    IncludeCycle(std::path::PathBuf),
+    #[error("Failed to run {0}: {1}")]
+    Test(String, Box<Error>),
+}
@@ -1,288 +1,551 @@
 use clap::Parser;
-use serde::de::Error as SerdeError;
-use serde::Deserialize;
+use smokers::config::Override;
+use smokers::report::Reporter;
+use smokers::{
+    discover, events, init, manifest, progress, report, review, run_suites, runner, validate,
+    watch, ConfigFormat, Error, RunOptions, Suite,
+};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use thiserror::Error;
+use std::time::Duration;
 
-#[derive(Deserialize, Debug, Default)]
-#[serde(rename_all = "kebab-case")]
-/// The configuration structure used to define a test case.
-pub struct Configuration {
-    #[serde(deserialize_with = "deserialize_command")]
-    command: (String, Vec<String>),
-    stdout: Option<String>,
-    #[serde(default)]
-    exit_code: i32,
-}
-
-fn deserialize_command<'a, D: serde::Deserializer<'a>>(
-    d: D,
-) -> std::result::Result<(String, Vec<String>), D::Error> {
-    #[derive(Deserialize)]
-    #[serde(untagged)]
-    enum Command {
-        List(Vec<String>),
-        String(String),
-    }
+/// All selected tests passed.
+const EXIT_PASSED: i32 = 0;
+/// At least one test failed.
+const EXIT_FAILED: i32 = 1;
+/// The command line or a configuration file is invalid. Also used by clap
+/// for usage errors.
+const EXIT_CONFIG_ERROR: i32 = 2;
+/// smokers itself failed, e.g. to spawn a process or write the report.
+const EXIT_INTERNAL_ERROR: i32 = 3;
 
-    let l = Command::deserialize(d)?;
-    match l {
-        Command::List(mut ls) if !ls.is_empty() => Ok((ls.remove(0), ls)),
-        Command::String(s) if s.trim().contains(' ') => Err(D::Error::custom(
-            "Please define a list instead of a string.",
-        )),
-        Command::String(s) if !s.is_empty() => Ok((s, vec![])),
-        _ => Err(D::Error::custom("Command needs at least one element")),
-    }
+#[derive(Debug, Parser)]
+#[clap(
+    version,
+    author,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[clap(subcommand)]
+    command: Option<Command>,
+    /// The configuration files. Glob patterns are expanded and
+    /// directories are searched for `*.smoke.yaml`, `*.smoke.yml`,
+    /// `*.smoke.toml` and `*.smoke.json` files.
+    #[clap(required = true)]
+    files: Vec<String>,
+    /// The format of the configuration files, detected from their extension
+    /// by default.
+    #[clap(long, arg_enum)]
+    config_format: Option<ConfigFormat>,
+    /// Override a setting of all tests, like `exit-code=2` or
+    /// `env.RUST_LOG=debug`. The value is parsed as YAML. Can be given
+    /// multiple times.
+    #[clap(long, value_name = "KEY=VALUE", parse(try_from_str = smokers::config::parse_override))]
+    set: Vec<Override>,
+    /// The output format of the test results.
+    #[clap(long, arg_enum, default_value = "human")]
+    format: report::Format,
+    /// The number of tests to run in parallel, defaults to the number of CPUs.
+    #[clap(long, short)]
+    jobs: Option<usize>,
+    /// Only run tests with the given tag. Can be given multiple times.
+    #[clap(long, value_name = "TAG")]
+    tag: Vec<String>,
+    /// Skip tests with the given tag. Can be given multiple times.
+    #[clap(long, value_name = "TAG")]
+    skip_tag: Vec<String>,
+    /// Stop after the first failing test.
+    #[clap(long)]
+    fail_fast: bool,
+    /// When to use colors in the output.
+    #[clap(long, arg_enum, default_value = "auto")]
+    color: report::ColorChoice,
+    /// How often failing tests are retried, unless set in the test.
+    #[clap(long, default_value = "0")]
+    retries: u32,
+    /// Run each test this many times and report tests whose results are
+    /// inconsistent across the runs.
+    #[clap(long, value_name = "N", default_value = "1")]
+    repeat: u32,
+    /// Run the tests in a random order to uncover dependencies between
+    /// them. The seed is printed to reproduce the order.
+    #[clap(long, value_name = "SEED", min_values = 0, require_equals = true)]
+    shuffle: Option<Option<u64>>,
+    /// Ignore trailing newlines when comparing output, unless a test sets
+    /// `trim-trailing-newline`.
+    #[clap(long)]
+    normalize_newlines: bool,
+    /// Kill tests that run longer than this, unless they set `timeout`
+    /// themselves. Accepts durations like `500ms`, `30s` or `5m`.
+    #[clap(long, value_name = "DURATION", parse(try_from_str = smokers::config::parse_duration))]
+    timeout: Option<Duration>,
+    /// Show the output of tests on stderr while they run, each line
+    /// prefixed with the name of the test.
+    #[clap(long, visible_alias = "show-output")]
+    verbose: bool,
+    /// Only report failed tests and the summary.
+    #[clap(long, short, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Log what smokers itself does to stderr: `-v` for the progress of
+    /// each test, `-vv` for the processes it spawns and `-vvv` for
+    /// everything. Only warnings are logged by default.
+    #[clap(short = 'v', parse(from_occurrences))]
+    log_level: u8,
+    /// Emit an event for each step of the run as it happens. The events
+    /// replace the report on stdout unless `--events-file` is given.
+    #[clap(long, arg_enum, value_name = "FORMAT")]
+    events: Option<events::EventFormat>,
+    /// Write the events to this file instead of stdout.
+    #[clap(long, value_name = "PATH", requires = "events")]
+    events_file: Option<PathBuf>,
+    /// Show mismatching output with this command, like `delta` or `meld`.
+    /// It's run with the paths of files containing the expected and the
+    /// actual text appended.
+    #[clap(long, value_name = "COMMAND")]
+    diff_tool: Option<String>,
+    /// Replace the expected stdout and stderr of failed tests in their
+    /// configuration files with the actual output.
+    #[clap(long)]
+    update_expected: bool,
+    /// Write the output, configuration, result and sandbox contents of
+    /// each failed test to a subdirectory of this directory.
+    #[clap(long, value_name = "PATH")]
+    artifacts_dir: Option<PathBuf>,
+    /// Record the tests that pass in this directory and don't run them
+    /// again while their command, binary, environment, stdin and files stay
+    /// the same.
+    #[clap(long, value_name = "PATH")]
+    cache_dir: Option<PathBuf>,
+    /// Write a JSON manifest of the run to this file, listing every
+    /// discovered test and whether it was selected, skipped or executed.
+    #[clap(long, value_name = "PATH", conflicts_with_all = &["watch", "list", "dry-run"])]
+    manifest: Option<PathBuf>,
+    /// Don't remove the sandbox directories of failed tests.
+    #[clap(long)]
+    keep_sandbox: bool,
+    /// Keep running and re-run the affected tests whenever a configuration
+    /// file or one of its watched paths changes.
+    #[clap(long, conflicts_with_all = &["list", "dry-run"])]
+    watch: bool,
+    /// Print the names of the selected tests without running them.
+    #[clap(long, conflicts_with = "dry-run")]
+    list: bool,
+    /// Print the commands, working directories and environment variables of
+    /// the selected tests without running them.
+    #[clap(long)]
+    dry_run: bool,
 }
 
-#[derive(Debug, Error)]
-pub enum Error {
-    #[error("IO")]
-    IO(#[from] std::io::Error),
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Run the tests and decide for each mismatching expected stdout,
+    /// stderr or golden file whether to replace it with the actual output.
+    Review(ReviewArgs),
+    /// Check configuration files for errors without running their tests:
+    /// parse errors, missing includes and files, empty commands and
+    /// contradicting expectations.
+    Validate(ValidateArgs),
+    /// Write a starter configuration file demonstrating the main settings.
+    Init(InitArgs),
 }
 
-fn run(
-    config: &Configuration,
-    log_file: &mut impl std::io::Write,
-) -> std::result::Result<bool, Error> {
-    let executable = &config.command.0;
-    let process = std::process::Command::new(&executable)
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .args(&config.command.1)
-        .spawn()?;
+#[derive(Debug, clap::Args)]
+struct ReviewArgs {
+    /// The configuration files, as for running the tests.
+    #[clap(required = true)]
+    files: Vec<String>,
+    /// The format of the configuration files, detected from their extension
+    /// by default.
+    #[clap(long, arg_enum)]
+    config_format: Option<ConfigFormat>,
+    /// Only run tests with the given tag. Can be given multiple times.
+    #[clap(long, value_name = "TAG")]
+    tag: Vec<String>,
+    /// Skip tests with the given tag. Can be given multiple times.
+    #[clap(long, value_name = "TAG")]
+    skip_tag: Vec<String>,
+    /// Ignore trailing newlines when comparing output, unless a test sets
+    /// `trim-trailing-newline`.
+    #[clap(long)]
+    normalize_newlines: bool,
+    /// Kill tests that run longer than this, unless they set `timeout`
+    /// themselves.
+    #[clap(long, value_name = "DURATION", parse(try_from_str = smokers::config::parse_duration))]
+    timeout: Option<Duration>,
+    /// Log what smokers itself does to stderr, as for running the tests.
+    #[clap(short = 'v', parse(from_occurrences))]
+    log_level: u8,
+}
 
-    let output = process.wait_with_output()?;
-    let output_stdout = String::from_utf8_lossy(&output.stdout);
-    let output_status_code = output.status.code();
+#[derive(Debug, clap::Args)]
+struct ValidateArgs {
+    /// The configuration files, as for running the tests.
+    #[clap(required = true)]
+    files: Vec<String>,
+    /// The format of the configuration files, detected from their extension
+    /// by default.
+    #[clap(long, arg_enum)]
+    config_format: Option<ConfigFormat>,
+}
 
-    let exit_code_failed = match (output_status_code, config.exit_code) {
-        (Some(code), expected_exit_code) => code != expected_exit_code,
-        (None, _) => true, // killed by signal, currently handled as failure
-    };
+#[derive(Debug, clap::Args)]
+struct InitArgs {
+    /// The name of the test, the file is called NAME.smoke.yaml. Defaults
+    /// to the program of `--from-command`, or `example`.
+    name: Option<String>,
+    /// The command line the test runs, like "mytool --version".
+    #[clap(long, value_name = "COMMAND")]
+    from_command: Option<String>,
+    /// Overwrite the file if it exists.
+    #[clap(long)]
+    force: bool,
+}
 
-    let stdout_failed = match (&config.stdout, &output_stdout) {
-        (Some(expected_stdout), s) => s != expected_stdout,
-        (None, _) => false,
+/// Sends the diagnostics of smokers to stderr, keeping them apart from the
+/// test results on stdout. `level` counts the `-v` flags.
+fn init_logging(level: u8) {
+    let level = match level {
+        0 => tracing::Level::WARN,
+        1 => tracing::Level::INFO,
+        2 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
     };
-
-    if exit_code_failed {
-        match output_status_code {
-            None => writeln!(
-                log_file,
-                "The process died due to a signal. Expected it to exit with status code {}",
-                config.exit_code
-            )?,
-            Some(exit_code) => writeln!(
-                log_file,
-                "Unexpected exit code {}, expected {}",
-                exit_code, config.exit_code
-            )?,
-        }
-    }
-
-    if stdout_failed {
-        writeln!(log_file, "stdout:          {:?}", output_stdout)?;
-        if let Some(expected_stdout) = &config.stdout {
-            writeln!(log_file, "expected stdout: {:?}", expected_stdout)?;
-        } else {
-            writeln!(log_file, "expected no stdout.")?;
-        }
-    }
-
-    let failed = stdout_failed | exit_code_failed;
-    if failed {
-        writeln!(
-            log_file,
-            "stdout: {:?}",
-            String::from_utf8_lossy(&output.stdout)
-        )?;
-        writeln!(
-            log_file,
-            "stderr: {:?}",
-            String::from_utf8_lossy(&output.stderr)
-        )?;
-    }
-
-    Ok(!failed)
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(level)
+        .init();
 }
 
-#[derive(Debug, Parser)]
-#[clap(version, author, about)]
-pub struct Cli {
-    file: String,
+/// A seed for `--shuffle` that differs between runs.
+fn random_seed() -> u64 {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    now.as_nanos() as u64 ^ u64::from(std::process::id()).rotate_left(32)
 }
 
-fn main() {
-    let cli = Cli::parse();
-    let mut fh = std::fs::File::open(&cli.file).expect("Failed to open the configuration file");
-    let config = serde_yaml::from_reader(&mut fh).expect("Failed to parse configuration file");
-    match run(&config, &mut std::io::stdout()).unwrap() {
-        true => {
-            println!("No errors.");
-            exit(0)
-        }
-        false => {
-            println!("Errors.");
-            exit(1)
+/// Expands the command line arguments into a de-duplicated list of
+/// configuration files.
+fn expand_files(args: &[String]) -> Result<Vec<PathBuf>, Error> {
+    let mut files: Vec<PathBuf> = vec![];
+    for arg in args {
+        for file in discover::expand(arg)? {
+            if !files.contains(&file) {
+                files.push(file);
+            }
         }
     }
+    Ok(files)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Loads a configuration file, in the given format or the one matching its
+/// extension, with the `overrides` applied to its tests.
+fn load_suite(
+    file: &Path,
+    format: Option<ConfigFormat>,
+    overrides: &[Override],
+) -> Result<Suite, Error> {
+    let format = format.unwrap_or_else(|| ConfigFormat::from_path(file));
+    Suite::from_path_with_overrides(file, format, overrides)
+}
 
-    fn discard() -> impl std::io::Write {
-        pub struct Discard;
-        impl std::io::Write for Discard {
-            fn write(&mut self, d: &[u8]) -> std::io::Result<usize> {
-                Ok(d.len())
+/// Runs the tests, then waits for changes and re-runs the suites affected
+/// by them, forever.
+fn watch(
+    args: &[String],
+    format: Option<ConfigFormat>,
+    overrides: &[Override],
+    options: &RunOptions,
+    outputs: &[PathBuf],
+    reporter: &mut dyn Reporter,
+) -> ! {
+    let mut changed: Option<Vec<PathBuf>> = None;
+    loop {
+        let files = expand_files(args).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            vec![]
+        });
+        let mut suites = vec![];
+        for file in &files {
+            match load_suite(file, format, overrides) {
+                Ok(suite) => suites.push(suite),
+                Err(e) => eprintln!("Failed to load {}: {}", file.display(), e),
             }
+        }
+
+        let mut watched: Vec<PathBuf> = args.iter().map(PathBuf::from).collect();
+        watched.extend(files.iter().cloned());
+        watched.extend(suites.iter().flat_map(Suite::watched_paths));
 
-            fn flush(&mut self) -> std::io::Result<()> {
-                Ok(())
+        // Changes made while the tests run trigger the next run.
+        let snapshot = watch::Snapshot::new(watched.iter().map(PathBuf::as_path), outputs);
+        if let Some(changed) = &changed {
+            suites.retain(|suite| suite.is_affected_by(changed));
+        }
+        if !suites.is_empty() {
+            let mut summary = watch::SummaryReporter::new(reporter);
+            if let Err(e) = run_suites(&suites, options, &mut summary) {
+                eprintln!("{}", e);
             }
+            eprintln!(
+                "{} passed, {} failed. Watching for changes...",
+                summary.passed, summary.failed
+            );
         }
 
-        Discard {}
-    }
-
-    fn capture() -> std::io::Cursor<Vec<u8>> {
-        let x = std::io::Cursor::new(vec![]);
-        x
+        changed = Some(watch::wait_for_changes(
+            &watched,
+            outputs,
+            &snapshot,
+            Duration::from_millis(500),
+        ));
     }
+}
 
-    #[test]
-    fn test_parse_configuration() {
-        let config = r#"
-command:
-  - echo
-  - foo
+/// Loads the configuration files matching `args`, exiting if any of them
+/// is invalid.
+fn load_suites(args: &[String], format: Option<ConfigFormat>) -> Vec<Suite> {
+    let files = expand_files(args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(EXIT_CONFIG_ERROR)
+    });
+    files
+        .iter()
+        .map(|file| {
+            load_suite(file, format, &[]).unwrap_or_else(|e| {
+                eprintln!("Failed to load {}: {}", file.display(), e);
+                exit(EXIT_CONFIG_ERROR)
+            })
+        })
+        .collect()
+}
 
-exit-code: 0
-stdout: foo
-"#;
-        let config: Configuration = serde_yaml::from_str(config).unwrap();
-        assert_eq!(&config.command.0, "echo");
-        assert_eq!(&config.command.1, &["foo"]);
-        assert_eq!(config.stdout, Some("foo".to_string()));
-        assert_eq!(config.exit_code, 0);
+/// Runs the tests, then asks which of the mismatching expectations of the
+/// failed tests to replace with the actual output.
+fn review(args: ReviewArgs) -> ! {
+    init_logging(args.log_level);
+    let suites = load_suites(&args.files, args.config_format);
+    let options = RunOptions {
+        jobs: std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get),
+        tags: args.tag,
+        skip_tags: args.skip_tag,
+        normalize_newlines: args.normalize_newlines,
+        timeout: args.timeout,
+        ..RunOptions::default()
+    };
+    let color = report::ColorChoice::Auto.enabled();
+    let mut reporter = report::reporter(report::Format::Human, std::io::stdout(), color, true);
+    let mut reporter = report::CollectingReporter::new(reporter.as_mut());
+    let passed = run_suites(&suites, &options, &mut reporter).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(EXIT_INTERNAL_ERROR)
+    });
+    let changes = review::changes(&suites, &reporter.reports);
+    if changes.is_empty() {
+        exit(if passed { EXIT_PASSED } else { EXIT_FAILED })
     }
+    let mut out = std::io::stdout().lock();
+    let summary =
+        review::review(&changes, &mut std::io::stdin().lock(), &mut out).unwrap_or_else(|e| {
+            eprintln!("Failed to review the changes: {}", e);
+            exit(EXIT_INTERNAL_ERROR)
+        });
+    println!(
+        "{} accepted, {} rejected, {} skipped",
+        summary.accepted, summary.rejected, summary.skipped
+    );
+    exit(if passed { EXIT_PASSED } else { EXIT_FAILED })
+}
 
-    #[test]
-    fn test_parse_configuration_command_single_string() {
-        let input = "command: foo bar baz";
-        let result: Result<Configuration, _> = serde_yaml::from_str(input);
-        assert!(result.is_err());
-        if let Err(e) = result {
-            assert!(e
-                .to_string()
-                .starts_with("Please define a list instead of a string"));
+/// Loads the configuration files and reports their problems, exiting with
+/// [`EXIT_CONFIG_ERROR`] if there are any.
+fn validate(args: ValidateArgs) -> ! {
+    let files = expand_files(&args.files).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(EXIT_CONFIG_ERROR)
+    });
+    let mut problems = 0;
+    for file in &files {
+        match load_suite(file, args.config_format, &[]) {
+            Ok(suite) => {
+                for problem in validate::problems(&suite) {
+                    println!("{}", problem);
+                    problems += 1;
+                }
+            }
+            Err(e) => {
+                println!("{}: {}", file.display(), e);
+                problems += 1;
+            }
         }
     }
-
-    #[test]
-    fn test_parse_configuration_command_empty_string() {
-        let input = r#"command: """#;
-        let result: Result<Configuration, _> = serde_yaml::from_str(input);
-        assert!(result.is_err());
-        if let Err(e) = result {
-            assert_eq!(
-                e.to_string(),
-                "Command needs at least one element at line 1 column 8"
-            );
-        }
+    if problems > 0 {
+        println!("Problems found: {}", problems);
+        exit(EXIT_CONFIG_ERROR)
     }
+    println!("No problems found in {} configuration file(s)", files.len());
+    exit(EXIT_PASSED)
+}
 
-    #[test]
-    fn test_run_hello_world() {
-        let config = Configuration {
-            command: (
-                "sh".to_string(),
-                vec!["-c".to_string(), "exit 1".to_string()],
-            ),
-            exit_code: 1,
-            ..Configuration::default()
-        };
-
-        let result = run(&config, &mut discard()).unwrap();
-        assert_eq!(result, true);
+/// Writes a starter configuration file to the current directory.
+fn init(args: InitArgs) -> ! {
+    let program = args.from_command.as_deref().and_then(|command| {
+        let program = Path::new(command.split_whitespace().next()?).file_stem()?;
+        Some(program.to_string_lossy().into_owned())
+    });
+    let name = args
+        .name
+        .or(program)
+        .unwrap_or_else(|| "example".to_string());
+    let path = PathBuf::from(format!("{}.smoke.yaml", name));
+    let config = init::starter_config(&name, args.from_command.as_deref());
+    let mut file = std::fs::OpenOptions::new();
+    if args.force {
+        file.create(true).truncate(true);
+    } else {
+        file.create_new(true);
     }
-
-    #[test]
-    fn test_run_exit1() {
-        let config = Configuration {
-            command: (
-                "sh".to_string(),
-                vec!["-c".to_string(), "exit 1".to_string()],
-            ),
-            exit_code: 1,
-            ..Configuration::default()
-        };
-        let result = run(&config, &mut discard()).unwrap();
-        assert_eq!(result, true);
+    let written = file
+        .write(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(config.as_bytes()));
+    if let Err(e) = written {
+        eprintln!("Failed to write {}: {}", path.display(), e);
+        exit(EXIT_CONFIG_ERROR)
     }
+    println!("Wrote {}", path.display());
+    exit(EXIT_PASSED)
+}
 
-    #[test]
-    fn test_run_unexpected_exit1() {
-        let config = Configuration {
-            command: (
-                "sh".to_string(),
-                vec!["-c".to_string(), "exit 1".to_string()],
-            ),
-            exit_code: 0,
-            ..Configuration::default()
-        };
-        let result = run(&config, &mut discard()).unwrap();
-        assert_eq!(result, false);
+fn main() {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Review(args)) => review(args),
+        Some(Command::Validate(args)) => validate(args),
+        Some(Command::Init(args)) => init(args),
+        None => {}
     }
-
-    #[test]
-    fn test_run_spits_out_stdout_on_exit_mismatch() {
-        let config = Configuration {
-            command: (
-                "sh".to_string(),
-                vec!["-c".to_string(), "echo foo bar baz".to_string()],
-            ),
-            exit_code: 1,
-            ..Configuration::default()
-        };
-
-        let mut capture = capture();
-        let result = run(&config, &mut capture).unwrap();
-        assert_eq!(result, false);
-        let o = capture.into_inner();
-        let output = String::from_utf8_lossy(&o);
-        assert!(
-            output.contains(r#"stdout: "foo bar baz\n""#),
-            "output: {:?}",
-            output
+    init_logging(cli.log_level);
+    let files = expand_files(&cli.files).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(EXIT_CONFIG_ERROR)
+    });
+    let options = RunOptions {
+        jobs: cli.jobs.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        }),
+        tags: cli.tag,
+        skip_tags: cli.skip_tag,
+        fail_fast: cli.fail_fast,
+        retries: cli.retries,
+        normalize_newlines: cli.normalize_newlines,
+        timeout: cli.timeout,
+        keep_sandbox: cli.keep_sandbox,
+        verbose: cli.verbose,
+        repeat: cli.repeat,
+        shuffle: cli.shuffle.map(|seed| {
+            let seed = seed.unwrap_or_else(random_seed);
+            eprintln!("Shuffling the tests with --shuffle={}", seed);
+            seed
+        }),
+        diff_tool: cli.diff_tool,
+        artifacts_dir: cli.artifacts_dir,
+        update_expected: cli.update_expected,
+        cache_dir: cli.cache_dir,
+    };
+    let mut reporter = report::reporter(
+        cli.format,
+        std::io::stdout(),
+        cli.color.enabled(),
+        cli.quiet,
+    );
+    let mut events;
+    let reporter: &mut dyn Reporter = match (cli.events, &cli.events_file) {
+        (None, _) => reporter.as_mut(),
+        (Some(events::EventFormat::Jsonl), None) => {
+            let out: Box<dyn Write> = Box::new(std::io::stdout());
+            events = events::EventReporter::new(None, out);
+            &mut events
+        }
+        (Some(events::EventFormat::Jsonl), Some(path)) => {
+            let file = std::fs::File::create(path).unwrap_or_else(|e| {
+                eprintln!("Failed to create {}: {}", path.display(), e);
+                exit(EXIT_INTERNAL_ERROR)
+            });
+            events = events::EventReporter::new(Some(reporter.as_mut()), Box::new(file));
+            &mut events
+        }
+    };
+    // The progress line would be interleaved with the output of the tests.
+    let mut progress;
+    let show_progress =
+        std::io::stderr().is_terminal() && !cli.verbose && !cli.quiet && cli.log_level == 0;
+    let reporter: &mut dyn Reporter = if show_progress {
+        progress = progress::ProgressReporter::new(reporter, std::io::stderr());
+        &mut progress
+    } else {
+        reporter
+    };
+    if cli.watch {
+        // The files smokers writes would trigger the next run otherwise.
+        let outputs: Vec<PathBuf> = [&options.cache_dir, &options.artifacts_dir, &cli.events_file]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        watch(
+            &cli.files,
+            cli.config_format,
+            &cli.set,
+            &options,
+            &outputs,
+            reporter,
         );
     }
-
-    #[test]
-    fn test_run_spits_out_stderr_on_exit_mismatch() {
-        let config = Configuration {
-            command: (
-                "sh".to_string(),
-                vec!["-c".to_string(), "echo foo bar baz >&2".to_string()],
-            ),
-            exit_code: 1,
-            ..Configuration::default()
+    let suites: Vec<Suite> = files
+        .iter()
+        .map(|file| {
+            load_suite(file, cli.config_format, &cli.set).unwrap_or_else(|e| {
+                eprintln!("Failed to load {}: {}", file.display(), e);
+                exit(EXIT_CONFIG_ERROR)
+            })
+        })
+        .collect();
+    if cli.list || cli.dry_run {
+        let mut out = std::io::stdout().lock();
+        let result = if cli.list {
+            runner::list_tests(&suites, &options, &mut out)
+        } else {
+            runner::dry_run(&suites, &options, &mut out)
         };
-
-        let mut capture = capture();
-        let result = run(&config, &mut capture).unwrap();
-        assert_eq!(result, false);
-        let o = capture.into_inner();
-        let output = String::from_utf8_lossy(&o);
-        assert!(
-            output.contains(r#"stderr: "foo bar baz\n""#),
-            "output: {:?}",
-            output
-        );
+        if let Err(e) = result {
+            eprintln!("Failed to write to stdout: {}", e);
+            exit(EXIT_INTERNAL_ERROR)
+        }
+        exit(EXIT_PASSED)
+    }
+    // The results are kept for the manifest.
+    let mut reporter = report::CollectingReporter::new(reporter);
+    let result = run_suites(&suites, &options, &mut reporter);
+    if let Some(path) = &cli.manifest {
+        let manifest = manifest::Manifest::new(&suites, &options, &reporter.reports);
+        let written = serde_json::to_vec_pretty(&manifest)
+            .map_err(std::io::Error::from)
+            .and_then(|json| std::fs::write(path, json));
+        if let Err(e) = written {
+            eprintln!("Failed to write the manifest {}: {}", path.display(), e);
+            exit(EXIT_INTERNAL_ERROR)
+        }
+    }
+    match result {
+        Ok(true) => exit(EXIT_PASSED),
+        Ok(false) => exit(EXIT_FAILED),
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(EXIT_INTERNAL_ERROR)
+        }
     }
 }
@@ -0,0 +1,185 @@
+//! The manifest of a run, listing every discovered test and what happened
+//! to it, so that a CI job can tell whether the filters dropped tests.
+
+use crate::config::Suite;
+use crate::report::TestResult;
+use crate::runner::RunOptions;
+use serde::Serialize;
+use std::path::PathBuf;
+
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    /// The number of test cases in all configuration files.
+    pub discovered: usize,
+    /// The number of test cases selected by the tag filters.
+    pub selected: usize,
+    /// The number of test cases that were run.
+    pub executed: usize,
+    pub cached: usize,
+    pub skipped: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub files: Vec<ManifestFile>,
+}
+
+/// A configuration file and its test cases.
+#[derive(Debug, Serialize)]
+pub struct ManifestFile {
+    pub path: Option<PathBuf>,
+    pub tests: Vec<ManifestTest>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestTest {
+    pub name: Option<String>,
+    /// Whether the tag filters select the test. Tests that aren't selected
+    /// still run as prerequisites of selected tests.
+    pub selected: bool,
+    pub status: Status,
+    /// Why the test wasn't selected, was skipped or failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    Passed,
+    /// Not run as it passed before with the same inputs.
+    CachedPass,
+    Failed,
+    Skipped,
+    /// Excluded by the tag filters.
+    NotSelected,
+    /// Selected, but not run because the run was aborted.
+    NotRun,
+}
+
+impl Manifest {
+    /// Describes the run of `suites` with `options` that produced
+    /// `reports`.
+    pub fn new(suites: &[Suite], options: &RunOptions, reports: &[TestResult]) -> Self {
+        let files: Vec<ManifestFile> = suites
+            .iter()
+            .map(|suite| ManifestFile {
+                path: suite.path.clone(),
+                tests: (0..suite.tests.len())
+                    .map(|index| describe(suite, index, options, reports))
+                    .collect(),
+            })
+            .collect();
+        let tests = || files.iter().flat_map(|file| &file.tests);
+        let count = |status| tests().filter(|test| test.status == status).count();
+        Self {
+            discovered: tests().count(),
+            selected: tests().filter(|test| test.selected).count(),
+            executed: count(Status::Passed) + count(Status::Failed),
+            cached: count(Status::CachedPass),
+            skipped: count(Status::Skipped),
+            passed: count(Status::Passed),
+            failed: count(Status::Failed),
+            files,
+        }
+    }
+}
+
+/// Describes the test at `index` of `suite` from its report among
+/// `reports`.
+fn describe(
+    suite: &Suite,
+    index: usize,
+    options: &RunOptions,
+    reports: &[TestResult],
+) -> ManifestTest {
+    let name = suite.test_name(index);
+    let exclusion_reason = options.exclusion_reason(&suite.tests[index]);
+    let report = reports
+        .iter()
+        .find(|report| report.file == suite.path && report.name == name);
+    let (status, reason) = match report {
+        Some(report) if report.skipped.is_some() => (Status::Skipped, report.skipped.clone()),
+        Some(report) if report.cached && report.passed => (Status::CachedPass, None),
+        Some(report) if report.passed => (Status::Passed, None),
+        Some(report) => (Status::Failed, report.failures.first().cloned()),
+        None if exclusion_reason.is_some() => (Status::NotSelected, exclusion_reason.clone()),
+        None => (Status::NotRun, Some("the run was aborted".to_string())),
+    };
+    ManifestTest {
+        name,
+        selected: exclusion_reason.is_none(),
+        status,
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::{CollectingReporter, HumanReporter};
+    use crate::run_suites;
+
+    #[test]
+    fn test_manifest() {
+        let input = r#"
+tests:
+  passing:
+    command: "true"
+    tags: [fast]
+  failing:
+    command: "false"
+    tags: [fast]
+  skipped:
+    command: "true"
+    skip: broken
+    tags: [fast]
+  slow:
+    command: "true"
+    tags: [slow]
+"#;
+        let suites = [Suite::from_reader(input.as_bytes()).unwrap()];
+        let options = RunOptions {
+            tags: vec!["fast".to_string()],
+            ..RunOptions::default()
+        };
+        let mut reporter = HumanReporter::new(std::io::sink());
+        let mut reporter = CollectingReporter::new(&mut reporter);
+        run_suites(&suites, &options, &mut reporter).unwrap();
+        let manifest = Manifest::new(&suites, &options, &reporter.reports);
+        assert_eq!(
+            serde_json::to_value(&manifest).unwrap(),
+            serde_json::json!({
+                "discovered": 4,
+                "selected": 3,
+                "executed": 2,
+                "cached": 0,
+                "skipped": 1,
+                "passed": 1,
+                "failed": 1,
+                "files": [{
+                    "path": null,
+                    "tests": [
+                        {"name": "passing", "selected": true, "status": "passed"},
+                        {
+                            "name": "failing",
+                            "selected": true,
+                            "status": "failed",
+                            "reason": "Unexpected exit code 1, expected 0",
+                        },
+                        {
+                            "name": "skipped",
+                            "selected": true,
+                            "status": "skipped",
+                            "reason": "broken",
+                        },
+                        {
+                            "name": "slow",
+                            "selected": false,
+                            "status": "not-selected",
+                            "reason": "not tagged fast",
+                        },
+                    ],
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! Comparison of output with expected text containing placeholders.
+//!
+//! The placeholders `{{uuid}}`, `{{timestamp}}`, `{{number}}`, `{{path}}`
+//! and `{{any}}` match values of the corresponding kind. Numbers that may
+//! vary within a tolerance are written like `{2.5~0.1}`, matching any
+//! number within 0.1 of 2.5, or `{2.5~5%}` for a relative tolerance. With a
+//! [`Tolerance`] configured, every number in the expected text is matched
+//! within it. A backslash in front of a placeholder, like `\{{any}}` or
+//! `\{2.5~0.1}`, makes it match itself without the backslash.
+
+use regex::Regex;
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+/// How far numbers in the output may deviate from the expected ones. A
+/// number matches if it is within either of the tolerances.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(from = "ToleranceValue")]
+pub struct Tolerance {
+    pub absolute: f64,
+    /// The tolerance as a fraction of the expected value.
+    pub relative: f64,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ToleranceValue {
+    Absolute(f64),
+    Both {
+        #[serde(default)]
+        absolute: f64,
+        #[serde(default)]
+        relative: f64,
+    },
+}
+
+impl From<ToleranceValue> for Tolerance {
+    fn from(value: ToleranceValue) -> Self {
+        match value {
+            ToleranceValue::Absolute(absolute) => Self {
+                absolute,
+                relative: 0.0,
+            },
+            ToleranceValue::Both { absolute, relative } => Self { absolute, relative },
+        }
+    }
+}
+
+impl Tolerance {
+    fn allows(&self, expected: f64, actual: f64) -> bool {
+        let difference = (expected - actual).abs();
+        difference <= self.absolute || difference <= self.relative * expected.abs()
+    }
+}
+
+impl std::fmt::Display for Tolerance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.absolute, self.relative) {
+            (absolute, 0.0) => write!(f, "{}", absolute),
+            (0.0, relative) => write!(f, "{}%", relative * 100.0),
+            (absolute, relative) => write!(f, "{} or {}%", absolute, relative * 100.0),
+        }
+    }
+}
+
+/// The outcome of comparing output with expected text.
+#[derive(Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    /// The text around the numbers differs.
+    Different,
+    /// A number is outside of its tolerance.
+    OutOfTolerance {
+        expected: f64,
+        actual: String,
+        tolerance: Tolerance,
+    },
+}
+
+const NUMBER: &str = r"[-+]?(?:\d+\.?\d*|\.\d+)(?:[eE][-+]?\d+)?";
+
+/// The placeholders and the regular expressions of the values they match.
+const PLACEHOLDERS: &[(&str, &str)] = &[
+    (
+        "uuid",
+        r"[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}",
+    ),
+    (
+        "timestamp",
+        r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(?:[.,]\d+)?(?:Z|[+-]\d{2}:?\d{2})?",
+    ),
+    ("number", NUMBER),
+    ("path", r"\S+"),
+    ("any", r"[^\n]*"),
+];
+
+/// Compares `actual` with `expected`, matching placeholders and, with a
+/// `tolerance`, all numbers of `expected` numerically.
+pub fn compare(expected: &str, actual: &str, tolerance: Option<Tolerance>) -> Comparison {
+    // Turn the expected text into a regex matching the placeholders and
+    // capturing the numbers to check against their tolerance.
+    static TOKENS: OnceLock<Regex> = OnceLock::new();
+    let tokens = TOKENS.get_or_init(|| {
+        let names: Vec<&str> = PLACEHOLDERS.iter().map(|(name, _)| *name).collect();
+        Regex::new(&format!(
+            r"\\(\{{\{{(?:{0})\}}\}}|\{{{1}~{1}%?\}})|\{{\{{({0})\}}\}}|\{{({1})~({1})(%?)\}}|{1}",
+            names.join("|"),
+            NUMBER
+        ))
+        .unwrap()
+    });
+    let mut pattern = String::from("(?s)^");
+    // Whether `expected` can be compared as it is.
+    let mut literal = true;
+    let mut numbers = vec![];
+    let mut end = 0;
+    for captures in tokens.captures_iter(expected) {
+        let found = captures.get(0).unwrap();
+        if let Some(escaped) = captures.get(1) {
+            pattern.push_str(&regex::escape(&expected[end..found.start()]));
+            pattern.push_str(&regex::escape(escaped.as_str()));
+            end = found.end();
+            literal = false;
+            continue;
+        }
+        if let Some(name) = captures.get(2) {
+            let (_, regex) = PLACEHOLDERS
+                .iter()
+                .find(|(placeholder, _)| *placeholder == name.as_str())
+                .unwrap();
+            pattern.push_str(&regex::escape(&expected[end..found.start()]));
+            pattern.push_str(&format!("(?:{})", regex));
+            end = found.end();
+            literal = false;
+            continue;
+        }
+        let number = match (captures.get(3), tolerance) {
+            (Some(value), _) => {
+                let amount: f64 = captures[4].parse().unwrap();
+                let tolerance = match &captures[5] {
+                    "%" => Tolerance {
+                        absolute: 0.0,
+                        relative: amount / 100.0,
+                    },
+                    _ => Tolerance {
+                        absolute: amount,
+                        relative: 0.0,
+                    },
+                };
+                (value.as_str().parse().unwrap(), tolerance)
+            }
+            (None, Some(tolerance)) => (found.as_str().parse().unwrap(), tolerance),
+            (None, None) => continue,
+        };
+        numbers.push(number);
+        pattern.push_str(&regex::escape(&expected[end..found.start()]));
+        pattern.push_str(&format!("({})", NUMBER));
+        end = found.end();
+    }
+    if numbers.is_empty() && literal {
+        return if expected == actual {
+            Comparison::Equal
+        } else {
+            Comparison::Different
+        };
+    }
+    pattern.push_str(&regex::escape(&expected[end..]));
+    pattern.push('$');
+
+    let captures = match Regex::new(&pattern).unwrap().captures(actual) {
+        Some(captures) => captures,
+        None => return Comparison::Different,
+    };
+    for (index, (expected, tolerance)) in numbers.into_iter().enumerate() {
+        let actual = &captures[index + 1];
+        if !actual
+            .parse()
+            .is_ok_and(|value| tolerance.allows(expected, value))
+        {
+            return Comparison::OutOfTolerance {
+                expected,
+                actual: actual.to_string(),
+                tolerance,
+            };
+        }
+    }
+    Comparison::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_placeholders() {
+        let compare = |expected, actual| compare(expected, actual, None);
+        assert_eq!(compare("took 2s\n", "took 2s\n"), Comparison::Equal);
+        assert_eq!(compare("took {2.5~0.1}s", "took 2.43s"), Comparison::Equal);
+        assert_eq!(compare("took {100~5%}ms", "took 104ms"), Comparison::Equal);
+        assert_eq!(
+            compare("took {2.5~0.1}s", "took 2.5 s"),
+            Comparison::Different
+        );
+        assert_eq!(compare("{1~0}, 2", "1, 3"), Comparison::Different);
+        assert_eq!(compare(r"\{2.5~0.1}s", "{2.5~0.1}s"), Comparison::Equal);
+        assert_eq!(compare(r"\{2.5~0.1}s", "2.5s"), Comparison::Different);
+        assert_eq!(
+            compare("took {2.5~0.1}s", "took 2.7s"),
+            Comparison::OutOfTolerance {
+                expected: 2.5,
+                actual: "2.7".to_string(),
+                tolerance: Tolerance {
+                    absolute: 0.1,
+                    relative: 0.0
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_compare_named_placeholders() {
+        let compare = |expected, actual| compare(expected, actual, None);
+        assert_eq!(
+            compare(
+                "id {{uuid}} at {{timestamp}}\n",
+                "id 123e4567-e89b-12d3-a456-426614174000 at 2024-01-02T03:04:05.678Z\n"
+            ),
+            Comparison::Equal
+        );
+        assert_eq!(
+            compare(
+                "{{path}}: {{number}} files, {{any}}!",
+                "/tmp/x y: -1.5 files, ok?!"
+            ),
+            Comparison::Different
+        );
+        assert_eq!(
+            compare(
+                "{{path}}: {{number}} files, {{any}}!",
+                "/tmp/x: -1.5 files, ok?!"
+            ),
+            Comparison::Equal
+        );
+        assert_eq!(compare("{{any}}\n", "a\nb\n"), Comparison::Different);
+        assert_eq!(compare("{{uuid}}", "not-a-uuid"), Comparison::Different);
+        assert_eq!(compare("{{unknown}}", "{{unknown}}"), Comparison::Equal);
+        assert_eq!(compare(r"a \{{any}}", "a {{any}}"), Comparison::Equal);
+        assert_eq!(compare(r"a \{{any}}", "a b"), Comparison::Different);
+        assert_eq!(
+            compare(r"\{{path}}: {{path}}", "{{path}}: /tmp"),
+            Comparison::Equal
+        );
+    }
+
+    #[test]
+    fn test_compare_tolerance() {
+        let tolerance = Tolerance {
+            absolute: 0.01,
+            relative: 0.1,
+        };
+        let compare = |expected, actual| compare(expected, actual, Some(tolerance));
+        assert_eq!(compare("x=0.001 y=100", "x=0.009 y=109"), Comparison::Equal);
+        assert_eq!(compare("pi is {3.14~0}", "pi is 3.14"), Comparison::Equal);
+        assert_eq!(compare(r"\{1~2} 1", "{1~2} 1.05"), Comparison::Equal);
+        assert_eq!(compare("x=1 y=200\n", "x=1.04 y=201\n"), Comparison::Equal);
+        assert!(matches!(
+            compare("x=0.001 y=100", "x=0.001 y=111"),
+            Comparison::OutOfTolerance { .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_tolerance() {
+        let parse = |s| serde_yaml::from_str::<Tolerance>(s).unwrap();
+        assert_eq!(
+            parse("0.5"),
+            Tolerance {
+                absolute: 0.5,
+                relative: 0.0
+            }
+        );
+        assert_eq!(
+            parse("{relative: 0.1}"),
+            Tolerance {
+                absolute: 0.0,
+                relative: 0.1
+            }
+        );
+    }
+}
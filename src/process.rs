@@ -0,0 +1,916 @@
+//! Running test processes and capturing their output.
+
+use crate::config::{IoPriority, Isolation, Limits};
+use crate::report::ResourceUsage;
+use crate::signal::Signal;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often a process with a timeout is checked for having exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long to wait for the remaining output of a killed process. The
+/// output may still be held open by processes it started.
+pub const KILL_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// How long a process may take to exit after receiving its timeout signal
+/// before it is killed.
+pub const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// What a process reads from its stdin.
+#[derive(Debug, Clone, Copy)]
+pub enum Input<'a> {
+    Bytes(&'a [u8]),
+    /// A file that is streamed into the process.
+    File(&'a Path),
+}
+
+impl Input<'_> {
+    /// Reads the whole input into memory.
+    pub fn read(&self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Input::Bytes(bytes) => Ok(bytes.to_vec()),
+            Input::File(path) => std::fs::read(path),
+        }
+    }
+}
+
+/// How a process is run and its output captured.
+#[derive(Debug, Default)]
+pub struct Options<'a> {
+    /// What the process reads from its stdin.
+    pub input: Option<Input<'a>>,
+    /// Kill the process once this has elapsed.
+    pub timeout: Option<Duration>,
+    /// The signal sent to the process on timeout instead of killing it
+    /// right away.
+    pub timeout_signal: Option<Signal>,
+    /// Discard output beyond this many bytes per stream.
+    pub max_output: Option<u64>,
+    /// Copy the output to stderr as it arrives, each line prefixed with
+    /// this.
+    pub echo: Option<&'a str>,
+    /// Capture stdout and stderr as a single stream, reported as stdout.
+    pub merge_output: bool,
+}
+
+/// The outcome of running a process.
+pub struct Finished {
+    pub output: Output,
+    /// Whether the process was killed because it exceeded its timeout.
+    pub timed_out: bool,
+    /// Whether stdout was cut off at the output limit.
+    pub stdout_truncated: bool,
+    /// Whether stderr was cut off at the output limit.
+    pub stderr_truncated: bool,
+    /// The resources used by the process, where they can be measured.
+    pub usage: Option<ResourceUsage>,
+}
+
+/// Runs `process` with piped stdio as configured in `options`.
+pub fn run_piped(mut process: Command, options: &Options) -> std::io::Result<Finished> {
+    tracing::debug!(command = ?process, "spawning");
+    let stdin = match options.input {
+        Some(Input::File(path)) => Stdio::from(std::fs::File::open(path)?),
+        _ => Stdio::piped(),
+    };
+    process.stdin(stdin);
+    let (mut child, stdout, stderr) = if options.merge_output {
+        let (reader, writer) = pipe()?;
+        process
+            .stdout(Stdio::from(writer.try_clone()?))
+            .stderr(Stdio::from(writer));
+        let child = spawn(&mut process)?;
+        // The output only ends once no process holds the pipe open anymore,
+        // so our copies have to be closed.
+        drop(process);
+        (child, Capture::start(reader, options), None)
+    } else {
+        let mut child = spawn(process.stdout(Stdio::piped()).stderr(Stdio::piped()))?;
+        let stdout = Capture::start(child.stdout.take().expect("stdout is piped"), options);
+        let stderr = Capture::start(child.stderr.take().expect("stderr is piped"), options);
+        (child, stdout, Some(stderr))
+    };
+
+    // Feed stdin from a separate thread so a child that produces lots of
+    // output before reading its input can't deadlock us.
+    let stdin_writer = match (child.stdin.take(), options.input) {
+        (Some(mut stdin), Some(Input::Bytes(input))) => {
+            let input = input.to_vec();
+            Some(std::thread::spawn(move || match stdin.write_all(&input) {
+                Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+                r => r,
+            }))
+        }
+        _ => None,
+    };
+
+    tracing::debug!(pid = child.id(), "spawned");
+    let (status, timed_out, usage) = wait(&mut child, options, false)?;
+    if !timed_out {
+        if let Some(writer) = stdin_writer {
+            writer.join().expect("stdin writer thread panicked")?;
+        }
+    }
+    let grace = timed_out.then_some(KILL_GRACE_PERIOD);
+    let (stdout, stdout_truncated) = stdout.finish(grace);
+    let (stderr, stderr_truncated) = stderr.map_or((vec![], false), |stderr| stderr.finish(grace));
+    Ok(Finished {
+        output: Output {
+            status,
+            stdout,
+            stderr,
+        },
+        timed_out,
+        stdout_truncated,
+        stderr_truncated,
+        usage,
+    })
+}
+
+/// Held for writing while file descriptors are created that only become
+/// close-on-exec afterwards, and for reading while spawning processes, so
+/// that no process inherits them in between.
+#[cfg(all(unix, not(target_os = "linux")))]
+static SPAWN_LOCK: std::sync::RwLock<()> = std::sync::RwLock::new(());
+
+/// Spawns `process`, waiting for file descriptors that are being created
+/// by other threads to become close-on-exec first.
+pub(crate) fn spawn(process: &mut Command) -> std::io::Result<Child> {
+    #[cfg(all(unix, not(target_os = "linux")))]
+    let _lock = SPAWN_LOCK
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    process.spawn()
+}
+
+/// Creates a pipe, returning its reading and writing end.
+#[cfg(unix)]
+fn pipe() -> std::io::Result<(std::fs::File, std::fs::File)> {
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+    // SAFETY: the pointer is valid for the duration of the call and the
+    // returned file descriptors are owned by the files.
+    unsafe {
+        #[cfg(target_os = "linux")]
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _lock = SPAWN_LOCK
+                .write()
+                .unwrap_or_else(std::sync::PoisonError::into_inner);
+            if libc::pipe(fds.as_mut_ptr()) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            for fd in fds {
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+        }
+        Ok((
+            std::fs::File::from_raw_fd(fds[0]),
+            std::fs::File::from_raw_fd(fds[1]),
+        ))
+    }
+}
+
+#[cfg(not(unix))]
+fn pipe() -> std::io::Result<(std::fs::File, std::fs::File)> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "merge-output is only supported on unix",
+    ))
+}
+
+/// Waits for `child` to exit, stopping it once the timeout of `options` has
+/// elapsed. The process is sent the timeout signal first, if any, and killed
+/// if it didn't exit within [`TERMINATE_GRACE_PERIOD`]. With `group` the
+/// whole process group led by the child is signalled. Returns the exit
+/// status, whether the timeout elapsed and the resources the process used.
+pub fn wait(
+    child: &mut Child,
+    options: &Options,
+    group: bool,
+) -> std::io::Result<(ExitStatus, bool, Option<ResourceUsage>)> {
+    let Some(timeout) = options.timeout else {
+        let (status, usage) = reap(child)?;
+        tracing::debug!(pid = child.id(), %status, ?usage, "exited");
+        return Ok((status, false, usage));
+    };
+    let deadline = Instant::now() + timeout;
+    if let Some((status, usage)) = poll_until(deadline, || try_reap(child))? {
+        tracing::debug!(pid = child.id(), %status, ?usage, "exited");
+        return Ok((status, false, usage));
+    }
+    tracing::info!(pid = child.id(), ?timeout, "timed out");
+    #[cfg(unix)]
+    if let Some(signal) = options.timeout_signal.filter(|s| s.0 != libc::SIGKILL) {
+        tracing::debug!(pid = child.id(), %signal, "sending the timeout signal");
+        send_signal(child, group, signal)?;
+        let deadline = Instant::now() + TERMINATE_GRACE_PERIOD;
+        if let Some((status, usage)) = poll_until(deadline, || try_reap(child))? {
+            return Ok((status, true, usage));
+        }
+    }
+    tracing::debug!(pid = child.id(), group, "killing");
+    kill(child, group)?;
+    let (status, usage) = reap(child)?;
+    Ok((status, true, usage))
+}
+
+/// Waits for `child` to exit and collects the resources it used. Unlike
+/// [`Child::wait`] this leaves `child` unaware that it exited.
+#[cfg(unix)]
+fn reap(child: &mut Child) -> std::io::Result<(ExitStatus, Option<ResourceUsage>)> {
+    wait4(child, 0).map(|exited| exited.expect("wait4 blocks without WNOHANG"))
+}
+
+#[cfg(not(unix))]
+fn reap(child: &mut Child) -> std::io::Result<(ExitStatus, Option<ResourceUsage>)> {
+    Ok((child.wait()?, None))
+}
+
+/// Collects the exit status and the resources used by `child` if it
+/// exited.
+#[cfg(unix)]
+fn try_reap(child: &mut Child) -> std::io::Result<Option<(ExitStatus, Option<ResourceUsage>)>> {
+    wait4(child, libc::WNOHANG)
+}
+
+#[cfg(not(unix))]
+fn try_reap(child: &mut Child) -> std::io::Result<Option<(ExitStatus, Option<ResourceUsage>)>> {
+    Ok(child.try_wait()?.map(|status| (status, None)))
+}
+
+#[cfg(unix)]
+fn wait4(
+    child: &Child,
+    flags: libc::c_int,
+) -> std::io::Result<Option<(ExitStatus, Option<ResourceUsage>)>> {
+    use std::os::unix::process::ExitStatusExt;
+
+    let mut status = 0;
+    // SAFETY: rusage is plain data, for which all zeroes are valid.
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: the pointers are valid for the duration of the call.
+        let pid = unsafe { libc::wait4(child.id() as i32, &mut status, flags, &mut rusage) };
+        match pid {
+            0 => return Ok(None),
+            -1 => {
+                let error = std::io::Error::last_os_error();
+                if error.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+            _ => break,
+        }
+    }
+    let time = |t: libc::timeval| {
+        Duration::from_secs(t.tv_sec as u64) + Duration::from_micros(t.tv_usec as u64)
+    };
+    // Linux reports the maximum resident set size in kilobytes, macOS in
+    // bytes.
+    let unit = if cfg!(target_os = "macos") { 1 } else { 1024 };
+    let usage = ResourceUsage {
+        max_rss: rusage.ru_maxrss as u64 * unit,
+        user_time: time(rusage.ru_utime),
+        system_time: time(rusage.ru_stime),
+    };
+    Ok(Some((ExitStatus::from_raw(status), Some(usage))))
+}
+
+/// Stops `child`, or the process group led by it with `group`. The process
+/// is asked to terminate first and killed if it didn't exit within
+/// [`TERMINATE_GRACE_PERIOD`]. Remaining members of the group are killed
+/// regardless.
+pub fn terminate(child: &mut Child, group: bool) -> std::io::Result<ExitStatus> {
+    #[cfg(unix)]
+    {
+        send_signal(child, group, Signal(libc::SIGTERM))?;
+        if let Some(status) = wait_until(child, Instant::now() + TERMINATE_GRACE_PERIOD)? {
+            if group {
+                kill(child, group)?;
+            }
+            return Ok(status);
+        }
+    }
+    kill(child, group)?;
+    child.wait()
+}
+
+/// Polls `child` until it exited or `deadline` passed.
+fn wait_until(child: &mut Child, deadline: Instant) -> std::io::Result<Option<ExitStatus>> {
+    poll_until(deadline, || child.try_wait())
+}
+
+/// Calls `poll` until it returns a value or `deadline` passed.
+fn poll_until<T>(
+    deadline: Instant,
+    mut poll: impl FnMut() -> std::io::Result<Option<T>>,
+) -> std::io::Result<Option<T>> {
+    loop {
+        if let Some(value) = poll()? {
+            return Ok(Some(value));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        std::thread::sleep((deadline - now).min(POLL_INTERVAL));
+    }
+}
+
+#[cfg(unix)]
+fn send_signal(child: &Child, group: bool, signal: Signal) -> std::io::Result<()> {
+    let pid = child.id() as i32;
+    let pid = if group { -pid } else { pid };
+    // SAFETY: kill has no memory safety requirements.
+    if unsafe { libc::kill(pid, signal.0) } == -1 {
+        let error = std::io::Error::last_os_error();
+        // The process exited in the meantime.
+        if error.raw_os_error() != Some(libc::ESRCH) {
+            return Err(error);
+        }
+    }
+    Ok(())
+}
+
+fn kill(child: &mut Child, group: bool) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if group {
+        return send_signal(child, true, Signal(libc::SIGKILL));
+    }
+    #[cfg(not(unix))]
+    let _ = group;
+    match child.kill() {
+        // The process exited in the meantime.
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => Ok(()),
+        r => r,
+    }
+}
+
+/// Applies `limits` to the process before it executes its command.
+#[cfg(unix)]
+pub fn set_limits(process: &mut Command, limits: Limits) -> std::io::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    if limits.is_empty() {
+        return Ok(());
+    }
+    let cpu = limits.cpu.map(|cpu| {
+        let seconds = cpu.as_secs();
+        if cpu.subsec_nanos() > 0 {
+            seconds + 1
+        } else {
+            seconds
+        }
+    });
+    let set = move |resource, value: Option<u64>| {
+        let Some(value) = value else { return Ok(()) };
+        let limit = libc::rlimit {
+            rlim_cur: value as libc::rlim_t,
+            rlim_max: value as libc::rlim_t,
+        };
+        // SAFETY: the pointer is valid for the duration of the call.
+        if unsafe { libc::setrlimit(resource, &limit) } == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    };
+    // SAFETY: only async-signal-safe functions are called in the child.
+    unsafe {
+        process.pre_exec(move || {
+            set(libc::RLIMIT_AS, limits.memory)?;
+            set(libc::RLIMIT_CPU, cpu)?;
+            set(libc::RLIMIT_FSIZE, limits.file_size)?;
+            set(libc::RLIMIT_NOFILE, limits.open_files)?;
+            set(libc::RLIMIT_NPROC, limits.processes)
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_limits(_process: &mut Command, limits: Limits) -> std::io::Result<()> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "resource limits are only supported on unix",
+    ))
+}
+
+/// Sets the file mode creation mask of the process.
+#[cfg(unix)]
+pub fn set_umask(process: &mut Command, umask: Option<u32>) -> std::io::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let Some(umask) = umask else { return Ok(()) };
+    // SAFETY: umask is async-signal-safe and can't fail.
+    unsafe {
+        process.pre_exec(move || {
+            libc::umask(umask as libc::mode_t);
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_umask(_process: &mut Command, umask: Option<u32>) -> std::io::Result<()> {
+    if umask.is_none() {
+        return Ok(());
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "umask is only supported on unix",
+    ))
+}
+
+/// Changes the scheduling priority of the process by `nice` and sets its
+/// I/O priority to `ionice`, which is only supported on Linux.
+#[cfg(unix)]
+pub fn set_priority(
+    process: &mut Command,
+    nice: Option<i32>,
+    ionice: Option<IoPriority>,
+) -> std::io::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    if nice.is_none() && ionice.is_none() {
+        return Ok(());
+    }
+    #[cfg(target_os = "linux")]
+    let ioprio = ionice.map(|priority| {
+        use crate::config::IoClass;
+
+        let (class, default_level) = match priority.class {
+            IoClass::Realtime => (1, 4),
+            IoClass::BestEffort => (2, 4),
+            IoClass::Idle => (3, 0),
+        };
+        (class << 13) | libc::c_long::from(priority.level.unwrap_or(default_level))
+    });
+    #[cfg(not(target_os = "linux"))]
+    if ionice.is_some() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "ionice is only supported on linux",
+        ));
+    }
+    // SAFETY: only async-signal-safe functions are called in the child.
+    unsafe {
+        process.pre_exec(move || {
+            if let Some(nice) = nice {
+                // Querying the calling process can't fail.
+                let current = libc::getpriority(libc::PRIO_PROCESS, 0);
+                if libc::setpriority(libc::PRIO_PROCESS, 0, current + nice) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(ioprio) = ioprio {
+                const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+                if libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_priority(
+    _process: &mut Command,
+    nice: Option<i32>,
+    ionice: Option<IoPriority>,
+) -> std::io::Result<()> {
+    if nice.is_none() && ionice.is_none() {
+        return Ok(());
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "nice and ionice are only supported on unix",
+    ))
+}
+
+/// Runs the process as `user` and `group`, given by name or id. Without a
+/// group the primary group of the user is used.
+#[cfg(unix)]
+pub fn set_user(
+    process: &mut Command,
+    user: Option<&str>,
+    group: Option<&str>,
+) -> std::io::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let mut gid = None;
+    if let Some(user) = user {
+        let (uid, primary_gid) = lookup_user(user)?;
+        process.uid(uid);
+        gid = primary_gid;
+    }
+    if let Some(group) = group {
+        gid = Some(lookup_group(group)?);
+    }
+    if let Some(gid) = gid {
+        process.gid(gid);
+    }
+    Ok(())
+}
+
+/// Finds the id and the primary group of the user with the given name or
+/// id. Numeric ids don't have to exist.
+#[cfg(unix)]
+fn lookup_user(user: &str) -> std::io::Result<(libc::uid_t, Option<libc::gid_t>)> {
+    // SAFETY: the struct consists of integers and pointers.
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut result = std::ptr::null_mut();
+    let id = user.parse::<libc::uid_t>().ok();
+    let name = std::ffi::CString::new(user)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: the pointers are valid for the duration of the call.
+    let error = unsafe {
+        match id {
+            Some(id) => libc::getpwuid_r(
+                id,
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            ),
+            None => libc::getpwnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            ),
+        }
+    };
+    if error != 0 {
+        return Err(std::io::Error::from_raw_os_error(error));
+    }
+    match (id, result.is_null()) {
+        (_, false) => Ok((entry.pw_uid, Some(entry.pw_gid))),
+        (Some(id), true) => Ok((id, None)),
+        (None, true) => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("unknown user {}", user),
+        )),
+    }
+}
+
+/// Finds the id of the group with the given name or id.
+#[cfg(unix)]
+fn lookup_group(group: &str) -> std::io::Result<libc::gid_t> {
+    if let Ok(id) = group.parse() {
+        return Ok(id);
+    }
+    // SAFETY: the struct consists of integers and pointers.
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut result = std::ptr::null_mut();
+    let name = std::ffi::CString::new(group)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: the pointers are valid for the duration of the call.
+    let error = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if error != 0 {
+        return Err(std::io::Error::from_raw_os_error(error));
+    }
+    if result.is_null() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("unknown group {}", group),
+        ));
+    }
+    Ok(entry.gr_gid)
+}
+
+#[cfg(not(unix))]
+pub fn set_user(
+    _process: &mut Command,
+    user: Option<&str>,
+    group: Option<&str>,
+) -> std::io::Result<()> {
+    if user.is_none() && group.is_none() {
+        return Ok(());
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "user and group are only supported on unix",
+    ))
+}
+
+/// Runs the process in the Linux namespaces requested by `isolation`.
+#[cfg(target_os = "linux")]
+pub fn isolate(process: &mut Command, isolation: Isolation) -> std::io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::process::CommandExt;
+
+    if isolation.is_empty() {
+        return Ok(());
+    }
+    // Everything the child needs is prepared here, it must not allocate.
+    // SAFETY: getuid and getgid can't fail.
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    let uid_map = format!("{0} {0} 1", uid);
+    let gid_map = format!("{0} {0} 1", gid);
+    let path = |s: &str| CString::new(s).expect("no NUL in constant");
+    let (setgroups_path, uid_map_path, gid_map_path) = (
+        path("/proc/self/setgroups"),
+        path("/proc/self/uid_map"),
+        path("/proc/self/gid_map"),
+    );
+    let (root, tmp, tmpfs, proc_path, proc_type) = (
+        path("/"),
+        path("/tmp"),
+        path("tmpfs"),
+        path("/proc"),
+        path("proc"),
+    );
+    let mut flags = libc::CLONE_NEWUSER;
+    if isolation.network {
+        flags |= libc::CLONE_NEWNET;
+    }
+    if isolation.private_tmp || isolation.pid {
+        flags |= libc::CLONE_NEWNS;
+    }
+    if isolation.pid {
+        flags |= libc::CLONE_NEWPID;
+    }
+
+    let check = |result: libc::c_int| {
+        if result == -1 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+    let write_file = move |path: &CString, content: &[u8]| {
+        // SAFETY: the pointers are valid for the duration of the calls.
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            check(fd)?;
+            let written = libc::write(fd, content.as_ptr().cast(), content.len());
+            libc::close(fd);
+            if written == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    };
+    // SAFETY: only async-signal-safe functions are called in the child.
+    unsafe {
+        process.pre_exec(move || {
+            check(libc::unshare(flags))?;
+            write_file(&setgroups_path, b"deny")?;
+            write_file(&uid_map_path, uid_map.as_bytes())?;
+            write_file(&gid_map_path, gid_map.as_bytes())?;
+            if flags & libc::CLONE_NEWNS != 0 {
+                // Keep the mounts from propagating back to the system.
+                check(libc::mount(
+                    std::ptr::null(),
+                    root.as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    std::ptr::null(),
+                ))?;
+            }
+            if isolation.private_tmp {
+                check(libc::mount(
+                    tmpfs.as_ptr(),
+                    tmp.as_ptr(),
+                    tmpfs.as_ptr(),
+                    0,
+                    std::ptr::null(),
+                ))?;
+            }
+            if isolation.network {
+                set_loopback_up()?;
+            }
+            if isolation.pid {
+                // Only the children of this process enter the new PID
+                // namespace, so the command runs in a child that this
+                // process waits for.
+                let child = libc::fork();
+                check(child)?;
+                if child > 0 {
+                    wait_for_child(child);
+                }
+                // Stop if smokers stops waiting for the process.
+                libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+                check(libc::mount(
+                    proc_type.as_ptr(),
+                    proc_path.as_ptr(),
+                    proc_type.as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                    std::ptr::null(),
+                ))?;
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// Brings up the loopback interface of a new network namespace.
+#[cfg(target_os = "linux")]
+unsafe fn set_loopback_up() -> std::io::Result<()> {
+    let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+    if socket == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    /// The part of `struct ifreq` used to get and set the flags.
+    #[repr(C)]
+    struct InterfaceRequest {
+        name: [libc::c_char; libc::IFNAMSIZ],
+        flags: libc::c_short,
+        padding: [u8; 22],
+    }
+
+    let mut request: InterfaceRequest = std::mem::zeroed();
+    for (dst, src) in request.name.iter_mut().zip(b"lo") {
+        *dst = *src as libc::c_char;
+    }
+    let mut result = libc::ioctl(socket, libc::SIOCGIFFLAGS, &mut request);
+    if result != -1 {
+        request.flags |= libc::IFF_UP as libc::c_short;
+        result = libc::ioctl(socket, libc::SIOCSIFFLAGS, &request);
+    }
+    let error = std::io::Error::last_os_error();
+    libc::close(socket);
+    if result == -1 {
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// Waits for the `child` running the command in a PID namespace and exits
+/// the same way it did. Never returns.
+#[cfg(target_os = "linux")]
+unsafe fn wait_for_child(child: libc::pid_t) -> ! {
+    // The standard library learns about a failed exec through a pipe that
+    // is closed on exec, which this process never does.
+    let max_fd = libc::sysconf(libc::_SC_OPEN_MAX).clamp(0, 65536) as libc::c_int;
+    for fd in 3..max_fd {
+        libc::close(fd);
+    }
+    let mut status = 0;
+    while libc::waitpid(child, &mut status, 0) == -1 {
+        if std::io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+            libc::_exit(127);
+        }
+    }
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn isolate(_process: &mut Command, isolation: Isolation) -> std::io::Result<()> {
+    if isolation.is_empty() {
+        return Ok(());
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "isolation is only supported on linux",
+    ))
+}
+
+/// Collects what is read from a stream on a background thread, up to the
+/// `max_output` limit. The stream is read to its end regardless, so the
+/// writing process doesn't block.
+pub struct Capture {
+    data: Arc<Mutex<Vec<u8>>>,
+    truncated: Arc<AtomicBool>,
+    done: mpsc::Receiver<()>,
+}
+
+impl Capture {
+    pub fn start(mut reader: impl Read + Send + 'static, options: &Options) -> Self {
+        let data = Arc::new(Mutex::new(vec![]));
+        let truncated = Arc::new(AtomicBool::new(false));
+        let (sender, done) = mpsc::channel();
+        let (shared, shared_truncated) = (data.clone(), truncated.clone());
+        let limit = options.max_output.map_or(usize::MAX, |limit| {
+            usize::try_from(limit).unwrap_or(usize::MAX)
+        });
+        let mut echo = options.echo.map(|prefix| Echo::new(prefix.to_string()));
+        std::thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        if let Some(echo) = &mut echo {
+                            echo.write(&buffer[..n]);
+                        }
+                        let mut data = shared.lock().unwrap();
+                        let kept = n.min(limit - data.len());
+                        data.extend_from_slice(&buffer[..kept]);
+                        if kept < n {
+                            shared_truncated.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    // A pseudo-terminal reports an error once the process
+                    // exited.
+                    Err(_) => break,
+                }
+            }
+            if let Some(echo) = &mut echo {
+                echo.finish();
+            }
+            let _ = sender.send(());
+        });
+        Self {
+            data,
+            truncated,
+            done,
+        }
+    }
+
+    /// Waits for the end of the stream, at most for `timeout`, and returns
+    /// what was captured so far and whether anything was discarded.
+    pub fn finish(self, timeout: Option<Duration>) -> (Vec<u8>, bool) {
+        match timeout {
+            None => {
+                let _ = self.done.recv();
+            }
+            Some(timeout) => {
+                let _ = self.done.recv_timeout(timeout);
+            }
+        }
+        let mut data = self.data.lock().unwrap();
+        (
+            std::mem::take(&mut *data),
+            self.truncated.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Copies output to stderr line by line, prefixing each line to tell apart
+/// the output of tests running in parallel.
+struct Echo {
+    prefix: String,
+    line: Vec<u8>,
+}
+
+impl Echo {
+    fn new(prefix: String) -> Self {
+        Self {
+            prefix,
+            line: vec![],
+        }
+    }
+
+    fn write(&mut self, mut data: &[u8]) {
+        while let Some(end) = data.iter().position(|&b| b == b'\n') {
+            self.line.extend_from_slice(&data[..=end]);
+            self.flush();
+            data = &data[end + 1..];
+        }
+        self.line.extend_from_slice(data);
+    }
+
+    /// Writes the last line if it lacks a newline.
+    fn finish(&mut self) {
+        if !self.line.is_empty() {
+            self.line.push(b'\n');
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        let mut stderr = std::io::stderr().lock();
+        // Failing to echo the output doesn't affect the test.
+        let _ = stderr
+            .write_all(self.prefix.as_bytes())
+            .and_then(|()| stderr.write_all(&self.line));
+        self.line.clear();
+    }
+}
@@ -0,0 +1,104 @@
+//! The progress line shown on a terminal while the tests run.
+
+use crate::report::{Reporter, TestResult};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// The width the progress line is cut to, so it never wraps and can be
+/// overwritten.
+const WIDTH: usize = 80;
+
+/// Forwards all events to another reporter while showing the number of
+/// finished tests and the test that is running on `out`, usually stderr.
+/// The line is cleared before the other reporter writes anything.
+pub struct ProgressReporter<'a, W: Write> {
+    inner: &'a mut dyn Reporter,
+    out: W,
+    total: usize,
+    finished: usize,
+    running: BTreeMap<usize, String>,
+}
+
+impl<'a, W: Write> ProgressReporter<'a, W> {
+    pub fn new(inner: &'a mut dyn Reporter, out: W) -> Self {
+        Self {
+            inner,
+            out,
+            total: 0,
+            finished: 0,
+            running: BTreeMap::new(),
+        }
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        write!(self.out, "\r\x1b[K")?;
+        self.out.flush()
+    }
+
+    fn draw(&mut self) -> std::io::Result<()> {
+        // The first running test is the one holding back the results of the
+        // others.
+        let Some(name) = self.running.values().next() else {
+            return self.clear();
+        };
+        let line = format!("{}/{} [{}] running…", self.finished, self.total, name);
+        let line: String = line.chars().take(WIDTH - 1).collect();
+        write!(self.out, "\r\x1b[K{}", line)?;
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Reporter for ProgressReporter<'_, W> {
+    fn start(&mut self, total: usize) -> std::io::Result<()> {
+        self.total = total;
+        self.finished = 0;
+        self.running.clear();
+        self.inner.start(total)
+    }
+
+    fn test_started(&mut self, index: usize, name: &str) -> std::io::Result<()> {
+        self.running.insert(index, name.to_string());
+        self.draw()?;
+        self.inner.test_started(index, name)
+    }
+
+    fn test_finished(&mut self, index: usize, report: &TestResult) -> std::io::Result<()> {
+        self.running.remove(&index);
+        self.finished += 1;
+        self.clear()?;
+        self.inner.test_finished(index, report)?;
+        self.draw()
+    }
+
+    fn finish(&mut self, reports: &[TestResult]) -> std::io::Result<()> {
+        self.clear()?;
+        self.inner.finish(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::{reporter, Format};
+
+    #[test]
+    fn test_progress() {
+        let (mut out, mut progress_out) = (vec![], vec![]);
+        {
+            let mut inner = reporter(Format::Tap, &mut out, false, false);
+            let mut progress = ProgressReporter::new(inner.as_mut(), &mut progress_out);
+            progress.start(2).unwrap();
+            progress.test_started(0, "first").unwrap();
+            progress.test_started(1, "second").unwrap();
+            progress.test_finished(0, &TestResult::default()).unwrap();
+            progress.finish(&[]).unwrap();
+        }
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("TAP version 13\n1..2\nnot ok 1"));
+        assert_eq!(
+            String::from_utf8(progress_out).unwrap(),
+            "\r\x1b[K0/2 [first] running…\r\x1b[K0/2 [first] running…\r\x1b[K\r\x1b[K1/2 [second] running…\r\x1b[K"
+        );
+    }
+}
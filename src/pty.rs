@@ -0,0 +1,117 @@
+//! Running processes attached to a pseudo-terminal.
+
+use crate::process::{Finished, Options};
+use std::process::Command;
+
+/// Runs `process` with its stdin, stdout and stderr attached to a new
+/// pseudo-terminal as configured in `options`, and collects everything the
+/// process printed as stdout.
+///
+/// Echoing of the input and the translation of newlines to `\r\n` are
+/// disabled so the output can be compared as it was written by the
+/// process.
+#[cfg(unix)]
+pub fn output(mut process: Command, options: &Options) -> std::io::Result<Finished> {
+    use crate::process::{spawn, wait, Capture, KILL_GRACE_PERIOD};
+    use std::io::Write;
+    use std::os::unix::process::CommandExt;
+
+    let (master, slave) = open()?;
+    process
+        .stdin(slave.try_clone()?)
+        .stdout(slave.try_clone()?)
+        .stderr(slave.try_clone()?);
+    // SAFETY: only async-signal-safe functions are called in the child.
+    unsafe {
+        process.pre_exec(|| {
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = spawn(&mut process)?;
+    // Reading from the master only fails with EIO once no process holds
+    // the slave open anymore, so our copies have to be closed.
+    drop(process);
+    drop(slave);
+
+    // The terminal signals the end of the input with ^D at the start of a
+    // line.
+    let mut input = options.input.map_or(Ok(vec![]), |input| input.read())?;
+    if !input.is_empty() && !input.ends_with(b"\n") {
+        input.push(b'\x04');
+    }
+    input.push(b'\x04');
+    let mut writer = master.try_clone()?;
+    let stdin_writer = std::thread::spawn(move || writer.write_all(&input));
+
+    let stdout = Capture::start(master, options);
+
+    // The process leads its own session, so the processes it started are
+    // killed as well on timeout.
+    let (status, timed_out, usage) = wait(&mut child, options, true)?;
+    // Input the process didn't read is discarded, the writer is left to
+    // finish on its own.
+    drop(stdin_writer);
+
+    let (stdout, stdout_truncated) = stdout.finish(timed_out.then_some(KILL_GRACE_PERIOD));
+    Ok(Finished {
+        output: std::process::Output {
+            status,
+            stdout,
+            stderr: vec![],
+        },
+        timed_out,
+        stdout_truncated,
+        stderr_truncated: false,
+        usage,
+    })
+}
+
+/// Opens a new pseudo-terminal, returning its master and slave side.
+#[cfg(unix)]
+fn open() -> std::io::Result<(std::fs::File, std::fs::File)> {
+    use std::fs::File;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let (mut master, mut slave) = (0, 0);
+    let mut size = libc::winsize {
+        ws_row: 24,
+        ws_col: 80,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: all pointers are valid for the duration of the calls and
+    // the returned file descriptors are owned by the files.
+    unsafe {
+        // The mutability of the pointers differs between platforms.
+        let name = std::ptr::null_mut();
+        let termp = std::ptr::null_mut();
+        let size = std::ptr::addr_of_mut!(size);
+        if libc::openpty(&mut master, &mut slave, name, termp, size) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let (master, slave) = (File::from_raw_fd(master), File::from_raw_fd(slave));
+
+        let mut termios = std::mem::MaybeUninit::<libc::termios>::zeroed();
+        if libc::tcgetattr(slave.as_raw_fd(), termios.as_mut_ptr()) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut termios = termios.assume_init();
+        termios.c_lflag &= !libc::ECHO;
+        termios.c_oflag &= !libc::ONLCR;
+        if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok((master, slave))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn output(_process: Command, _options: &Options) -> std::io::Result<Finished> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "pseudo-terminals are only supported on unix",
+    ))
+}
@@ -0,0 +1,946 @@
+//! Reporting of test results in the different output formats.
+
+use crate::sandbox::Sandbox;
+use serde::Serialize;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The output format of the test results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum Format {
+    /// Human readable output.
+    Human,
+    /// Test Anything Protocol, see <https://testanything.org>.
+    Tap,
+    /// A JSON document containing all test results.
+    Json,
+    /// GitHub Actions workflow commands that annotate the definitions of
+    /// failed tests.
+    Github,
+}
+
+/// When to use colors in the human readable output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub enum ColorChoice {
+    /// Use colors if stdout is a terminal and `NO_COLOR` isn't set.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Wraps `text` in the given ANSI escape sequence if `enabled`.
+fn paint(enabled: bool, style: &str, text: &str) -> String {
+    if enabled {
+        format!("{}{}{}", style, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// The style of a line of the failure log, highlighting unified diffs.
+fn log_line_style(line: &str) -> Option<&'static str> {
+    if line.starts_with("+++ ") || line.starts_with("--- ") {
+        Some(BOLD)
+    } else if line.starts_with("@@") {
+        Some(CYAN)
+    } else if line.starts_with('+') {
+        Some(GREEN)
+    } else if line.starts_with('-') {
+        Some(RED)
+    } else {
+        None
+    }
+}
+
+/// The outcome of a single test case.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct TestResult {
+    /// The configuration file the test case is defined in.
+    pub file: Option<PathBuf>,
+    pub name: Option<String>,
+    /// What the test case checks.
+    pub description: Option<String>,
+    pub command: Vec<String>,
+    pub passed: bool,
+    /// Why the test wasn't run, if it was skipped.
+    pub skipped: Option<String>,
+    /// Whether the test is marked as expected to fail. `passed` is
+    /// inverted accordingly.
+    pub expected_failure: bool,
+    /// Whether the test wasn't run because it passed before with the same
+    /// inputs.
+    pub cached: bool,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    /// The wall-clock duration of the process, serialized in seconds.
+    #[serde(serialize_with = "serialize_duration")]
+    pub duration: Duration,
+    /// The reasons why the test failed, one per line.
+    pub failures: Vec<String>,
+    /// How often the test was executed until it passed or ran out of
+    /// retries.
+    pub attempts: u32,
+    /// How often the test was run with `--repeat`.
+    pub runs: u32,
+    /// How many of the runs failed.
+    pub failed_runs: u32,
+    /// The resources used by the process, where they can be measured.
+    pub resource_usage: Option<ResourceUsage>,
+    /// The expected texts of the output that the actual output differs
+    /// from.
+    #[serde(skip)]
+    pub mismatched_output: Vec<OutputMismatch>,
+    /// The texts that differ from what was expected, collected for the
+    /// diff tool.
+    #[serde(skip)]
+    pub diffs: Vec<TextDiff>,
+    /// The sandbox the test ran in, retained to copy it to the artifacts
+    /// of the test.
+    #[serde(skip)]
+    pub sandbox: Option<Arc<Sandbox>>,
+}
+
+/// An expected text of `stdout` or `stderr` that the actual output didn't
+/// match, which can be replaced with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputMismatch {
+    /// `stdout` or `stderr`.
+    pub stream: String,
+    /// The golden file holding the text, the text is part of the
+    /// configuration otherwise.
+    pub golden_file: Option<PathBuf>,
+}
+
+/// The expected and the actual text of an output stream or file that
+/// differ.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextDiff {
+    /// `stdout`, `stderr` or the path of the file.
+    pub name: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// The resources used by a process, including the processes it waited
+/// for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ResourceUsage {
+    /// The maximum resident set size in bytes.
+    pub max_rss: u64,
+    /// The CPU time spent in user mode, serialized in seconds.
+    #[serde(serialize_with = "serialize_duration")]
+    pub user_time: Duration,
+    /// The CPU time spent in the kernel, serialized in seconds.
+    #[serde(serialize_with = "serialize_duration")]
+    pub system_time: Duration,
+}
+
+impl ResourceUsage {
+    /// The CPU time spent in user mode and in the kernel.
+    pub fn cpu_time(&self) -> Duration {
+        self.user_time + self.system_time
+    }
+}
+
+fn serialize_duration<S: serde::Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64())
+}
+
+/// Formats a number of bytes for humans, in the largest binary unit that
+/// keeps the number at least 1.
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Formats `d` for humans, in milliseconds below a second.
+pub(crate) fn format_duration(d: Duration) -> String {
+    if d < Duration::from_secs(1) {
+        format!("{}ms", d.as_millis())
+    } else {
+        format!("{:.2}s", d.as_secs_f64())
+    }
+}
+
+impl TestResult {
+    /// The name of the test case qualified with the file it is defined in.
+    pub fn display_name(&self) -> Option<String> {
+        match (&self.file, &self.name) {
+            (Some(file), Some(name)) => Some(format!("{}::{}", file.display(), name)),
+            (Some(file), None) => Some(file.display().to_string()),
+            (None, name) => name.clone(),
+        }
+    }
+
+    /// Whether the test failed as expected.
+    pub fn is_xfail(&self) -> bool {
+        self.passed && self.expected_failure
+    }
+
+    /// Whether the test only passed after being retried.
+    pub fn is_flaky(&self) -> bool {
+        self.passed && self.attempts > 1
+    }
+
+    /// Whether the test both passed and failed in repeated runs.
+    pub fn is_inconsistent(&self) -> bool {
+        self.failed_runs > 0 && self.failed_runs < self.runs
+    }
+
+    /// The percentage of failed runs.
+    pub fn failure_rate(&self) -> f64 {
+        if self.runs == 0 {
+            return 0.0;
+        }
+        100.0 * f64::from(self.failed_runs) / f64::from(self.runs)
+    }
+
+    /// Writes the failure reasons followed by the captured output of a
+    /// failed test. Nothing is written for tests that passed.
+    pub fn write_log(&self, out: &mut impl Write) -> std::io::Result<()> {
+        if self.passed {
+            return Ok(());
+        }
+        if let Some(description) = &self.description {
+            writeln!(out, "Description: {}", description.trim_end())?;
+        }
+        for failure in &self.failures {
+            writeln!(out, "{}", failure)?;
+        }
+        writeln!(out, "stdout: {:?}", self.stdout)?;
+        writeln!(out, "stderr: {:?}", self.stderr)
+    }
+}
+
+/// Receives the test results as the tests are executed.
+pub trait Reporter {
+    /// Called once before any test is executed.
+    fn start(&mut self, _total: usize) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Called when a test case starts running, with its position in the
+    /// suite and its name. Tests running in parallel start in any order.
+    fn test_started(&mut self, _index: usize, _name: &str) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Called after each test case with its position in the suite.
+    fn test_finished(&mut self, index: usize, report: &TestResult) -> std::io::Result<()>;
+
+    /// Called once after all tests have been executed.
+    fn finish(&mut self, reports: &[TestResult]) -> std::io::Result<()>;
+}
+
+/// Creates a reporter for the given format that writes to `out`. Colors
+/// and `quiet` only affect the human readable format.
+pub fn reporter<'a, W: Write + 'a>(
+    format: Format,
+    out: W,
+    color: bool,
+    quiet: bool,
+) -> Box<dyn Reporter + 'a> {
+    match format {
+        Format::Human => Box::new(HumanReporter::new(out).with_color(color).with_quiet(quiet)),
+        Format::Tap => Box::new(TapReporter { out }),
+        Format::Json => Box::new(JsonReporter { out }),
+        Format::Github => Box::new(GithubReporter { out }),
+    }
+}
+
+/// How many of the slowest tests are listed in the summary.
+const SLOWEST_TESTS: usize = 3;
+
+pub struct HumanReporter<W> {
+    out: W,
+    named: bool,
+    color: bool,
+    quiet: bool,
+    started: Option<Instant>,
+}
+
+impl<W: Write> HumanReporter<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            named: false,
+            color: false,
+            quiet: false,
+            started: None,
+        }
+    }
+
+    pub fn with_color(self, color: bool) -> Self {
+        Self { color, ..self }
+    }
+
+    /// Only report failed tests and the summary.
+    pub fn with_quiet(self, quiet: bool) -> Self {
+        Self { quiet, ..self }
+    }
+}
+
+impl<W: Write> Reporter for HumanReporter<W> {
+    fn start(&mut self, total: usize) -> std::io::Result<()> {
+        self.named = total > 1;
+        self.started = Some(Instant::now());
+        Ok(())
+    }
+
+    fn test_finished(&mut self, index: usize, report: &TestResult) -> std::io::Result<()> {
+        if self.quiet && report.passed {
+            return Ok(());
+        }
+        if let (Some(reason), true) = (&report.skipped, report.passed) {
+            let name = report
+                .display_name()
+                .unwrap_or_else(|| format!("#{}", index + 1));
+            let status = paint(self.color, YELLOW, "skipped");
+            return writeln!(self.out, "{}: {} ({})", name, status, reason);
+        }
+        if report.cached && report.passed {
+            let name = report
+                .display_name()
+                .unwrap_or_else(|| format!("#{}", index + 1));
+            let status = paint(self.color, GREEN, "cached-pass");
+            return writeln!(self.out, "{}: {}", name, status);
+        }
+        // A lone unnamed test is reported without a header line.
+        if self.named || report.name.is_some() {
+            let status = match (report.passed, report.expected_failure) {
+                (true, false) => paint(self.color, GREEN, "passed"),
+                (true, true) => paint(self.color, YELLOW, "xfail"),
+                (false, false) => paint(self.color, RED, "failed"),
+                (false, true) => paint(self.color, RED, "unexpected pass"),
+            };
+            write!(
+                self.out,
+                "{}: {} in {}",
+                report
+                    .display_name()
+                    .unwrap_or_else(|| format!("#{}", index + 1)),
+                status,
+                format_duration(report.duration)
+            )?;
+            if report.is_flaky() {
+                let flaky = format!("(flaky, {} attempts)", report.attempts);
+                write!(self.out, " {}", paint(self.color, YELLOW, &flaky))?;
+            }
+            if report.is_inconsistent() {
+                let inconsistent = format!(
+                    "(inconsistent, failed {} of {} runs)",
+                    report.failed_runs, report.runs
+                );
+                write!(self.out, " {}", paint(self.color, YELLOW, &inconsistent))?;
+            }
+            writeln!(self.out)?;
+        }
+
+        if !self.color {
+            return report.write_log(&mut self.out);
+        }
+        let mut log = vec![];
+        report.write_log(&mut log)?;
+        for line in String::from_utf8_lossy(&log).lines() {
+            match log_line_style(line) {
+                Some(style) => writeln!(self.out, "{}", paint(true, style, line))?,
+                None => writeln!(self.out, "{}", line)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, reports: &[TestResult]) -> std::io::Result<()> {
+        if reports.len() > 1 {
+            self.write_summary(reports)?;
+        }
+        if reports.iter().all(|r| r.passed) {
+            writeln!(self.out, "{}", paint(self.color, GREEN, "No errors."))
+        } else {
+            writeln!(self.out, "{}", paint(self.color, RED, "Errors."))
+        }
+    }
+}
+
+impl<W: Write> HumanReporter<W> {
+    /// Writes the number of passed and failed tests, the total duration and
+    /// the slowest tests.
+    fn write_summary(&mut self, reports: &[TestResult]) -> std::io::Result<()> {
+        let skipped = reports
+            .iter()
+            .filter(|r| r.passed && r.skipped.is_some())
+            .count();
+        let xfail = reports.iter().filter(|r| r.is_xfail()).count();
+        let passed = reports.iter().filter(|r| r.passed).count() - skipped - xfail;
+        let flaky = reports.iter().filter(|r| r.is_flaky()).count();
+        let cached = reports.iter().filter(|r| r.cached && r.passed).count();
+        let failed = reports.len() - passed - skipped - xfail;
+        let total = self
+            .started
+            .map_or(Duration::ZERO, |started| started.elapsed());
+        write!(
+            self.out,
+            "\n{} tests: {} passed, {} failed",
+            reports.len(),
+            paint(self.color && passed > 0, GREEN, &passed.to_string()),
+            paint(self.color && failed > 0, RED, &failed.to_string())
+        )?;
+        if skipped > 0 {
+            let skipped = format!("{} skipped", skipped);
+            write!(self.out, ", {}", paint(self.color, YELLOW, &skipped))?;
+        }
+        if xfail > 0 {
+            let xfail = format!("{} xfail", xfail);
+            write!(self.out, ", {}", paint(self.color, YELLOW, &xfail))?;
+        }
+        if cached > 0 {
+            let cached = format!("({} cached)", cached);
+            write!(self.out, " {}", paint(self.color, GREEN, &cached))?;
+        }
+        if flaky > 0 {
+            write!(
+                self.out,
+                " ({})",
+                paint(self.color, YELLOW, &format!("{} flaky", flaky))
+            )?;
+        }
+        writeln!(self.out, " in {}", format_duration(total))?;
+
+        let mut slowest: Vec<(usize, &TestResult)> = reports
+            .iter()
+            .enumerate()
+            .filter(|(_, report)| report.skipped.is_none() && !report.cached)
+            .collect();
+        slowest.sort_by_key(|(_, report)| std::cmp::Reverse(report.duration));
+        writeln!(self.out, "Slowest tests:")?;
+        for (index, report) in slowest.into_iter().take(SLOWEST_TESTS) {
+            writeln!(
+                self.out,
+                "  {:>8}  {}",
+                format_duration(report.duration),
+                report
+                    .display_name()
+                    .unwrap_or_else(|| format!("#{}", index + 1))
+            )?;
+        }
+
+        let mut inconsistent: Vec<(usize, &TestResult)> = reports
+            .iter()
+            .enumerate()
+            .filter(|(_, report)| report.is_inconsistent())
+            .collect();
+        if !inconsistent.is_empty() {
+            inconsistent.sort_by(|(_, a), (_, b)| b.failure_rate().total_cmp(&a.failure_rate()));
+            writeln!(self.out, "Inconsistent tests:")?;
+            for (index, report) in inconsistent {
+                writeln!(
+                    self.out,
+                    "  {:>7.0}%  {}",
+                    report.failure_rate(),
+                    report
+                        .display_name()
+                        .unwrap_or_else(|| format!("#{}", index + 1))
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct TapReporter<W> {
+    out: W,
+}
+
+impl<W: Write> Reporter for TapReporter<W> {
+    fn start(&mut self, total: usize) -> std::io::Result<()> {
+        writeln!(self.out, "TAP version 13")?;
+        writeln!(self.out, "1..{}", total)
+    }
+
+    fn test_finished(&mut self, index: usize, report: &TestResult) -> std::io::Result<()> {
+        let status = if report.passed && !report.expected_failure {
+            "ok"
+        } else {
+            "not ok"
+        };
+        match report.display_name() {
+            Some(name) => write!(self.out, "{} {} - {}", status, index + 1, name)?,
+            None => write!(self.out, "{} {}", status, index + 1)?,
+        }
+        if let (Some(reason), true) = (&report.skipped, report.passed) {
+            return writeln!(self.out, " # SKIP {}", reason);
+        }
+        if report.is_xfail() {
+            // TAP reports expected failures as failing TODO tests.
+            return writeln!(self.out, " # TODO expected failure");
+        }
+        // The timing comment as written by node-tap.
+        writeln!(
+            self.out,
+            " # time={:.3}ms",
+            report.duration.as_secs_f64() * 1000.0
+        )?;
+        if report.is_flaky() {
+            writeln!(
+                self.out,
+                "# flaky: passed after {} attempts",
+                report.attempts
+            )?;
+        }
+        let mut log = vec![];
+        report.write_log(&mut log)?;
+        for line in String::from_utf8_lossy(&log).lines() {
+            writeln!(self.out, "# {}", line)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, _reports: &[TestResult]) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct JsonReporter<W> {
+    out: W,
+}
+
+impl<W: Write> Reporter for JsonReporter<W> {
+    fn test_finished(&mut self, _index: usize, _report: &TestResult) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self, reports: &[TestResult]) -> std::io::Result<()> {
+        #[derive(Serialize)]
+        struct Document<'a> {
+            passed: bool,
+            tests: &'a [TestResult],
+        }
+
+        let document = Document {
+            passed: reports.iter().all(|r| r.passed),
+            tests: reports,
+        };
+        serde_json::to_writer_pretty(&mut self.out, &document)?;
+        writeln!(self.out)
+    }
+}
+
+pub struct GithubReporter<W> {
+    out: W,
+}
+
+impl<W: Write> Reporter for GithubReporter<W> {
+    fn test_finished(&mut self, index: usize, report: &TestResult) -> std::io::Result<()> {
+        let name = report
+            .display_name()
+            .unwrap_or_else(|| format!("#{}", index + 1));
+        if let (Some(reason), true) = (&report.skipped, report.passed) {
+            return writeln!(self.out, "{}: skipped ({})", name, reason);
+        }
+        if report.is_xfail() {
+            return writeln!(self.out, "{}: xfail", name);
+        }
+        let (command, message) = if !report.passed {
+            let mut log = vec![];
+            report.write_log(&mut log)?;
+            ("error", String::from_utf8_lossy(&log).into_owned())
+        } else if report.is_flaky() {
+            let message = format!("Passed after {} attempts", report.attempts);
+            ("warning", message)
+        } else {
+            return writeln!(self.out, "{}: passed", name);
+        };
+        let mut properties = vec![];
+        if let Some(file) = &report.file {
+            properties.push(format!(
+                "file={}",
+                escape_property(&file.display().to_string())
+            ));
+            let line = std::fs::read_to_string(file).ok().and_then(|source| {
+                definition_line(&source, report.name.as_deref().unwrap_or_default())
+            });
+            properties.push(format!("line={}", line.unwrap_or(1)));
+        }
+        properties.push(format!("title={}", escape_property(&name)));
+        writeln!(
+            self.out,
+            "::{} {}::{}",
+            command,
+            properties.join(","),
+            escape_data(message.trim_end())
+        )
+    }
+
+    fn finish(&mut self, reports: &[TestResult]) -> std::io::Result<()> {
+        let passed = reports.iter().filter(|r| r.passed).count();
+        writeln!(
+            self.out,
+            "{} passed, {} failed",
+            passed,
+            reports.len() - passed
+        )
+    }
+}
+
+/// The line of the key defining the test `name` in a configuration file,
+/// ignoring the parameters of matrix tests.
+fn definition_line(source: &str, name: &str) -> Option<usize> {
+    let name = name.split_once('[').map_or(name, |(base, _)| base);
+    if name.is_empty() {
+        return None;
+    }
+    let keys = [
+        format!("{}:", name),
+        format!("\"{}\":", name),
+        format!("'{}':", name),
+        format!("[tests.{}]", name),
+        format!("[tests.\"{}\"]", name),
+    ];
+    source
+        .lines()
+        .position(|line| {
+            let line = line.trim_start();
+            keys.iter().any(|key| line.starts_with(key.as_str()))
+        })
+        .map(|i| i + 1)
+}
+
+/// Escapes the message of a workflow command.
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes a property value of a workflow command.
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Forwards all events to another reporter while keeping the results of
+/// the tests.
+pub struct CollectingReporter<'a> {
+    inner: &'a mut dyn Reporter,
+    pub reports: Vec<TestResult>,
+}
+
+impl<'a> CollectingReporter<'a> {
+    pub fn new(inner: &'a mut dyn Reporter) -> Self {
+        Self {
+            inner,
+            reports: vec![],
+        }
+    }
+}
+
+impl Reporter for CollectingReporter<'_> {
+    fn start(&mut self, total: usize) -> std::io::Result<()> {
+        self.inner.start(total)
+    }
+
+    fn test_started(&mut self, index: usize, name: &str) -> std::io::Result<()> {
+        self.inner.test_started(index, name)
+    }
+
+    fn test_finished(&mut self, index: usize, report: &TestResult) -> std::io::Result<()> {
+        self.inner.test_finished(index, report)
+    }
+
+    fn finish(&mut self, reports: &[TestResult]) -> std::io::Result<()> {
+        self.reports = reports.to_vec();
+        self.inner.finish(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reports() -> Vec<TestResult> {
+        vec![
+            TestResult {
+                file: Some(PathBuf::from("tests/a.smoke.yaml")),
+                name: Some("good".to_string()),
+                command: vec!["true".to_string()],
+                passed: true,
+                exit_code: Some(0),
+                duration: Duration::from_millis(1500),
+                attempts: 2,
+                ..TestResult::default()
+            },
+            TestResult {
+                command: vec!["false".to_string()],
+                exit_code: Some(1),
+                stderr: "oops".to_string(),
+                duration: Duration::from_micros(12345),
+                failures: vec!["Unexpected exit code 1, expected 0".to_string()],
+                ..TestResult::default()
+            },
+        ]
+    }
+
+    fn render(format: Format) -> String {
+        render_with_color(format, false)
+    }
+
+    fn render_with_color(format: Format, color: bool) -> String {
+        render_with(format, color, false)
+    }
+
+    fn render_with(format: Format, color: bool, quiet: bool) -> String {
+        render_reports(format, color, quiet, reports())
+    }
+
+    fn render_reports(
+        format: Format,
+        color: bool,
+        quiet: bool,
+        reports: Vec<TestResult>,
+    ) -> String {
+        let mut out = vec![];
+        {
+            let mut reporter = reporter(format, &mut out, color, quiet);
+            reporter.start(reports.len()).unwrap();
+            for (i, report) in reports.iter().enumerate() {
+                reporter.test_finished(i, report).unwrap();
+            }
+            reporter.finish(&reports).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 bytes");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 << 30), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_human() {
+        let output = render(Format::Human);
+        let (tests, summary) = output.split_once("\n\n").unwrap();
+        assert_eq!(
+            tests,
+            "tests/a.smoke.yaml::good: passed in 1.50s (flaky, 2 attempts)\n#2: failed in 12ms\nUnexpected exit code 1, expected 0\nstdout: \"\"\nstderr: \"oops\""
+        );
+        let (counts, rest) = summary.split_once(" in ").unwrap();
+        assert_eq!(counts, "2 tests: 1 passed, 1 failed (1 flaky)");
+        assert_eq!(
+            rest.split_once('\n').unwrap().1,
+            "Slowest tests:\n     1.50s  tests/a.smoke.yaml::good\n      12ms  #2\nErrors.\n"
+        );
+    }
+
+    #[test]
+    fn test_human_quiet() {
+        let output = render_with(Format::Human, false, true);
+        assert!(
+            output.starts_with("#2: failed in 12ms\nUnexpected exit code 1"),
+            "{:?}",
+            output
+        );
+        assert!(
+            output.contains("\n2 tests: 1 passed, 1 failed"),
+            "{:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_human_description() {
+        let mut reports = reports();
+        reports[0].description = Some("Passing tests don't show it\n".to_string());
+        reports[1].description = Some("Checks the exit code\n".to_string());
+        let output = render_reports(Format::Human, false, false, reports);
+        assert!(
+            output.contains("#2: failed in 12ms\nDescription: Checks the exit code\nUnexpected"),
+            "{:?}",
+            output
+        );
+        assert!(!output.contains("Passing tests"), "{:?}", output);
+        let mut reports = self::reports();
+        reports[1].description = Some("Checks the exit code".to_string());
+        let output = render_reports(Format::Json, false, false, reports);
+        let document: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(document["tests"][1]["description"], "Checks the exit code");
+    }
+
+    #[test]
+    fn test_human_inconsistent() {
+        let mut reports = reports();
+        reports[1].runs = 4;
+        reports[1].failed_runs = 1;
+        let output = render_reports(Format::Human, false, false, reports);
+        assert!(
+            output.contains("#2: failed in 12ms (inconsistent, failed 1 of 4 runs)\n"),
+            "{:?}",
+            output
+        );
+        assert!(
+            output.ends_with("Inconsistent tests:\n       25%  #2\nErrors.\n"),
+            "{:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_skipped() {
+        let mut reports = reports();
+        reports[1] = TestResult {
+            name: Some("check".to_string()),
+            passed: true,
+            skipped: Some("build failed".to_string()),
+            ..TestResult::default()
+        };
+        let output = render_reports(Format::Human, false, false, reports);
+        assert!(
+            output.contains(
+                "\ncheck: skipped (build failed)\n\n2 tests: 1 passed, 0 failed, 1 skipped (1 flaky) in"
+            ),
+            "{:?}",
+            output
+        );
+        assert!(!output.contains("  0ms  check"), "{:?}", output);
+        let mut reports = self::reports();
+        reports[1].passed = true;
+        reports[1].skipped = Some("build failed".to_string());
+        let output = render_reports(Format::Tap, false, false, reports);
+        assert!(
+            output.ends_with("\nok 2 # SKIP build failed\n"),
+            "{:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_expected_failure() {
+        let mut reports = reports();
+        reports[0].attempts = 1;
+        reports[0].passed = false;
+        reports[0].expected_failure = true;
+        reports[0].failures = vec!["The test passed although it is expected to fail".to_string()];
+        reports[1].passed = true;
+        reports[1].expected_failure = true;
+        let output = render_reports(Format::Human, false, false, reports);
+        assert!(
+            output.starts_with("tests/a.smoke.yaml::good: unexpected pass in 1.50s\nThe test passed although it is expected to fail\n"),
+            "{:?}",
+            output
+        );
+        assert!(output.contains("\n#2: xfail in 12ms\n"), "{:?}", output);
+        assert!(
+            output.contains("\n2 tests: 0 passed, 1 failed, 1 xfail in"),
+            "{:?}",
+            output
+        );
+        let mut reports = self::reports();
+        reports[1].passed = true;
+        reports[1].expected_failure = true;
+        let output = render_reports(Format::Tap, false, false, reports);
+        assert!(
+            output.ends_with("\nnot ok 2 # TODO expected failure\n"),
+            "{:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_human_color() {
+        let output = render_with_color(Format::Human, true);
+        assert!(
+            output.contains("good: \x1b[32mpassed\x1b[0m"),
+            "{:?}",
+            output
+        );
+        assert!(output.contains("#2: \x1b[31mfailed\x1b[0m"), "{:?}", output);
+        assert!(output.ends_with("\x1b[31mErrors.\x1b[0m\n"), "{:?}", output);
+    }
+
+    #[test]
+    fn test_log_line_style() {
+        assert_eq!(log_line_style("--- expected stdout"), Some(BOLD));
+        assert_eq!(log_line_style("@@ -1 +1 @@"), Some(CYAN));
+        assert_eq!(log_line_style("+foo"), Some(GREEN));
+        assert_eq!(log_line_style("-foo"), Some(RED));
+        assert_eq!(log_line_style(" foo"), None);
+    }
+
+    #[test]
+    fn test_tap() {
+        assert_eq!(
+            render(Format::Tap),
+            "TAP version 13\n1..2\nok 1 - tests/a.smoke.yaml::good # time=1500.000ms\n# flaky: passed after 2 attempts\nnot ok 2 # time=12.345ms\n# Unexpected exit code 1, expected 0\n# stdout: \"\"\n# stderr: \"oops\"\n"
+        );
+    }
+
+    #[test]
+    fn test_github() {
+        assert_eq!(
+            render(Format::Github),
+            "::warning file=tests/a.smoke.yaml,line=1,title=tests/a.smoke.yaml%3A%3Agood::Passed after 2 attempts\n::error title=#2::Unexpected exit code 1, expected 0%0Astdout: \"\"%0Astderr: \"oops\"\n1 passed, 1 failed\n"
+        );
+    }
+
+    #[test]
+    fn test_definition_line() {
+        let yaml = "tests:\n  hello:\n    command: [echo, hello]\n  convert:\n    command: x\n";
+        assert_eq!(definition_line(yaml, "hello"), Some(2));
+        assert_eq!(definition_line(yaml, "convert[format=json]"), Some(4));
+        assert_eq!(definition_line(yaml, "missing"), None);
+        let toml = "[tests.hello]\ncommand = \"true\"\n\n[tests.\"other\"]\n";
+        assert_eq!(definition_line(toml, "other"), Some(4));
+        let json = "{\"tests\": {\n  \"hello\": {}\n}}";
+        assert_eq!(definition_line(json, "hello"), Some(2));
+    }
+
+    #[test]
+    fn test_json() {
+        let document: serde_json::Value = serde_json::from_str(&render(Format::Json)).unwrap();
+        assert_eq!(document["passed"], false);
+        assert_eq!(document["tests"][0]["file"], "tests/a.smoke.yaml");
+        assert_eq!(document["tests"][0]["name"], "good");
+        assert_eq!(document["tests"][0]["command"][0], "true");
+        assert_eq!(document["tests"][0]["attempts"], 2);
+        assert_eq!(document["tests"][1]["name"], serde_json::Value::Null);
+        assert_eq!(document["tests"][1]["exit_code"], 1);
+        assert_eq!(document["tests"][1]["stderr"], "oops");
+        assert_eq!(document["tests"][1]["duration"], 0.012345);
+        assert_eq!(
+            document["tests"][1]["failures"][0],
+            "Unexpected exit code 1, expected 0"
+        );
+    }
+}
@@ -0,0 +1,256 @@
+//! Interactive review of the expected output that failed tests didn't
+//! match, for `smokers review`.
+
+use crate::config::Suite;
+use crate::report::TestResult;
+use crate::{diff, runner, update};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+/// A proposed replacement of an expected output with the actual one.
+#[derive(Debug)]
+pub struct Change<'a> {
+    suite: &'a Suite,
+    index: usize,
+    /// `stdout` or `stderr`.
+    pub stream: String,
+    /// The golden file holding the expected output, which is part of the
+    /// configuration file otherwise.
+    pub golden_file: Option<PathBuf>,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Change<'_> {
+    /// The name of the test qualified with the file it is defined in.
+    pub fn test_name(&self) -> String {
+        self.suite
+            .display_name(self.index)
+            .unwrap_or_else(|| format!("#{}", self.index + 1))
+    }
+
+    /// Replaces the expected output with the actual one. Returns the
+    /// updated file or the reason it couldn't be updated.
+    pub fn apply(&self) -> Result<PathBuf, String> {
+        match &self.golden_file {
+            Some(path) => std::fs::write(path, &self.actual)
+                .map(|()| path.clone())
+                .map_err(|e| e.to_string()),
+            None => update::update_expected(self.suite, self.index, &self.stream, &self.actual)
+                .map(Path::to_path_buf),
+        }
+    }
+}
+
+/// The changes proposed by the failed tests among the `reports` of a run
+/// of `suites`.
+pub fn changes<'a>(suites: &'a [Suite], reports: &[TestResult]) -> Vec<Change<'a>> {
+    let mut changes = vec![];
+    for report in reports {
+        if report.passed || report.skipped.is_some() {
+            continue;
+        }
+        let Some((suite, index)) = find_test(suites, report) else {
+            continue;
+        };
+        let config = &suite.tests[index];
+        if config.expected_failure {
+            continue;
+        }
+        for mismatch in &report.mismatched_output {
+            let (actual, inline) = match mismatch.stream.as_str() {
+                "stdout" => (&report.stdout, &config.stdout),
+                _ => (&report.stderr, &config.stderr),
+            };
+            let actual = if config.strip_ansi {
+                runner::strip_ansi(actual).into_owned()
+            } else {
+                actual.clone()
+            };
+            let expected = match &mismatch.golden_file {
+                Some(path) => std::fs::read_to_string(path).unwrap_or_default(),
+                None => inline.clone().unwrap_or_default(),
+            };
+            changes.push(Change {
+                suite,
+                index,
+                stream: mismatch.stream.clone(),
+                golden_file: mismatch.golden_file.clone(),
+                expected,
+                actual,
+            });
+        }
+    }
+    changes
+}
+
+/// Finds the suite and the position of the test that `report` is about.
+fn find_test<'a>(suites: &'a [Suite], report: &TestResult) -> Option<(&'a Suite, usize)> {
+    suites
+        .iter()
+        .filter(|suite| suite.path == report.file)
+        .find_map(|suite| {
+            (0..suite.tests.len())
+                .find(|&index| suite.test_name(index) == report.name)
+                .map(|index| (suite, index))
+        })
+}
+
+/// How the changes of a review were decided on.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub accepted: usize,
+    pub rejected: usize,
+    /// The changes that were left undecided or couldn't be applied.
+    pub skipped: usize,
+}
+
+/// Shows the diff of each of the `changes` on `out` and asks on `input`
+/// whether to accept it, which applies it, to reject it or to skip the
+/// remaining changes.
+pub fn review(
+    changes: &[Change],
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> std::io::Result<Summary> {
+    let mut summary = Summary::default();
+    for (position, change) in changes.iter().enumerate() {
+        let target = change
+            .golden_file
+            .as_ref()
+            .map_or_else(|| change.stream.clone(), |file| file.display().to_string());
+        writeln!(
+            out,
+            "{} ({}/{}): {}",
+            change.test_name(),
+            position + 1,
+            changes.len(),
+            target
+        )?;
+        if let Some(description) = &change.suite.tests[change.index].description {
+            writeln!(out, "{}", description.trim_end())?;
+        }
+        write!(
+            out,
+            "{}",
+            diff::unified_diff(
+                &format!("expected {}", change.stream),
+                &format!("actual {}", change.stream),
+                &change.expected,
+                &change.actual
+            )
+        )?;
+        match prompt(input, out)? {
+            Decision::Accept => match change.apply() {
+                Ok(path) => {
+                    writeln!(out, "Updated {}", path.display())?;
+                    summary.accepted += 1;
+                }
+                Err(reason) => {
+                    writeln!(
+                        out,
+                        "The expected {} could not be updated: {}",
+                        change.stream, reason
+                    )?;
+                    summary.skipped += 1;
+                }
+            },
+            Decision::Reject => summary.rejected += 1,
+            Decision::Skip => {
+                summary.skipped += changes.len() - position;
+                break;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+enum Decision {
+    Accept,
+    Reject,
+    Skip,
+}
+
+/// Asks until a valid answer is given. The end of the input skips the
+/// remaining changes.
+fn prompt(input: &mut impl BufRead, out: &mut impl Write) -> std::io::Result<Decision> {
+    loop {
+        write!(
+            out,
+            "Accept the actual output? [a]ccept, [r]eject, [s]kip the rest: "
+        )?;
+        out.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            writeln!(out)?;
+            return Ok(Decision::Skip);
+        }
+        match answer.trim() {
+            "a" | "accept" => return Ok(Decision::Accept),
+            "r" | "reject" => return Ok(Decision::Reject),
+            "s" | "skip" => return Ok(Decision::Skip),
+            _ => writeln!(out, "Please answer a, r or s.")?,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::{CollectingReporter, HumanReporter};
+    use crate::{run_suites, RunOptions};
+
+    #[test]
+    fn test_review() {
+        let dir = std::env::temp_dir().join(format!("smokers-review-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tests.yaml");
+        let yaml = "\
+tests:
+  inline:
+    command: [echo, new]
+    stdout: \"old\\n\"
+  golden:
+    command: [echo, new]
+    stdout-file: golden.txt
+  rejected:
+    command: [echo, new]
+    stdout: \"old\\n\"
+  skipped:
+    command: [echo, new]
+    stdout: \"old\\n\"
+";
+        std::fs::write(&path, yaml).unwrap();
+        std::fs::write(dir.join("golden.txt"), "old\n").unwrap();
+        let suites = [Suite::from_path(&path).unwrap()];
+        let mut reporter = HumanReporter::new(std::io::sink());
+        let mut reporter = CollectingReporter::new(&mut reporter);
+        let passed = run_suites(&suites, &RunOptions::default(), &mut reporter).unwrap();
+        let changes = changes(&suites, &reporter.reports);
+        let mut out = vec![];
+        let summary = review(&changes, &mut "x\na\naccept\nr\n".as_bytes(), &mut out).unwrap();
+        let updated = std::fs::read_to_string(&path).unwrap();
+        let golden = std::fs::read_to_string(dir.join("golden.txt")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!passed);
+        assert_eq!(changes.len(), 4);
+        assert_eq!(
+            summary,
+            Summary {
+                accepted: 2,
+                rejected: 1,
+                skipped: 1,
+            }
+        );
+        assert_eq!(
+            updated,
+            yaml.replacen("stdout: \"old\\n\"", "stdout: \"new\\n\"", 1)
+        );
+        assert_eq!(golden, "new\n");
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Please answer a, r or s."), "{}", out);
+        assert!(out.contains("-old\n+new\n"), "{}", out);
+        assert!(out.contains("::skipped (4/4): stdout"), "{}", out);
+    }
+}
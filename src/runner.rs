@@ -0,0 +1,4331 @@
+//! Execution of test cases and the checks of their expectations.
+
+use crate::config::{
+    self, CompareMode, Configuration, FixtureFile, OutputExpectation, Runner, Suite,
+};
+use crate::pattern::{self, Comparison};
+use crate::process::{self, Finished, Input};
+use crate::report::{self, OutputMismatch, Reporter, ResourceUsage, TestResult, TextDiff};
+use crate::sandbox::Sandbox;
+use crate::service::Services;
+use crate::signal::Signal;
+use crate::{cache, diff, json, pty, update, wait, Error};
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::Duration;
+
+/// Runs the test case and checks its expectations.
+pub fn run_test(config: &Configuration) -> Result<TestResult, Error> {
+    run_test_with_options(config, &RunOptions::default())
+}
+
+/// Runs the test case and checks its expectations, using `options` for the
+/// settings the test case doesn't configure itself.
+pub fn run_test_with_options(
+    config: &Configuration,
+    options: &RunOptions,
+) -> Result<TestResult, Error> {
+    let sandbox = if config.sandbox {
+        let sandbox = Sandbox::create()?;
+        tracing::debug!(path = %sandbox.path().display(), "created the sandbox");
+        Some(Arc::new(sandbox))
+    } else {
+        None
+    };
+    let dir = match &sandbox {
+        Some(sandbox) => sandbox.path(),
+        None => config.cwd.as_deref().unwrap_or_else(|| Path::new(".")),
+    };
+    let process = |command: &(String, Vec<String>)| {
+        let mut process = config.process(command);
+        process.current_dir(dir);
+        process
+    };
+
+    let mut setup_failures: Vec<String> = create_files(config, dir).into_iter().collect();
+    for command in &config.setup {
+        if !setup_failures.is_empty() {
+            break;
+        }
+        setup_failures.extend(run_fixture("Setup", command, process(command))?);
+    }
+    let mut services = Services::default();
+    if setup_failures.is_empty() {
+        match Services::start(&config.services, process, dir) {
+            Ok(started) => services = started,
+            Err(failures) => setup_failures = failures,
+        }
+    }
+    if setup_failures.is_empty() && !config.wait_for.is_empty() {
+        if let Some(failure) = wait::wait(&config.wait_for, dir) {
+            setup_failures.push(failure);
+            setup_failures.extend(services.logs());
+        }
+    }
+
+    let result = if setup_failures.is_empty() {
+        run_command(config, options, config.command_process(dir), dir).map(|mut report| {
+            if !report.passed {
+                report.failures.extend(services.logs());
+            }
+            report
+        })
+    } else {
+        Ok(TestResult {
+            name: config.name.clone(),
+            description: config.description.clone(),
+            command: command_line(&config.command),
+            failures: setup_failures,
+            attempts: 1,
+            runs: 1,
+            failed_runs: 1,
+            ..TestResult::default()
+        })
+    };
+
+    // Teardown commands run regardless of the outcome of the test, the
+    // services are stopped afterwards.
+    let mut teardown_failures = vec![];
+    for command in &config.teardown {
+        teardown_failures.extend(run_fixture("Teardown", command, process(command))?);
+    }
+    drop(services);
+
+    let mut report = result?;
+    if !teardown_failures.is_empty() {
+        report.passed = false;
+        report.failures.extend(teardown_failures);
+    }
+    if let Some(sandbox) = sandbox {
+        if !report.passed && options.keep_sandbox {
+            let path = sandbox.keep();
+            report
+                .failures
+                .push(format!("The sandbox was kept at {}", path.display()));
+        }
+        if options.artifacts_dir.is_some() {
+            report.sandbox = Some(sandbox);
+        }
+    }
+    if config.expected_failure {
+        report.expected_failure = true;
+        report.passed = !report.passed;
+        if !report.passed {
+            report
+                .failures
+                .push("The test passed although it is expected to fail".to_string());
+        }
+        report.failed_runs = u32::from(!report.passed);
+    }
+    Ok(report)
+}
+
+/// Writes the output, the configuration and the result of a failed test,
+/// the contents of its sandbox and a script reproducing it to `dir`,
+/// replacing the artifacts of earlier runs of smokers. The artifacts are
+/// removed if the test passed.
+fn write_artifacts(dir: &Path, config: &Configuration, report: &TestResult) -> Result<(), Error> {
+    match std::fs::remove_dir_all(dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    if report.passed {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join("stdout"), &report.stdout)?;
+    std::fs::write(dir.join("stderr"), &report.stderr)?;
+    std::fs::write(dir.join("config.txt"), format!("{:#?}\n", config))?;
+    std::fs::write(dir.join("result.json"), serde_json::to_vec_pretty(report)?)?;
+    let cwd = match &report.sandbox {
+        Some(sandbox) => {
+            copy_dir(sandbox.path(), &dir.join("sandbox"))?;
+            std::fs::canonicalize(dir.join("sandbox"))?
+        }
+        None => config::absolute(config.cwd.as_deref().unwrap_or_else(|| Path::new("."))),
+    };
+    if let Some(stdin) = &config.stdin {
+        std::fs::write(dir.join("stdin"), stdin)?;
+    }
+    let repro = dir.join("repro.sh");
+    std::fs::write(&repro, repro_script(config, &cwd))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&repro, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+/// A shell script that runs the command of the test in `cwd` with its
+/// environment and stdin. A copied sandbox is used as is, with the state
+/// the test left it in.
+fn repro_script(config: &Configuration, cwd: &Path) -> String {
+    let process = config.command_process(cwd);
+    let mut script = String::from("#!/bin/sh\n");
+    let test = config
+        .name
+        .clone()
+        .unwrap_or_else(|| shell_words(&config.command));
+    script.push_str(&format!("# Reproduces the test {}.\n", test));
+    script.push_str(&format!(
+        "cd {} || exit\n",
+        shell_quote(&cwd.to_string_lossy())
+    ));
+
+    let mut words = vec![];
+    if config.runner == Runner::Local {
+        if config.clean_env {
+            words.push("env -i".to_string());
+            // The variables are passed from the environment of the script.
+            for name in &config.pass_env {
+                words.push(format!("${{{0}+\"{0}=${0}\"}}", name));
+            }
+        } else if !config.env.is_empty() {
+            words.push("env".to_string());
+        }
+        for (name, value) in &config.env {
+            words.push(shell_quote(&format!("{}={}", name, value)));
+        }
+    }
+    words.push(shell_quote(&process.get_program().to_string_lossy()));
+    words.extend(
+        process
+            .get_args()
+            .map(|arg| shell_quote(&arg.to_string_lossy())),
+    );
+    match (&config.stdin, &config.stdin_file) {
+        (Some(_), _) => words.push("< \"$(dirname \"$0\")/stdin\"".to_string()),
+        (None, Some(path)) => words.push(format!(
+            "< {}",
+            shell_quote(&config::absolute(path).to_string_lossy())
+        )),
+        (None, None) => words.push("< /dev/null".to_string()),
+    }
+    script.push_str(&format!("exec {}\n", words.join(" ")));
+    script
+}
+
+/// Copies the directory `from` with all its contents to `to`.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates the fixture files of the test case in `dir`. Returns the reason
+/// if a file could not be created.
+fn create_files(config: &Configuration, dir: &Path) -> Option<String> {
+    for (path, file) in &config.files {
+        let path = dir.join(path);
+        let created = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| match file {
+                FixtureFile::Content(content) => std::fs::write(&path, content),
+                FixtureFile::Source { source } => std::fs::copy(source, &path).map(drop),
+            });
+        if let Err(e) = created {
+            return Some(format!(
+                "File {} could not be created: {}",
+                path.display(),
+                e
+            ));
+        }
+    }
+    None
+}
+
+/// Runs a setup or teardown command prepared as `process`. Returns the
+/// reason if the command failed.
+fn run_fixture(
+    kind: &str,
+    command: &(String, Vec<String>),
+    mut process: std::process::Command,
+) -> Result<Option<String>, Error> {
+    tracing::debug!(command = ?command_line(command), "running the {} command", kind.to_lowercase());
+    process
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    let output = match process::spawn(&mut process).and_then(|child| child.wait_with_output()) {
+        Ok(output) => output,
+        Err(e) => {
+            return Ok(Some(format!(
+                "{} command {:?} could not be started: {}",
+                kind,
+                command_line(command),
+                e
+            )))
+        }
+    };
+
+    if output.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "{} command {:?} failed ({}): {:?}",
+        kind,
+        command_line(command),
+        output.status,
+        String::from_utf8_lossy(&output.stderr)
+    )))
+}
+
+fn command_line(command: &(String, Vec<String>)) -> Vec<String> {
+    std::iter::once(&command.0)
+        .chain(&command.1)
+        .cloned()
+        .collect()
+}
+
+/// Runs the command of the test case, prepared as `command` to run in
+/// `dir`, and checks its expectations.
+fn run_command(
+    config: &Configuration,
+    options: &RunOptions,
+    mut command: std::process::Command,
+    dir: &Path,
+) -> Result<TestResult, Error> {
+    let mut log_file = vec![];
+    let start = std::time::Instant::now();
+    let timeout = config.timeout.or(options.timeout);
+    let max_output = config.max_output_bytes;
+    let echo_prefix = options.verbose.then(|| {
+        let name = config.name.clone();
+        format!("{}: ", name.unwrap_or_else(|| shell_words(&config.command)))
+    });
+    let process_options = process::Options {
+        input: match (&config.stdin, &config.stdin_file) {
+            (Some(text), _) => Some(Input::Bytes(text.as_bytes())),
+            (None, Some(path)) => Some(Input::File(path)),
+            (None, None) => None,
+        },
+        timeout,
+        timeout_signal: config.timeout_signal,
+        max_output,
+        echo: echo_prefix.as_deref(),
+        merge_output: config.merge_output,
+    };
+    process::set_limits(&mut command, config.limits)?;
+    process::isolate(&mut command, config.isolation)?;
+    process::set_user(
+        &mut command,
+        config.user.as_deref(),
+        config.group.as_deref(),
+    )?;
+    process::set_umask(&mut command, config.umask)?;
+    process::set_priority(&mut command, config.nice, config.ionice)?;
+    let finished = if config.pty {
+        pty::output(command, &process_options)
+    } else {
+        process::run_piped(command, &process_options)
+    };
+    let finished = match finished {
+        Ok(finished) => finished,
+        Err(e) => {
+            return Ok(TestResult {
+                name: config.name.clone(),
+                description: config.description.clone(),
+                command: command_line(&config.command),
+                failures: vec![format!(
+                    "The command {:?} could not be started: {}",
+                    command_line(&config.command),
+                    e
+                )],
+                attempts: 1,
+                runs: 1,
+                failed_runs: 1,
+                ..TestResult::default()
+            })
+        }
+    };
+    let Finished {
+        output,
+        timed_out,
+        stdout_truncated,
+        stderr_truncated,
+        usage,
+    } = finished;
+    let duration = start.elapsed();
+    let output_stdout = String::from_utf8_lossy(&output.stdout);
+    let output_stderr = String::from_utf8_lossy(&output.stderr);
+    let output_status_code = output.status.code();
+    let output_signal = Signal::from_status(&output.status);
+
+    let exit_code_failed = match (output_status_code, output_signal, config.expected_signal) {
+        _ if timed_out => {
+            match config.timeout_signal {
+                Some(signal) => writeln!(
+                    &mut log_file,
+                    "The process timed out after {:?} and was sent {}",
+                    timeout.unwrap_or_default(),
+                    signal
+                )?,
+                None => writeln!(
+                    &mut log_file,
+                    "The process timed out after {:?} and was killed",
+                    timeout.unwrap_or_default()
+                )?,
+            }
+            true
+        }
+        (_, Some(signal), Some(expected_signal)) if signal == expected_signal => false,
+        (_, Some(signal), Some(expected_signal)) => {
+            writeln!(
+                &mut log_file,
+                "The process died due to {}. Expected it to be killed by {}",
+                signal, expected_signal
+            )?;
+            true
+        }
+        (Some(exit_code), _, Some(expected_signal)) => {
+            writeln!(
+                &mut log_file,
+                "The process exited with status code {}. Expected it to be killed by {}",
+                describe_exit_code(exit_code),
+                expected_signal
+            )?;
+            true
+        }
+        (Some(exit_code), _, None) if !config.exit_code.matches(exit_code) => {
+            writeln!(
+                &mut log_file,
+                "Unexpected exit code {}, expected {}",
+                describe_exit_code(exit_code),
+                config.exit_code
+            )?;
+            true
+        }
+        (Some(_), _, None) => false,
+        (None, signal, _) => {
+            let signal = signal.map_or_else(|| "a signal".to_string(), |s| s.to_string());
+            writeln!(
+                &mut log_file,
+                "The process died due to {}. Expected it to exit with status code {}",
+                signal, config.exit_code
+            )?;
+            true
+        }
+    };
+
+    let duration_failed = match (config.min_duration, config.max_duration) {
+        // A killed process took as long as the timeout allowed.
+        _ if timed_out => false,
+        (_, Some(max)) if duration > max => {
+            writeln!(
+                &mut log_file,
+                "The process took {:?}, longer than the maximum of {:?}",
+                duration, max
+            )?;
+            true
+        }
+        (Some(min), _) if duration < min => {
+            writeln!(
+                &mut log_file,
+                "The process took {:?}, shorter than the minimum of {:?}",
+                duration, min
+            )?;
+            true
+        }
+        _ => false,
+    };
+
+    let usage_failed = !timed_out && !check_usage(&mut log_file, config, usage)?;
+
+    let mut truncated = false;
+    for (name, cut) in [("stdout", stdout_truncated), ("stderr", stderr_truncated)] {
+        if cut {
+            writeln!(
+                &mut log_file,
+                "{} exceeded the limit of {} bytes and was truncated",
+                name,
+                max_output.unwrap_or_default()
+            )?;
+            truncated = true;
+        }
+    }
+
+    let trim_trailing_newline = config
+        .trim_trailing_newline
+        .unwrap_or(options.normalize_newlines);
+    let diffs = RefCell::new(vec![]);
+    let diffs = options.diff_tool.is_some().then_some(&diffs);
+    // The output of a killed process is incomplete, comparing it would only
+    // add noise to the report.
+    let expectation = |base| OutputExpectation {
+        trim_trailing_newline,
+        newline_mode: config.newline_mode,
+        tolerance: config.tolerance,
+        compare: config.compare,
+        case_insensitive: config.case_insensitive,
+        strip_ansi: config.strip_ansi,
+        diffs,
+        ..base
+    };
+    let stdout_expectation = expectation(config.stdout_expectation());
+    let stderr_expectation = expectation(config.stderr_expectation());
+    let stdout_failed =
+        !timed_out && !check_output(&mut log_file, "stdout", &output.stdout, &stdout_expectation)?;
+    let stderr_failed =
+        !timed_out && !check_output(&mut log_file, "stderr", &output.stderr, &stderr_expectation)?;
+    // Only the exact expectations and golden files can be updated with the
+    // actual output.
+    let mut mismatched_output = vec![];
+    for (name, failed, actual, expected) in [
+        ("stdout", stdout_failed, &output.stdout, &stdout_expectation),
+        ("stderr", stderr_failed, &output.stderr, &stderr_expectation),
+    ] {
+        if !failed {
+            continue;
+        }
+        let exact_only = OutputExpectation {
+            file: None,
+            ..only_text(expected)
+        };
+        if expected.exact.is_some()
+            && !check_output(&mut std::io::sink(), name, actual, &exact_only)?
+        {
+            mismatched_output.push(OutputMismatch {
+                stream: name.to_string(),
+                golden_file: None,
+            });
+        }
+        let file_only = OutputExpectation {
+            exact: None,
+            ..only_text(expected)
+        };
+        if let Some(file) = expected.file {
+            if !check_output(&mut std::io::sink(), name, actual, &file_only)? {
+                mismatched_output.push(OutputMismatch {
+                    stream: name.to_string(),
+                    golden_file: Some(file.to_path_buf()),
+                });
+            }
+        }
+    }
+
+    let validator_failed = match &config.validate_stdout {
+        Some(validator) if !timed_out => !validate_stdout(
+            &mut log_file,
+            config,
+            validator,
+            &output.stdout,
+            dir,
+            timeout,
+        )?,
+        _ => false,
+    };
+
+    let files_failed = !timed_out && !check_files(&mut log_file, config, diffs, dir)?;
+
+    let failed = stdout_failed
+        | stderr_failed
+        | validator_failed
+        | exit_code_failed
+        | files_failed
+        | truncated
+        | duration_failed
+        | usage_failed;
+    Ok(TestResult {
+        file: None,
+        name: config.name.clone(),
+        description: config.description.clone(),
+        command: command_line(&config.command),
+        passed: !failed,
+        skipped: None,
+        expected_failure: false,
+        cached: false,
+        exit_code: output_status_code,
+        signal: output_signal.map(|s| s.0),
+        stdout: output_stdout.into_owned(),
+        stderr: output_stderr.into_owned(),
+        duration,
+        failures: String::from_utf8_lossy(&log_file)
+            .lines()
+            .map(String::from)
+            .collect(),
+        attempts: 1,
+        runs: 1,
+        failed_runs: u32::from(failed),
+        resource_usage: usage,
+        mismatched_output,
+        diffs: diffs.map_or_else(Vec::new, |diffs| diffs.take()),
+        sandbox: None,
+    })
+}
+
+/// Checks the resources the process used against the `max-memory` and
+/// `max-cpu-time` of the test, logging the limits it exceeded.
+fn check_usage(
+    log_file: &mut impl Write,
+    config: &Configuration,
+    usage: Option<ResourceUsage>,
+) -> std::io::Result<bool> {
+    if config.max_memory.is_none() && config.max_cpu_time.is_none() {
+        return Ok(true);
+    }
+    let Some(usage) = usage else {
+        writeln!(
+            log_file,
+            "The resource usage of the process can't be measured on this platform"
+        )?;
+        return Ok(false);
+    };
+    let mut passed = true;
+    if let Some(max) = config.max_memory.filter(|&max| usage.max_rss > max) {
+        writeln!(
+            log_file,
+            "The process used {} of memory, more than the maximum of {}",
+            report::format_size(usage.max_rss),
+            report::format_size(max)
+        )?;
+        passed = false;
+    }
+    if let Some(max) = config.max_cpu_time.filter(|&max| usage.cpu_time() > max) {
+        writeln!(
+            log_file,
+            "The process used {:?} of CPU time, more than the maximum of {:?}",
+            usage.cpu_time(),
+            max
+        )?;
+        passed = false;
+    }
+    Ok(passed)
+}
+
+/// The `expected` output without the expectations besides the exact text
+/// and the golden file.
+fn only_text<'a>(expected: &OutputExpectation<'a>) -> OutputExpectation<'a> {
+    OutputExpectation {
+        lines: None,
+        line_count: None,
+        empty: false,
+        line_assertions: &[],
+        json: None,
+        json_assertions: &[],
+        contains: &[],
+        not_contains: &[],
+        regex: None,
+        bytes: vec![],
+        diffs: None,
+        ..*expected
+    }
+}
+
+/// Runs the `validator` command of the test case in `dir` with `stdout` on
+/// its stdin. Returns whether it succeeded, its output is written to the
+/// `log_file` otherwise.
+fn validate_stdout(
+    log_file: &mut impl std::io::Write,
+    config: &Configuration,
+    validator: &(String, Vec<String>),
+    stdout: &[u8],
+    dir: &Path,
+    timeout: Option<Duration>,
+) -> Result<bool, Error> {
+    let mut process = config.process(validator);
+    process.current_dir(dir);
+    let options = process::Options {
+        input: Some(Input::Bytes(stdout)),
+        timeout,
+        ..process::Options::default()
+    };
+    let finished = match process::run_piped(process, &options) {
+        Ok(finished) => finished,
+        Err(e) => {
+            writeln!(
+                log_file,
+                "The stdout validator {:?} could not be started: {}",
+                command_line(validator),
+                e
+            )?;
+            return Ok(false);
+        }
+    };
+    if finished.timed_out {
+        writeln!(
+            log_file,
+            "The stdout validator {:?} timed out after {:?}",
+            command_line(validator),
+            timeout.unwrap_or_default()
+        )?;
+        return Ok(false);
+    }
+    if finished.output.status.success() {
+        return Ok(true);
+    }
+    writeln!(
+        log_file,
+        "The stdout validator {:?} failed ({}):",
+        command_line(validator),
+        finished.output.status
+    )?;
+    for output in [&finished.output.stdout, &finished.output.stderr] {
+        log_file.write_all(String::from_utf8_lossy(output).trim_end().as_bytes())?;
+        if !output.is_empty() {
+            writeln!(log_file)?;
+        }
+    }
+    Ok(false)
+}
+
+/// Formats an exit code for messages. Windows reports crashes as negative
+/// NTSTATUS codes, which are only recognizable in hex.
+fn describe_exit_code(code: i32) -> String {
+    if cfg!(windows) && code < 0 {
+        format!("{} ({:#010X})", code, code as u32)
+    } else {
+        code.to_string()
+    }
+}
+
+/// Runs the test case until it passes, retrying it as often as configured
+/// in the test case or `options`. The report of the last attempt is
+/// returned.
+pub fn run_test_with_retries(
+    config: &Configuration,
+    options: &RunOptions,
+) -> Result<TestResult, Error> {
+    let retries = config.retries.unwrap_or(options.retries);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let mut report = run_test_with_options(config, options)?;
+        report.attempts = attempts;
+        if report.passed || attempts > retries {
+            return Ok(report);
+        }
+    }
+}
+
+/// Runs the test case `options.repeat` times, with retries, to detect
+/// inconsistent results. The report of the first failed run is returned, or
+/// of the last run if all of them passed.
+pub fn run_test_repeated(
+    config: &Configuration,
+    options: &RunOptions,
+) -> Result<TestResult, Error> {
+    let runs = options.repeat.max(1);
+    let mut failed = None;
+    let mut failed_runs = 0;
+    let mut last = None;
+    for _ in 0..runs {
+        let report = run_test_with_retries(config, options)?;
+        if !report.passed {
+            failed_runs += 1;
+            failed.get_or_insert(report);
+        } else {
+            last = Some(report);
+        }
+    }
+    let mut report = failed.or(last).expect("the test ran at least once");
+    report.runs = runs;
+    report.failed_runs = failed_runs;
+    if report.is_inconsistent() {
+        report
+            .failures
+            .insert(0, format!("Failed in {} of {} runs", failed_runs, runs));
+    }
+    Ok(report)
+}
+
+/// Options that control how a suite is executed.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    /// The maximum number of tests executed concurrently.
+    pub jobs: usize,
+    /// Only run tests that have at least one of these tags.
+    pub tags: Vec<String>,
+    /// Don't run tests that have any of these tags.
+    pub skip_tags: Vec<String>,
+    /// Don't start any further tests after the first failure.
+    pub fail_fast: bool,
+    /// How often failing tests are retried unless configured otherwise.
+    pub retries: u32,
+    /// Ignore trailing newlines when comparing output, unless configured
+    /// otherwise.
+    pub normalize_newlines: bool,
+    /// Kill tests running longer than this unless configured otherwise.
+    pub timeout: Option<Duration>,
+    /// Retain the sandboxes of failed tests for inspection.
+    pub keep_sandbox: bool,
+    /// Copy the output of tests to stderr while they run.
+    pub verbose: bool,
+    /// How often each test is run to detect inconsistent results.
+    pub repeat: u32,
+    /// Run the tests in a random order determined by this seed.
+    pub shuffle: Option<u64>,
+    /// A command that [`run_suites`] runs with the expected and actual text
+    /// of every mismatch of a failed test, like `delta` or `meld`.
+    pub diff_tool: Option<String>,
+    /// Where [`run_suites`] writes the output, configuration, result and
+    /// sandbox of failed tests, in a subdirectory per test.
+    pub artifacts_dir: Option<PathBuf>,
+    /// Replace the expected stdout and stderr of failed tests in their
+    /// configuration files with the actual output.
+    pub update_expected: bool,
+    /// Where the tests that passed are recorded with the hash of their
+    /// inputs, to not run them again while the inputs stay the same.
+    pub cache_dir: Option<PathBuf>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            jobs: 1,
+            tags: vec![],
+            skip_tags: vec![],
+            fail_fast: false,
+            retries: 0,
+            normalize_newlines: false,
+            timeout: None,
+            keep_sandbox: false,
+            verbose: false,
+            repeat: 1,
+            shuffle: None,
+            diff_tool: None,
+            artifacts_dir: None,
+            update_expected: false,
+            cache_dir: None,
+        }
+    }
+}
+
+impl RunOptions {
+    /// Whether the test case is selected by the tag filters.
+    pub fn is_selected(&self, config: &Configuration) -> bool {
+        self.exclusion_reason(config).is_none()
+    }
+
+    /// Why the tag filters don't select the test case, if they don't.
+    pub fn exclusion_reason(&self, config: &Configuration) -> Option<String> {
+        if !self.tags.is_empty() && !config.tags.iter().any(|t| self.tags.contains(t)) {
+            return Some(format!("not tagged {}", self.tags.join(" or ")));
+        }
+        config
+            .tags
+            .iter()
+            .find(|t| self.skip_tags.contains(t))
+            .map(|t| format!("tagged {}, which is skipped", t))
+    }
+}
+
+/// Keeps track of the before-all and after-all commands and the services
+/// of a suite while its tests are running.
+struct SuiteFixtures<'a> {
+    suite: &'a Suite,
+    /// The failures of the before-all commands or services, once they have
+    /// been run.
+    before_all: Mutex<Option<Vec<String>>>,
+    /// The services of the suite while they are running.
+    services: Mutex<Services>,
+    /// The number of selected tests of the suite that haven't finished.
+    remaining: AtomicUsize,
+}
+
+impl<'a> SuiteFixtures<'a> {
+    fn new(suite: &'a Suite, selected: usize) -> Self {
+        Self {
+            suite,
+            before_all: Mutex::new(None),
+            services: Mutex::new(Services::default()),
+            remaining: AtomicUsize::new(selected),
+        }
+    }
+
+    /// Runs the before-all commands and starts the services unless that
+    /// already happened. Returns the failures if that didn't succeed.
+    fn before_all(&self) -> Result<Vec<String>, Error> {
+        let mut outcome = self.before_all.lock().expect("poisoned fixture lock");
+        if outcome.is_none() {
+            let mut failures = vec![];
+            for command in &self.suite.before_all {
+                failures.extend(run_fixture(
+                    "before-all",
+                    command,
+                    self.suite.process(command),
+                )?);
+                if !failures.is_empty() {
+                    break;
+                }
+            }
+            if failures.is_empty() {
+                let process = |command: &(String, Vec<String>)| self.suite.process(command);
+                match Services::start(&self.suite.services, process, Path::new(".")) {
+                    Ok(services) => {
+                        *self.services.lock().expect("poisoned fixture lock") = services
+                    }
+                    Err(service_failures) => failures = service_failures,
+                }
+            }
+            *outcome = Some(failures);
+        }
+        Ok(outcome.clone().unwrap_or_default())
+    }
+
+    /// The ends of the logs of the running services.
+    fn service_logs(&self) -> Vec<String> {
+        self.services.lock().expect("poisoned fixture lock").logs()
+    }
+
+    /// Marks one test as finished. Once all selected tests have finished
+    /// the after-all commands are run and their failures are returned.
+    fn test_finished(&self) -> Result<Vec<String>, Error> {
+        if self.remaining.fetch_sub(1, Ordering::SeqCst) == 1 {
+            return self.after_all();
+        }
+        Ok(vec![])
+    }
+
+    /// Stops the services and runs the after-all commands if the
+    /// before-all commands have been run and the after-all commands
+    /// haven't.
+    fn after_all(&self) -> Result<Vec<String>, Error> {
+        let mut outcome = self.before_all.lock().expect("poisoned fixture lock");
+        if outcome.take().is_none() {
+            return Ok(vec![]);
+        }
+        drop(std::mem::take(
+            &mut *self.services.lock().expect("poisoned fixture lock"),
+        ));
+        let mut failures = vec![];
+        for command in &self.suite.after_all {
+            failures.extend(run_fixture(
+                "after-all",
+                command,
+                self.suite.process(command),
+            )?);
+        }
+        Ok(failures)
+    }
+}
+
+/// The outcomes of the tests of a run, for tests waiting on their
+/// prerequisites.
+struct Outcomes {
+    /// Whether each test passed, once it finished.
+    passed: Mutex<Vec<Option<bool>>>,
+    changed: Condvar,
+}
+
+impl Outcomes {
+    fn new(tests: usize) -> Self {
+        Self {
+            passed: Mutex::new(vec![None; tests]),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn set(&self, test: usize, passed: bool) {
+        self.passed.lock().expect("poisoned outcome lock")[test] = Some(passed);
+        self.changed.notify_all();
+    }
+
+    /// Waits until all of `tests` passed or one of them failed, returning the
+    /// failed test. Returns `None` if the run was aborted in the meantime.
+    fn wait(&self, tests: &[usize], abort: &AtomicBool) -> Option<Option<usize>> {
+        let mut passed = self.passed.lock().expect("poisoned outcome lock");
+        loop {
+            if abort.load(Ordering::SeqCst) {
+                return None;
+            }
+            if let Some(&failed) = tests.iter().find(|&&t| passed[t] == Some(false)) {
+                return Some(Some(failed));
+            }
+            if tests.iter().all(|&t| passed[t].is_some()) {
+                return Some(None);
+            }
+            // Aborting doesn't notify, check for it regularly.
+            passed = self
+                .changed
+                .wait_timeout(passed, Duration::from_millis(100))
+                .expect("poisoned outcome lock")
+                .0;
+        }
+    }
+}
+
+/// Orders `tests` so that every test comes after its prerequisites,
+/// otherwise keeping their order.
+fn order_by_dependencies(suites: &[Suite], mut tests: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let mut ordered = Vec::with_capacity(tests.len());
+    while !tests.is_empty() {
+        let next = tests
+            .iter()
+            .position(|&(s, index)| {
+                suites[s]
+                    .dependencies(index)
+                    .iter()
+                    .all(|&d| !tests.contains(&(s, d)))
+            })
+            .expect("dependency cycles are rejected when loading the suite");
+        ordered.push(tests.remove(next));
+    }
+    ordered
+}
+
+/// Shuffles `items` with a permutation that only depends on `seed`, using
+/// the SplitMix64 generator. The quality is good enough to uncover
+/// dependencies between tests and doesn't need a dependency.
+fn shuffle<T>(items: &mut [T], mut seed: u64) {
+    let mut next = || {
+        seed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = seed;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    };
+    for i in (1..items.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        items.swap(i, j);
+    }
+}
+
+/// Runs all test cases of the suites, reporting the result of each test
+/// case separately. Returns `true` if all of them succeeded.
+///
+/// Up to `options.jobs` tests run concurrently, the results are still
+/// reported in the order the tests are defined in, or the shuffled order
+/// with `options.shuffle`. Tests are moved behind their prerequisites and
+/// skipped if one of those failed.
+pub fn run_suites(
+    suites: &[Suite],
+    options: &RunOptions,
+    reporter: &mut dyn Reporter,
+) -> Result<bool, Error> {
+    let mut tests: Vec<(usize, usize)> = suites
+        .iter()
+        .enumerate()
+        .flat_map(|(s, suite)| (0..suite.tests.len()).map(move |i| (s, i)))
+        .filter(|(s, i)| options.is_selected(&suites[*s].tests[*i]))
+        .collect();
+    // The prerequisites of the selected tests run even if the filters don't
+    // select them.
+    let mut checked = 0;
+    while checked < tests.len() {
+        let (s, index) = tests[checked];
+        for dependency in suites[s].dependencies(index) {
+            if !tests.contains(&(s, dependency)) {
+                tests.push((s, dependency));
+            }
+        }
+        checked += 1;
+    }
+    tests.sort_unstable();
+    if let Some(seed) = options.shuffle {
+        shuffle(&mut tests, seed);
+    }
+    let tests = order_by_dependencies(suites, tests);
+    // The positions of the prerequisites of each test in `tests`.
+    let dependencies: Vec<Vec<usize>> = tests
+        .iter()
+        .map(|&(s, index)| {
+            suites[s]
+                .dependencies(index)
+                .into_iter()
+                .filter_map(|d| tests.iter().position(|&t| t == (s, d)))
+                .collect()
+        })
+        .collect();
+    let fixtures: Vec<SuiteFixtures> = suites
+        .iter()
+        .enumerate()
+        .map(|(s, suite)| SuiteFixtures::new(suite, tests.iter().filter(|t| t.0 == s).count()))
+        .collect();
+    reporter.start(tests.len())?;
+
+    let next = AtomicUsize::new(0);
+    let abort = AtomicBool::new(false);
+    let outcomes = Outcomes::new(tests.len());
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let mut reports = vec![];
+
+    std::thread::scope(|scope| -> Result<(), Error> {
+        for _ in 0..options.jobs.clamp(1, tests.len().max(1)) {
+            let sender = sender.clone();
+            let (tests, fixtures, next, abort) = (&tests, &fixtures, &next, &abort);
+            let (dependencies, outcomes) = (&dependencies, &outcomes);
+            scope.spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= tests.len() || abort.load(Ordering::SeqCst) {
+                    break;
+                }
+                let (s, index) = tests[i];
+                let Some(failed_dependency) = outcomes.wait(&dependencies[i], abort) else {
+                    break;
+                };
+                let result = match failed_dependency {
+                    Some(d) => {
+                        let (_, dependency) = tests[d];
+                        let name = suites[s].test_name(dependency).unwrap_or_default();
+                        let reason = format!("prerequisite {} didn't pass", name);
+                        skip_suite_test(&fixtures[s], index, reason)
+                    }
+                    None => {
+                        if sender.send((i, None)).is_err() {
+                            break;
+                        }
+                        run_suite_test(&fixtures[s], index, options)
+                    }
+                };
+                outcomes.set(
+                    i,
+                    result
+                        .as_ref()
+                        .is_ok_and(|r| r.passed && r.skipped.is_none()),
+                );
+                // Abort before picking up the next test, the receiving end
+                // may only see the failure after that.
+                if options.fail_fast && result.as_ref().map_or(true, |r| !r.passed) {
+                    abort.store(true, Ordering::SeqCst);
+                }
+                if sender.send((i, Some(result))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let test_name = |i: usize| {
+            let (s, index) = tests[i];
+            let name = suites[s].display_name(index);
+            name.unwrap_or_else(|| format!("#{}", index + 1))
+        };
+        // Results arrive in completion order, buffer them until all
+        // preceding tests have been reported.
+        let mut pending = BTreeMap::new();
+        // A message without a result announces the start of a test.
+        for (i, result) in receiver {
+            match result {
+                None => {
+                    reporter.test_started(i, &test_name(i))?;
+                    continue;
+                }
+                Some(Ok(report)) => pending.insert(i, report),
+                Some(Err(e)) => {
+                    abort.store(true, Ordering::SeqCst);
+                    return Err(Error::Test(test_name(i), Box::new(e)));
+                }
+            };
+            while let Some(report) = pending.remove(&reports.len()) {
+                reporter.test_finished(reports.len(), &report)?;
+                reports.push(report);
+            }
+        }
+        Ok(())
+    })?;
+
+    // Tests that never started after an abort don't trigger the after-all
+    // commands, make sure they still run.
+    for fixture in &fixtures {
+        for failure in fixture.after_all()? {
+            tracing::error!("{}", failure);
+        }
+    }
+
+    reporter.finish(&reports)?;
+    Ok(reports.iter().all(|r| r.passed))
+}
+
+/// Writes the names of the selected tests to `out`, one per line.
+pub fn list_tests(
+    suites: &[Suite],
+    options: &RunOptions,
+    out: &mut impl Write,
+) -> std::io::Result<()> {
+    for suite in suites {
+        for (index, config) in suite.tests.iter().enumerate() {
+            if options.is_selected(config) {
+                let name = suite.display_name(index);
+                writeln!(out, "{}", name.unwrap_or_else(|| format!("#{}", index + 1)))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the commands that would be executed for the selected tests,
+/// together with their working directory and environment, to `out`.
+pub fn dry_run(
+    suites: &[Suite],
+    options: &RunOptions,
+    out: &mut impl Write,
+) -> std::io::Result<()> {
+    let current_dir = std::env::current_dir()?;
+    for suite in suites {
+        let selected: Vec<usize> = (0..suite.tests.len())
+            .filter(|&index| options.is_selected(&suite.tests[index]))
+            .collect();
+        if selected.is_empty() {
+            continue;
+        }
+        let file = suite
+            .path
+            .as_ref()
+            .map_or_else(String::new, |path| format!("{}: ", path.display()));
+        for command in &suite.before_all {
+            writeln!(out, "{}before-all: {}", file, shell_words(command))?;
+        }
+        for (name, service) in &suite.services {
+            writeln!(
+                out,
+                "{}service {}: {}",
+                file,
+                name,
+                shell_words(&service.command)
+            )?;
+        }
+        for index in selected {
+            let config = &suite.tests[index];
+            let name = suite.display_name(index);
+            writeln!(out, "{}", name.unwrap_or_else(|| format!("#{}", index + 1)))?;
+            if config.sandbox {
+                writeln!(out, "  cwd: (sandbox)")?;
+            } else {
+                let cwd = config.cwd.as_ref().map_or(&current_dir, |cwd| cwd);
+                writeln!(out, "  cwd: {}", cwd.display())?;
+            }
+            if config.clean_env {
+                writeln!(out, "  clean-env: passing {}", config.pass_env.join(" "))?;
+            }
+            for (key, value) in &config.env {
+                writeln!(out, "  env: {}={}", key, shell_quote(value))?;
+            }
+            for command in &suite.before_each {
+                writeln!(out, "  before-each: {}", shell_words(command))?;
+            }
+            for command in &config.setup {
+                writeln!(out, "  setup: {}", shell_words(command))?;
+            }
+            for (name, service) in &config.services {
+                writeln!(out, "  service {}: {}", name, shell_words(&service.command))?;
+            }
+            match (config.runner, &config.container, &config.ssh) {
+                (Runner::Container, Some(container), _) => {
+                    writeln!(out, "  container: {} {}", container.engine, container.image)?
+                }
+                (Runner::Ssh, _, Some(ssh)) => writeln!(out, "  ssh: {}", ssh.host)?,
+                _ => {}
+            }
+            match &config.nix {
+                Some(nix) => writeln!(
+                    out,
+                    "  command: {}",
+                    shell_words(&("nix".to_string(), nix.args(&config.command)))
+                )?,
+                None => writeln!(out, "  command: {}", shell_words(&config.command))?,
+            }
+            for command in &config.teardown {
+                writeln!(out, "  teardown: {}", shell_words(command))?;
+            }
+            for command in &suite.after_each {
+                writeln!(out, "  after-each: {}", shell_words(command))?;
+            }
+        }
+        for command in &suite.after_all {
+            writeln!(out, "{}after-all: {}", file, shell_words(command))?;
+        }
+    }
+    Ok(())
+}
+
+/// Formats the command as a line that can be pasted into a shell.
+pub(crate) fn shell_words(command: &(String, Vec<String>)) -> String {
+    command_line(command)
+        .iter()
+        .map(|word| shell_quote(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes `word` for a POSIX shell unless it only consists of characters
+/// that have no special meaning.
+pub(crate) fn shell_quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Runs a single test of a suite, including the file-level fixtures.
+fn run_suite_test(
+    fixtures: &SuiteFixtures,
+    index: usize,
+    options: &RunOptions,
+) -> Result<TestResult, Error> {
+    let suite = fixtures.suite;
+    let config = &suite.tests[index];
+    let name = suite
+        .display_name(index)
+        .unwrap_or_else(|| format!("#{}", index + 1));
+    let _span = tracing::info_span!("test", %name).entered();
+    if let Some(reason) = config.skip_reason() {
+        tracing::info!(%reason, "skipped");
+        return skip_suite_test(fixtures, index, reason);
+    }
+    let cache_key = options
+        .cache_dir
+        .as_ref()
+        .and_then(|dir| Some((dir, cache::key(suite, index, options)?)));
+    if let Some((dir, key)) = &cache_key {
+        if cache::is_cached(dir, key) {
+            tracing::info!(%key, "passed before with the same inputs");
+            return cached_suite_test(fixtures, index);
+        }
+    }
+    tracing::info!(command = ?command_line(&config.command), "running");
+    let before_all_failures = fixtures.before_all()?;
+    let result = if before_all_failures.is_empty() {
+        run_test_with_hooks(suite, config, options).map(|mut report| {
+            if !report.passed {
+                report.failures.extend(fixtures.service_logs());
+            }
+            report
+        })
+    } else {
+        Ok(TestResult {
+            command: command_line(&config.command),
+            failures: before_all_failures,
+            attempts: 1,
+            runs: 1,
+            failed_runs: 1,
+            ..TestResult::default()
+        })
+    };
+    let after_all_failures = fixtures.test_finished()?;
+
+    let mut report = result?;
+    report.file = suite.path.clone();
+    report.name = suite.test_name(index);
+    report.description = config.description.clone();
+    if !after_all_failures.is_empty() {
+        report.passed = false;
+        report.failures.extend(after_all_failures);
+    }
+    if let Some(dir) = &options.artifacts_dir {
+        write_artifacts(&dir.join(artifacts_name(suite, index)), config, &report)?;
+    }
+    // The sandbox is only retained for the artifacts.
+    report.sandbox = None;
+    let diffs = std::mem::take(&mut report.diffs);
+    if let Some(tool) = options.diff_tool.as_deref().filter(|_| !report.passed) {
+        show_diffs(tool, &diffs, &mut report);
+    }
+    if let Some((dir, key)) = &cache_key {
+        cache::store(dir, key, report.passed)?;
+    }
+    if options.update_expected && !report.passed && !config.expected_failure {
+        update_expectations(suite, index, &mut report);
+    }
+    tracing::info!(
+        passed = report.passed,
+        duration = ?report.duration,
+        attempts = report.attempts,
+        "finished"
+    );
+    Ok(report)
+}
+
+/// Replaces the exact expectations of the test at `index` of `suite` that
+/// its output didn't match with the actual output, noting the outcome in
+/// its `report`.
+fn update_expectations(suite: &Suite, index: usize, report: &mut TestResult) {
+    let config = &suite.tests[index];
+    // Golden files are left to `smokers review`.
+    for mismatch in &report.mismatched_output {
+        if mismatch.golden_file.is_some() {
+            continue;
+        }
+        let name = &mismatch.stream;
+        let actual = match name.as_str() {
+            "stdout" => &report.stdout,
+            _ => &report.stderr,
+        };
+        let actual = if config.strip_ansi {
+            strip_ansi(actual)
+        } else {
+            Cow::Borrowed(actual.as_str())
+        };
+        let note = match update::update_expected(suite, index, name, &actual) {
+            Ok(path) => format!("Updated the expected {} in {}", name, path.display()),
+            Err(reason) => format!("The expected {} could not be updated: {}", name, reason),
+        };
+        report.failures.push(note);
+    }
+}
+
+/// The name of the directory holding the artifacts of the test at `index`
+/// of `suite`, derived from its file and name.
+fn artifacts_name(suite: &Suite, index: usize) -> String {
+    let name = suite
+        .display_name(index)
+        .unwrap_or_else(|| format!("test-{}", index + 1));
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Runs a test of `suite` wrapped in the before-each and after-each
+/// commands of the suite. The test isn't run if a before-each command
+/// failed, the after-each commands run regardless.
+fn run_test_with_hooks(
+    suite: &Suite,
+    config: &Configuration,
+    options: &RunOptions,
+) -> Result<TestResult, Error> {
+    let mut before_each_failure = None;
+    for command in &suite.before_each {
+        before_each_failure = run_fixture("before-each", command, suite.process(command))?;
+        if before_each_failure.is_some() {
+            break;
+        }
+    }
+    let result = match before_each_failure {
+        None => run_test_repeated(config, options),
+        Some(failure) => Ok(TestResult {
+            command: command_line(&config.command),
+            failures: vec![failure],
+            attempts: 1,
+            runs: 1,
+            failed_runs: 1,
+            ..TestResult::default()
+        }),
+    };
+
+    let mut after_each_failures = vec![];
+    for command in &suite.after_each {
+        after_each_failures.extend(run_fixture("after-each", command, suite.process(command))?);
+    }
+    let mut report = result?;
+    if !after_each_failures.is_empty() {
+        report.passed = false;
+        report.failures.extend(after_each_failures);
+    }
+    Ok(report)
+}
+
+/// Reports the test at `index` of the suite as passed without running it,
+/// as it passed before with the same inputs.
+fn cached_suite_test(fixtures: &SuiteFixtures, index: usize) -> Result<TestResult, Error> {
+    let suite = fixtures.suite;
+    let after_all_failures = fixtures.test_finished()?;
+    Ok(TestResult {
+        file: suite.path.clone(),
+        name: suite.test_name(index),
+        description: suite.tests[index].description.clone(),
+        command: command_line(&suite.tests[index].command),
+        passed: after_all_failures.is_empty(),
+        cached: true,
+        failures: after_all_failures,
+        ..TestResult::default()
+    })
+}
+
+/// Reports a test of a suite as skipped for `reason` without running it.
+fn skip_suite_test(
+    fixtures: &SuiteFixtures,
+    index: usize,
+    reason: String,
+) -> Result<TestResult, Error> {
+    let suite = fixtures.suite;
+    let after_all_failures = fixtures.test_finished()?;
+    Ok(TestResult {
+        file: suite.path.clone(),
+        name: suite.test_name(index),
+        description: suite.tests[index].description.clone(),
+        command: command_line(&suite.tests[index].command),
+        passed: after_all_failures.is_empty(),
+        skipped: Some(reason),
+        failures: after_all_failures,
+        ..TestResult::default()
+    })
+}
+
+/// Strips trailing newlines for the exact comparison with the expected
+/// output if configured.
+fn trim<'a>(text: &'a str, expected: &OutputExpectation) -> &'a str {
+    if expected.trim_trailing_newline {
+        text.trim_end_matches('\n')
+    } else {
+        text
+    }
+}
+
+/// Removes ANSI escape sequences, like colors and cursor movements, from
+/// `text`.
+pub(crate) fn strip_ansi(text: &str) -> Cow<'_, str> {
+    // CSI sequences, OSC sequences terminated by BEL or ST, and the
+    // remaining two-character escapes.
+    static ESCAPES: OnceLock<Regex> = OnceLock::new();
+    let escapes = ESCAPES.get_or_init(|| {
+        Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(\x07|\x1b\\)|\x1b[@-Z\\-_]")
+            .unwrap()
+    });
+    escapes.replace_all(text, "")
+}
+
+/// Lowercases `text` if the comparison ignores case.
+fn fold_case<'a>(text: &'a str, expected: &OutputExpectation) -> Cow<'a, str> {
+    if expected.case_insensitive {
+        Cow::Owned(text.to_lowercase())
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+/// Replaces the matches of the `mask` regexes in `text`.
+fn apply_mask<'a>(text: &'a str, mask: &[(Regex, String)]) -> Cow<'a, str> {
+    let mut text = Cow::Borrowed(text);
+    for (regex, replacement) in mask {
+        if let Cow::Owned(replaced) = regex.replace_all(&text, replacement.as_str()) {
+            text = Cow::Owned(replaced);
+        }
+    }
+    text
+}
+
+/// Checks the captured output of one stream against the expectations of
+/// the configuration. Mismatches are written to the `log_file`.
+fn check_output(
+    log_file: &mut impl std::io::Write,
+    name: &str,
+    actual_bytes: &[u8],
+    expected: &OutputExpectation,
+) -> Result<bool, Error> {
+    let mut ok = true;
+    let actual = String::from_utf8_lossy(actual_bytes);
+    let actual = if expected.strip_ansi {
+        strip_ansi(&actual)
+    } else {
+        Cow::Borrowed(&*actual)
+    };
+    let actual = &*expected.newline_mode.normalize(&actual);
+    let actual = &*fold_case(actual, expected);
+    // Masks only apply to the comparisons with the expected text.
+    let masked_actual = &*apply_mask(actual, expected.mask);
+    let trimmed_actual = trim(masked_actual, expected);
+
+    if let Some(exact) = expected.exact {
+        let exact = expected.newline_mode.normalize(exact);
+        let exact = fold_case(&exact, expected);
+        let exact = apply_mask(&exact, expected.mask);
+        let expected_label = format!("expected {}", name);
+        ok &= compare_text(
+            log_file,
+            name,
+            &format!("the {}", expected_label),
+            &expected_label,
+            trim(&exact, expected),
+            trimmed_actual,
+            expected,
+        )?;
+    }
+
+    if let Some(file) = expected.file {
+        let golden = match std::fs::read_to_string(file) {
+            Ok(golden) => golden,
+            Err(e) => {
+                writeln!(
+                    log_file,
+                    "The golden file {} could not be read: {}",
+                    file.display(),
+                    e
+                )?;
+                return Ok(false);
+            }
+        };
+        let golden = expected.newline_mode.normalize(&golden);
+        let golden = fold_case(&golden, expected);
+        let golden = apply_mask(&golden, expected.mask);
+        let expected_label = file.display().to_string();
+        ok &= compare_text(
+            log_file,
+            name,
+            &expected_label,
+            &expected_label,
+            trim(&golden, expected),
+            trimmed_actual,
+            expected,
+        )?;
+    }
+
+    if let Some(lines) = expected.lines {
+        let lines: Vec<_> = lines
+            .iter()
+            .map(|l| apply_mask(&fold_case(l, expected), expected.mask).into_owned())
+            .collect();
+        let actual_lines = split_lines(masked_actual);
+        if actual_lines.iter().ne(lines.iter()) {
+            let line = actual_lines
+                .iter()
+                .zip(&lines)
+                .position(|(a, e)| a != e)
+                .unwrap_or_else(|| actual_lines.len().min(lines.len()));
+            writeln!(
+                log_file,
+                "{} differs from the expected lines at line {}:",
+                name,
+                line + 1
+            )?;
+            let expected_text: String = lines.iter().map(|l| format!("{}\n", l)).collect();
+            let actual_text: String = actual_lines.iter().map(|l| format!("{}\n", l)).collect();
+            let expected_label = format!("expected {}", name);
+            let actual_label = format!("actual {}", name);
+            let diff =
+                diff::unified_diff(&expected_label, &actual_label, &expected_text, &actual_text);
+            write!(log_file, "{}", diff)?;
+            ok = false;
+        }
+    }
+
+    if !expected.line_assertions.is_empty() {
+        let actual_lines = split_lines(masked_actual);
+        for (number, line) in expected.line_assertions {
+            let line = apply_mask(&fold_case(line, expected), expected.mask).into_owned();
+            let position = match usize::try_from(*number) {
+                Ok(number) => number.checked_sub(1),
+                Err(_) => actual_lines
+                    .len()
+                    .checked_sub(number.unsigned_abs() as usize),
+            };
+            match position.and_then(|position| actual_lines.get(position)) {
+                Some(actual_line) if *actual_line == line => {}
+                Some(actual_line) => {
+                    writeln!(
+                        log_file,
+                        "{} line {} is {:?}, expected {:?}",
+                        name, number, actual_line, line
+                    )?;
+                    ok = false;
+                }
+                None => {
+                    writeln!(
+                        log_file,
+                        "{} has no line {}, it has {} lines",
+                        name,
+                        number,
+                        actual_lines.len()
+                    )?;
+                    ok = false;
+                }
+            }
+        }
+    }
+
+    if let Some(count) = expected.line_count {
+        let actual_count = split_lines(actual).len();
+        if actual_count != count {
+            writeln!(
+                log_file,
+                "{} has {} lines, expected {}",
+                name, actual_count, count
+            )?;
+            ok = false;
+        }
+    }
+
+    if expected.empty && !actual_bytes.is_empty() {
+        writeln!(log_file, "{} is not empty", name)?;
+        ok = false;
+    }
+
+    if expected.json.is_some() || !expected.json_assertions.is_empty() {
+        match serde_json::from_str::<serde_json::Value>(actual) {
+            Ok(actual_json) => {
+                ok &= check_json(log_file, name, &actual_json, expected)?;
+            }
+            Err(e) => {
+                writeln!(log_file, "{} is not valid JSON: {}", name, e)?;
+                ok = false;
+            }
+        }
+    }
+
+    for needle in expected.contains {
+        if !actual.contains(&*fold_case(needle, expected)) {
+            writeln!(log_file, "{} does not contain {:?}", name, needle)?;
+            ok = false;
+        }
+    }
+
+    for needle in expected.not_contains {
+        if actual.contains(&*fold_case(needle, expected)) {
+            writeln!(log_file, "{} contains {:?}", name, needle)?;
+            ok = false;
+        }
+    }
+
+    if let Some(regex) = expected.regex {
+        // The output is already lowercase, but the regex may not be.
+        let matches = if expected.case_insensitive {
+            RegexBuilder::new(regex.as_str())
+                .case_insensitive(true)
+                .build()
+                .is_ok_and(|regex| regex.is_match(actual))
+        } else {
+            regex.is_match(actual)
+        };
+        if !matches {
+            writeln!(
+                log_file,
+                "{} does not match regex {:?}",
+                name,
+                regex.as_str()
+            )?;
+            ok = false;
+        }
+    }
+
+    for bytes in &expected.bytes {
+        if actual_bytes != *bytes {
+            let offset = actual_bytes
+                .iter()
+                .zip(bytes.iter())
+                .position(|(a, b)| a != b)
+                .unwrap_or_else(|| actual_bytes.len().min(bytes.len()));
+            writeln!(
+                log_file,
+                "{} differs from the expected bytes at offset {} ({} bytes expected, {} bytes actual)",
+                name,
+                offset,
+                bytes.len(),
+                actual_bytes.len()
+            )?;
+            ok = false;
+        }
+    }
+
+    Ok(ok)
+}
+
+/// Compares the `actual` text of an output stream with the `expected` text,
+/// described as `source` in messages and labelled `expected_label` in
+/// diffs, in the way configured in `expectation`.
+fn compare_text(
+    log_file: &mut impl std::io::Write,
+    name: &str,
+    source: &str,
+    expected_label: &str,
+    expected: &str,
+    actual: &str,
+    expectation: &OutputExpectation,
+) -> Result<bool, Error> {
+    let actual_label = format!("actual {}", name);
+    let (expected, actual) = match expectation.compare {
+        CompareMode::IgnoreWhitespace => {
+            (collapse_whitespace(expected), collapse_whitespace(actual))
+        }
+        _ => (expected.to_string(), actual.to_string()),
+    };
+    let (expected, actual) = (expected.as_str(), actual.as_str());
+    if expectation.compare == CompareMode::UnorderedLines {
+        let mut counts: BTreeMap<&str, isize> = BTreeMap::new();
+        for line in split_lines(expected) {
+            *counts.entry(line).or_default() += 1;
+        }
+        for line in split_lines(actual) {
+            *counts.entry(line).or_default() -= 1;
+        }
+        if counts.values().all(|&count| count == 0) {
+            return Ok(true);
+        }
+        writeln!(
+            log_file,
+            "{} differs from {} ignoring the order of lines:",
+            name, source
+        )?;
+        writeln!(log_file, "--- {}\n+++ {}", expected_label, actual_label)?;
+        for (line, count) in counts {
+            let sign = if count > 0 { '-' } else { '+' };
+            for _ in 0..count.abs() {
+                writeln!(log_file, "{}{}", sign, line)?;
+            }
+        }
+        record_diff(name, expected, actual, expectation);
+        return Ok(false);
+    }
+
+    match pattern::compare(expected, actual, expectation.tolerance) {
+        Comparison::Equal => Ok(true),
+        Comparison::Different => {
+            writeln!(log_file, "{} differs from {}:", name, source)?;
+            let diff = diff::unified_diff(expected_label, &actual_label, expected, actual);
+            write!(log_file, "{}", diff)?;
+            record_diff(name, expected, actual, expectation);
+            Ok(false)
+        }
+        Comparison::OutOfTolerance {
+            expected,
+            actual,
+            tolerance,
+        } => {
+            writeln!(
+                log_file,
+                "{} differs from {}: {} is not within {} of {}",
+                name, source, actual, tolerance, expected
+            )?;
+            Ok(false)
+        }
+    }
+}
+
+/// Collects a mismatch of the output stream or file `name` for the diff
+/// tool, if one is configured.
+fn record_diff(name: &str, expected: &str, actual: &str, expectation: &OutputExpectation) {
+    if let Some(diffs) = expectation.diffs {
+        diffs.borrow_mut().push(TextDiff {
+            name: name.to_string(),
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        });
+    }
+}
+
+/// Shows the mismatches of a failed test with the diff `tool`, noting the
+/// ones it failed to run for in the `report`. Only one test uses the tool
+/// at a time, so that their output doesn't interleave.
+fn show_diffs(tool: &str, diffs: &[TextDiff], report: &mut TestResult) {
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _lock = LOCK
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    for diff in diffs {
+        if let Err(e) = run_diff_tool(tool, diff) {
+            report
+                .failures
+                .push(format!("Failed to run the diff tool {:?}: {}", tool, e));
+        }
+    }
+}
+
+/// Writes the expected and actual text of `diff` to temporary files and
+/// runs `tool` on them. The words of `tool` are separated by whitespace,
+/// the paths are appended. Its output is written to stderr, so that it
+/// doesn't mix with the report.
+fn run_diff_tool(tool: &str, diff: &TextDiff) -> std::io::Result<()> {
+    let mut words = tool.split_whitespace();
+    let program = words.next().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "the command is empty")
+    })?;
+    let dir = Sandbox::create()?;
+    let file_name = diff.name.replace(['/', '\\'], "_");
+    let expected_path = dir.path().join(format!("expected-{}", file_name));
+    let actual_path = dir.path().join(format!("actual-{}", file_name));
+    std::fs::write(&expected_path, &diff.expected)?;
+    std::fs::write(&actual_path, &diff.actual)?;
+    let mut child = process::spawn(
+        std::process::Command::new(program)
+            .args(words)
+            .arg(&expected_path)
+            .arg(&actual_path)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped()),
+    )?;
+    let copied = std::io::copy(
+        &mut child.stdout.take().expect("stdout is piped"),
+        &mut std::io::stderr().lock(),
+    );
+    // Diff tools commonly exit with a failure when the files differ, which
+    // they do.
+    child.wait()?;
+    copied.map(drop)
+}
+
+/// Replaces runs of spaces and tabs in `text` with a single space and
+/// removes them from the end of lines.
+fn collapse_whitespace(text: &str) -> String {
+    let mut collapsed = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let (content, newline) = match line.strip_suffix('\n') {
+            Some(content) => (content, "\n"),
+            None => (line, ""),
+        };
+        // Leading whitespace is collapsed but kept, it often matters for
+        // indentation.
+        if content.starts_with([' ', '\t']) {
+            collapsed.push(' ');
+        }
+        let words: Vec<&str> = content
+            .split([' ', '\t'])
+            .filter(|w| !w.is_empty())
+            .collect();
+        collapsed.push_str(&words.join(" "));
+        collapsed.push_str(newline);
+    }
+    collapsed
+}
+
+/// Checks the JSON expectations on an output stream against the parsed
+/// `actual` value.
+fn check_json(
+    log_file: &mut impl std::io::Write,
+    name: &str,
+    actual: &serde_json::Value,
+    expected: &OutputExpectation,
+) -> Result<bool, Error> {
+    let mut ok = true;
+    if let Some(expected) = expected.json {
+        if !json::equal(actual, expected) {
+            writeln!(log_file, "{} differs from the expected JSON:", name)?;
+            let expected_label = format!("expected {}", name);
+            let actual_label = format!("actual {}", name);
+            let diff = diff::unified_diff(
+                &expected_label,
+                &actual_label,
+                &json::pretty(expected),
+                &json::pretty(actual),
+            );
+            write!(log_file, "{}", diff)?;
+            ok = false;
+        }
+    }
+
+    for (query, expected) in expected.json_assertions {
+        match query.evaluate(actual) {
+            Ok(value) if json::equal(&value, expected) => {}
+            Ok(value) => {
+                writeln!(
+                    log_file,
+                    "{} at `{}` is {}, expected {}",
+                    name, query, value, expected
+                )?;
+                ok = false;
+            }
+            Err(e) => {
+                writeln!(
+                    log_file,
+                    "{} at `{}` can't be evaluated: {}",
+                    name, query, e
+                )?;
+                ok = false;
+            }
+        }
+    }
+    Ok(ok)
+}
+
+/// Splits `text` into lines, ignoring the newline at the end of the last
+/// line.
+fn split_lines(text: &str) -> Vec<&str> {
+    if text.is_empty() {
+        return vec![];
+    }
+    text.strip_suffix('\n')
+        .unwrap_or(text)
+        .split('\n')
+        .collect()
+}
+
+/// Checks the `expect-files` of the test case against the files in `dir`.
+fn check_files(
+    log_file: &mut impl std::io::Write,
+    config: &Configuration,
+    diffs: Option<&RefCell<Vec<TextDiff>>>,
+    dir: &Path,
+) -> Result<bool, Error> {
+    let mut ok = true;
+    for (path, expected) in &config.expect_files {
+        let content = match std::fs::read(dir.join(path)) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                writeln!(log_file, "File {} could not be read: {}", path.display(), e)?;
+                ok = false;
+                continue;
+            }
+        };
+        match (content, expected.exists) {
+            (Some(content), true) => {
+                let name = path.display().to_string();
+                ok &= check_output(
+                    log_file,
+                    &name,
+                    &content,
+                    &OutputExpectation {
+                        newline_mode: config.newline_mode,
+                        tolerance: config.tolerance,
+                        compare: config.compare,
+                        case_insensitive: config.case_insensitive,
+                        strip_ansi: config.strip_ansi,
+                        diffs,
+                        mask: &config.mask,
+                        ..expected.expectation()
+                    },
+                )?;
+            }
+            (None, false) => {}
+            (Some(_), false) => {
+                writeln!(log_file, "File {} exists but shouldn't", path.display())?;
+                ok = false;
+            }
+            (None, true) => {
+                writeln!(log_file, "File {} doesn't exist", path.display())?;
+                ok = false;
+            }
+        }
+    }
+    Ok(ok)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::config::ExitCode;
+    use crate::report;
+
+    /// Runs the test case, writing the reasons of a failure to the `log_file`.
+    fn run(config: &Configuration, log_file: &mut impl std::io::Write) -> Result<bool, Error> {
+        let report = run_test(config)?;
+        report.write_log(log_file)?;
+        Ok(report.passed)
+    }
+
+    fn discard() -> impl std::io::Write {
+        pub struct Discard;
+        impl std::io::Write for Discard {
+            fn write(&mut self, d: &[u8]) -> std::io::Result<usize> {
+                Ok(d.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        Discard {}
+    }
+
+    fn capture() -> std::io::Cursor<Vec<u8>> {
+        std::io::Cursor::new(vec![])
+    }
+
+    /// The human readable output with the varying durations removed.
+    fn without_durations(output: &[u8]) -> String {
+        let duration = Regex::new(r" in \d+(ms|\.\d+s)").unwrap();
+        duration
+            .replace_all(&String::from_utf8_lossy(output), "")
+            .into_owned()
+    }
+
+    #[test]
+    fn test_run_hello_world() {
+        let config = Configuration {
+            command: (
+                "sh".to_string(),
+                vec!["-c".to_string(), "exit 1".to_string()],
+            ),
+            exit_code: ExitCode::Code(1),
+            ..Configuration::default()
+        };
+
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(result);
+    }
+
+    #[test]
+    fn test_run_exit1() {
+        let config = Configuration {
+            command: (
+                "sh".to_string(),
+                vec!["-c".to_string(), "exit 1".to_string()],
+            ),
+            exit_code: ExitCode::Code(1),
+            ..Configuration::default()
+        };
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(result);
+    }
+
+    #[test]
+    fn test_run_shell() {
+        let input = r#"
+tests:
+  - command: echo "$GREETING world" | tr a-z A-Z > /dev/stderr
+    shell: true
+    env:
+      GREETING: hello
+    stderr: "HELLO WORLD\n"
+  - script: |
+      set -e
+      printf '%s\n' "${UNSET:-default}"
+      exit 3
+    exit-code: 3
+    stdout: "default\n"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        for config in &suite.tests {
+            let mut log = capture();
+            let result = run(config, &mut log).unwrap();
+            assert!(result, "{}", String::from_utf8_lossy(&log.into_inner()));
+        }
+    }
+
+    #[test]
+    fn test_run_pty() {
+        let input = r#"
+tests:
+  - script: "[ -t 0 ] && [ -t 1 ] && [ -t 2 ] && echo tty && echo err >&2"
+    stdout: "tty\nerr\n"
+    stderr: ""
+  - script: "read line; echo \"got $line\"; cat"
+    stdin: "first\nsecond"
+    stdout: "got first\nsecond"
+  - command: "true"
+    stdin: "ignored\n"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        for mut config in suite.tests {
+            config.pty = true;
+            let mut log = capture();
+            let result = run(&config, &mut log).unwrap();
+            assert!(result, "{}", String::from_utf8_lossy(&log.into_inner()));
+        }
+    }
+
+    #[test]
+    fn test_run_timeout_signal() {
+        let input = r#"
+script: |
+  trap 'echo cleanup; exit 3' TERM
+  sleep 5 >/dev/null &
+  wait
+timeout: 200ms
+timeout-signal: TERM
+"#;
+        let config = Configuration::from_reader(input.as_bytes()).unwrap();
+        let start = std::time::Instant::now();
+        let report = run_test_with_options(&config, &RunOptions::default()).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(3));
+        assert!(!report.passed);
+        assert_eq!(report.stdout, "cleanup\n");
+        assert_eq!(report.exit_code, Some(3));
+        assert_eq!(
+            report.failures,
+            vec!["The process timed out after 200ms and was sent SIGTERM (15)"]
+        );
+    }
+
+    #[test]
+    fn test_run_timeout() {
+        let input = r#"
+tests:
+  - script: echo started; sleep 5
+    stdout: "started\n"
+    timeout: 200ms
+  - script: sleep 5
+"#;
+        let options = RunOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..RunOptions::default()
+        };
+        for pty in [false, true] {
+            let suite = Suite::from_reader(input.as_bytes()).unwrap();
+            for mut config in suite.tests {
+                config.pty = pty;
+                let start = std::time::Instant::now();
+                let report = run_test_with_options(&config, &options).unwrap();
+                assert!(start.elapsed() < Duration::from_secs(3));
+                assert!(!report.passed);
+                assert_eq!(
+                    report.failures,
+                    vec!["The process timed out after 200ms and was killed"]
+                );
+            }
+        }
+
+        // The timeout of the test takes precedence over the default.
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let options = RunOptions {
+            timeout: Some(Duration::from_secs(10)),
+            ..RunOptions::default()
+        };
+        let report = run_test_with_options(&suite.tests[0], &options).unwrap();
+        assert_eq!(
+            report.failures,
+            vec!["The process timed out after 200ms and was killed"]
+        );
+    }
+
+    #[test]
+    fn test_run_max_output_bytes() {
+        let input = "command: [head, -c, \"100000\", /dev/zero]\nmax-output-bytes: 1K";
+        for pty in [false, true] {
+            let mut config = Configuration::from_reader(input.as_bytes()).unwrap();
+            config.pty = pty;
+            let report = run_test(&config).unwrap();
+            assert_eq!(report.stdout.len(), 1024);
+            assert_eq!(
+                report.failures,
+                vec!["stdout exceeded the limit of 1024 bytes and was truncated"]
+            );
+        }
+
+        let input = "command: [echo, hello]\nmax-output-bytes: 6\nstdout: \"hello\\n\"";
+        let config = Configuration::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&config).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+    }
+
+    #[test]
+    fn test_run_limits() {
+        let input = r#"
+tests:
+  - script: ulimit -n; ulimit -t; ulimit -v
+    limits:
+      open-files: 32
+      cpu: 7
+      memory: 512M
+    stdout: "32\n7\n524288\n"
+  - script: f=$(mktemp); head -c 4096 /dev/zero > "$f"; s=$?; rm -f "$f"; exit $s
+    limits:
+      file-size: 1K
+    exit-code: nonzero
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        for config in &suite.tests {
+            let mut log = capture();
+            let result = run(config, &mut log).unwrap();
+            assert!(result, "{}", String::from_utf8_lossy(&log.into_inner()));
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_user() {
+        let config = Configuration::from_reader(
+            "script: id -u; id -g\nuser: 12345\ngroup: 23456\nstdout: \"12345\\n23456\\n\""
+                .as_bytes(),
+        )
+        .unwrap();
+        // SAFETY: getuid can't fail.
+        if unsafe { libc::getuid() } == 0 {
+            let report = run_test(&config).unwrap();
+            assert!(report.passed, "{:?}", report.failures);
+        } else {
+            assert!(run_test(&config).is_err());
+        }
+        let config =
+            Configuration::from_reader("command: \"true\"\nuser: smokers-missing-user".as_bytes())
+                .unwrap();
+        assert_eq!(
+            run_test(&config).unwrap_err().to_string(),
+            "IO error: unknown user smokers-missing-user"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_umask() {
+        let input = r#"
+tests:
+  - script: umask
+    umask: 027
+    stdout: "0027\n"
+  - script: umask
+    umask: "0o077"
+    stdout: "0077\n"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        for config in &suite.tests {
+            let report = run_test(config).unwrap();
+            assert!(report.passed, "{:?}", report.failures);
+        }
+        assert!(Configuration::from_reader("command: ls\numask: 089".as_bytes()).is_err());
+        assert!(Configuration::from_reader("command: ls\numask: 1777".as_bytes()).is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_run_priority() {
+        let input = r#"
+tests:
+  - script: "cut -d' ' -f19 /proc/self/stat"
+    nice: 5
+    stdout: "5\n"
+  - command: [ionice]
+    ionice: idle
+    stdout: "idle\n"
+  - command: [ionice]
+    ionice:
+      class: best-effort
+      level: 6
+    stdout: "best-effort: prio 6\n"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        for config in &suite.tests {
+            let report = run_test(config).unwrap();
+            assert!(report.passed, "{:?}", report.failures);
+        }
+        assert!(Configuration::from_reader(
+            "command: ls\nionice:\n  class: idle\n  level: 1".as_bytes()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_run_sandbox() {
+        let input = r#"
+tests:
+  - setup: [[touch, created]]
+    command: [ls]
+    stdout: "created\n"
+    sandbox: true
+  - script: pwd; exit 1
+    sandbox: true
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+
+        // The sandbox is removed unless it is kept for a failed test.
+        let report = run_test(&suite.tests[1]).unwrap();
+        let path = std::path::PathBuf::from(report.stdout.trim_end());
+        assert!(!path.exists());
+        let options = RunOptions {
+            keep_sandbox: true,
+            ..RunOptions::default()
+        };
+        let report = run_test_with_options(&suite.tests[1], &options).unwrap();
+        let path = std::path::PathBuf::from(report.stdout.trim_end());
+        assert!(path.is_dir());
+        assert_eq!(
+            report.failures.last().unwrap(),
+            &format!("The sandbox was kept at {}", path.display())
+        );
+        std::fs::remove_dir(path).unwrap();
+
+        let input = "command: [ls]\ncwd: /\nsandbox: true";
+        assert!(Configuration::from_reader(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_run_files() {
+        let input = r#"
+tests:
+  - files:
+      input.txt: "hello\n"
+      nested/dir/copy.toml:
+        source: Cargo.toml
+    script: cat input.txt; head -n 1 nested/dir/copy.toml
+    stdout: "hello\n[package]\n"
+    sandbox: true
+  - files:
+      missing.txt:
+        source: /nonexistent/smokers
+    command: ["true"]
+    sandbox: true
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert!(!report.passed);
+        assert!(
+            report.failures[0].starts_with("File ") && report.failures[0].contains("missing.txt"),
+            "{:?}",
+            report.failures
+        );
+    }
+
+    #[test]
+    fn test_run_expect_files() {
+        let input = r#"
+tests:
+  - script: printf 'hello\nworld\n' > out.txt; mkdir -p logs; echo done > logs/run.log
+    sandbox: true
+    expect-files:
+      out.txt:
+        content: "hello\nworld\n"
+        contains: world
+      logs/run.log:
+        regex: "^do+ne"
+      missing.txt:
+        exists: false
+  - script: echo hello > out.txt; touch unexpected
+    sandbox: true
+    expect-files:
+      out.txt:
+        content: "bye\n"
+      unexpected:
+        exists: false
+      missing.txt: {}
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let mut log = capture();
+        let result = run(&suite.tests[0], &mut log).unwrap();
+        assert!(result, "{}", String::from_utf8_lossy(&log.into_inner()));
+
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert!(!report.passed);
+        assert_eq!(
+            report.failures,
+            vec![
+                "File missing.txt doesn't exist",
+                "out.txt differs from the expected out.txt:",
+                "--- expected out.txt",
+                "+++ actual out.txt",
+                "@@ -1 +1 @@",
+                "-bye",
+                "+hello",
+                "File unexpected exists but shouldn't",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_expect_files_unreadable() {
+        let input = r#"
+tests:
+  - script: mkdir logs
+    sandbox: true
+    expect-files:
+      logs:
+        content: ""
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(!report.passed);
+        assert_eq!(report.failures.len(), 1);
+        assert!(
+            report.failures[0].starts_with("File logs could not be read: "),
+            "{:?}",
+            report.failures
+        );
+    }
+
+    #[test]
+    fn test_run_clean_env() {
+        std::env::set_var("SMOKERS_TEST_NOT_PASSED", "1");
+        let input = r#"
+command: [env]
+clean-env: true
+pass-env: [PATH, SMOKERS_TEST_UNSET]
+env:
+  FOO: bar
+stdout-regex: "^(FOO=bar\nPATH=[^\n]+|PATH=[^\n]+\nFOO=bar)\n$"
+"#;
+        let config = Configuration::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&config).unwrap();
+        assert!(report.passed, "{:?} {:?}", report.failures, report.stdout);
+    }
+
+    #[test]
+    fn test_run_stdout_bytes() {
+        let input = r#"
+tests:
+  - script: printf '\377\000PNG'
+    stdout-base64: /wBQTkc=
+    stdout-hex: ff 00 50 4e 47
+  - script: printf '\377\001PN'
+    stdout-hex: ff00504e47
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        assert!(run(&suite.tests[0], &mut discard()).unwrap());
+
+        let mut log = capture();
+        assert!(!run(&suite.tests[1], &mut log).unwrap());
+        let log = String::from_utf8(log.into_inner()).unwrap();
+        assert!(
+            log.starts_with(
+                "stdout differs from the expected bytes at offset 1 (5 bytes expected, 4 bytes actual)\n"
+            ),
+            "{}",
+            log
+        );
+    }
+
+    #[test]
+    fn test_run_trim_trailing_newline() {
+        let input = r#"
+tests:
+  - command: [echo, foo]
+    stdout: foo
+    trim-trailing-newline: true
+  - command: [printf, foo]
+    stdout: "foo\n\n"
+    trim-trailing-newline: true
+  - command: [echo, foo]
+    stdout: foo
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        assert!(run(&suite.tests[0], &mut discard()).unwrap());
+        assert!(run(&suite.tests[1], &mut discard()).unwrap());
+        assert!(!run(&suite.tests[2], &mut discard()).unwrap());
+
+        let options = RunOptions {
+            normalize_newlines: true,
+            ..RunOptions::default()
+        };
+        assert!(
+            run_test_with_options(&suite.tests[2], &options)
+                .unwrap()
+                .passed
+        );
+        let config = Configuration {
+            command: ("printf".to_string(), vec!["foo\n".to_string()]),
+            stdout: Some("foo".to_string()),
+            trim_trailing_newline: Some(false),
+            ..Configuration::default()
+        };
+        assert!(!run_test_with_options(&config, &options).unwrap().passed);
+    }
+
+    #[test]
+    fn test_run_newline_mode() {
+        let input = r#"
+tests:
+  - command: [printf, "a\r\nb\r\n"]
+    stdout: "a\nb\n"
+    stdout-contains: "a\nb"
+    newline-mode: lf
+  - command: [printf, "a\nb\n"]
+    stdout: "a\r\nb\r\n"
+    newline-mode: lf
+  - command: [printf, "a\r\nb\r\n"]
+    stdout: "a\nb\n"
+    newline-mode: strict
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        assert!(run(&suite.tests[0], &mut discard()).unwrap());
+        assert!(run(&suite.tests[1], &mut discard()).unwrap());
+        assert!(!run(&suite.tests[2], &mut discard()).unwrap());
+        assert_eq!(suite.tests[2].newline_mode, crate::NewlineMode::Strict);
+        assert!(Configuration::from_reader("command: ls\nnewline-mode: cr".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_run_unexpected_exit1() {
+        let config = Configuration {
+            command: (
+                "sh".to_string(),
+                vec!["-c".to_string(), "exit 1".to_string()],
+            ),
+            exit_code: ExitCode::Code(0),
+            ..Configuration::default()
+        };
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(!result);
+    }
+
+    #[test]
+    fn test_run_spits_out_stdout_on_exit_mismatch() {
+        let config = Configuration {
+            command: (
+                "sh".to_string(),
+                vec!["-c".to_string(), "echo foo bar baz".to_string()],
+            ),
+            exit_code: ExitCode::Code(1),
+            ..Configuration::default()
+        };
+
+        let mut capture = capture();
+        let result = run(&config, &mut capture).unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains(r#"stdout: "foo bar baz\n""#),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_spits_out_stderr_on_exit_mismatch() {
+        let config = Configuration {
+            command: (
+                "sh".to_string(),
+                vec!["-c".to_string(), "echo foo bar baz >&2".to_string()],
+            ),
+            exit_code: ExitCode::Code(1),
+            ..Configuration::default()
+        };
+
+        let mut capture = capture();
+        let result = run(&config, &mut capture).unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains(r#"stderr: "foo bar baz\n""#),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_stderr() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "echo foo bar baz >&2"]
+stderr: "foo bar baz\n"
+stderr-contains: [foo, baz]
+stderr-regex: "^foo .* baz\n$"
+"#,
+        )
+        .unwrap();
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(result);
+    }
+
+    #[test]
+    fn test_run_unexpected_stderr() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "echo foo >&2"]
+stderr-contains: bar
+"#,
+        )
+        .unwrap();
+        let mut capture = capture();
+        let result = run(&config, &mut capture).unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains(r#"stderr does not contain "bar""#),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_suite_reports_each_test() {
+        let input = r#"
+tests:
+  good:
+    command: "true"
+  bad:
+    command: "false"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let mut capture = capture();
+        let result = run_suites(
+            &[suite],
+            &RunOptions::default(),
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        assert!(!result);
+        let output = without_durations(&capture.into_inner());
+        assert!(output.contains("good: passed\n"), "output: {:?}", output);
+        assert!(output.contains("bad: failed\n"), "output: {:?}", output);
+    }
+
+    #[test]
+    fn test_run_stdout_regex() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "echo version 1.2.3"]
+stdout-regex: "^version \\d+\\.\\d+\\.\\d+\n$"
+"#,
+        )
+        .unwrap();
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(result);
+    }
+
+    #[test]
+    fn test_run_stdout_regex_mismatch() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "echo version unknown"]
+stdout-regex: "^version \\d+"
+"#,
+        )
+        .unwrap();
+        let mut capture = capture();
+        let result = run(&config, &mut capture).unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains(r#"stdout does not match regex "^version \\d+""#),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_stdout_contains() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "echo hello smokers world"]
+stdout-contains: [hello, world]
+"#,
+        )
+        .unwrap();
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(result);
+    }
+
+    #[test]
+    fn test_run_stdout_contains_mismatch() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "echo hello world"]
+stdout-contains: smokers
+"#,
+        )
+        .unwrap();
+        let mut capture = capture();
+        let result = run(&config, &mut capture).unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains(r#"stdout does not contain "smokers""#),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_not_contains() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "echo hello world; echo 'WARNING: deprecated' >&2"]
+stdout-not-contains: ["panicked at", smokers]
+stderr-not-contains: WARNING
+"#,
+        )
+        .unwrap();
+        let mut capture = capture();
+        let result = run(&config, &mut capture).unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.starts_with("stderr contains \"WARNING\"\n"),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_stdout_lines() {
+        let input = r#"
+tests:
+  - command: [printf, "one\ntwo\nthree\n"]
+    stdout-lines: [one, two, three]
+  - command: [printf, ""]
+    stdout-lines: []
+  - command: [printf, "one\ntwo\nthree"]
+    stdout-lines: [one, zwei, three, four]
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        assert!(run(&suite.tests[0], &mut discard()).unwrap());
+        assert!(run(&suite.tests[1], &mut discard()).unwrap());
+
+        let report = run_test(&suite.tests[2]).unwrap();
+        assert!(!report.passed);
+        assert_eq!(
+            report.failures,
+            vec![
+                "stdout differs from the expected lines at line 2:",
+                "--- expected stdout",
+                "+++ actual stdout",
+                "@@ -1,4 +1,3 @@",
+                " one",
+                "-zwei",
+                "+two",
+                " three",
+                "-four",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_line_count_and_empty() {
+        let input = r#"
+tests:
+  - command: [seq, "100"]
+    stdout-line-count: 100
+    stderr-empty: true
+  - command: [printf, "a\nb"]
+    stdout-line-count: 2
+  - command: [seq, "3"]
+    stdout-line-count: 2
+    stdout-empty: true
+  - command: [printf, "\n"]
+    stdout: ""
+    trim-trailing-newline: true
+    stdout-empty: true
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        for test in &suite.tests[..2] {
+            let report = run_test(test).unwrap();
+            assert!(report.passed, "{:?}", report.failures);
+        }
+        let report = run_test(&suite.tests[2]).unwrap();
+        assert_eq!(
+            report.failures,
+            ["stdout has 3 lines, expected 2", "stdout is not empty"]
+        );
+        let report = run_test(&suite.tests[3]).unwrap();
+        assert_eq!(report.failures, ["stdout is not empty"]);
+    }
+
+    #[test]
+    fn test_run_assert_lines() {
+        let input = r#"
+tests:
+  - command: [printf, "Usage: mytool\n\nmore\ndone\n"]
+    assert-lines:
+      1: "Usage: mytool"
+      2: ""
+      -1: done
+      -4: "Usage: mytool"
+  - command: [printf, "Usage: other\ndone"]
+    assert-lines:
+      1: "Usage: mytool"
+      -1: done
+      3: x
+      -3: x
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert_eq!(
+            report.failures,
+            [
+                "stdout line 1 is \"Usage: other\", expected \"Usage: mytool\"",
+                "stdout has no line 3, it has 2 lines",
+                "stdout has no line -3, it has 2 lines",
+            ]
+        );
+
+        let err = Suite::from_reader("command: [x]\nassert-lines: {0: x}".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("non-zero"), "{}", err);
+    }
+
+    #[test]
+    fn test_run_stdout_json() {
+        let input = r#"
+tests:
+  - command: [echo, '{"version": "1.2.3", "items": [1, 2.0]}']
+    stdout-json:
+      items: [1, 2]
+      version: 1.2.3
+  - command: [echo, '{"version": "1.2.4", "items": []}']
+    stdout-json: {version: 1.2.3, items: []}
+  - command: [echo, "not json"]
+    stdout-json: {}
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let mut log = capture();
+        let result = run(&suite.tests[0], &mut log).unwrap();
+        assert!(result, "{}", String::from_utf8_lossy(&log.into_inner()));
+
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert_eq!(
+            report.failures,
+            vec![
+                "stdout differs from the expected JSON:",
+                "--- expected stdout",
+                "+++ actual stdout",
+                "@@ -1,4 +1,4 @@",
+                " {",
+                "   \"items\": [],",
+                "-  \"version\": \"1.2.3\"",
+                "+  \"version\": \"1.2.4\"",
+                " }",
+            ]
+        );
+
+        let report = run_test(&suite.tests[2]).unwrap();
+        assert!(
+            report.failures[0].starts_with("stdout is not valid JSON: "),
+            "{:?}",
+            report.failures
+        );
+    }
+
+    #[test]
+    fn test_run_assert_json() {
+        let input = r#"
+command: [echo, '{"version": "1.2.3", "items": [{"id": 1}, {"id": 2}]}']
+assert-json:
+  - .version: 1.2.3
+  - .items | length: 3
+  - .items[0].id: 1
+  - .items[1].name: b
+"#;
+        let config = Configuration::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&config).unwrap();
+        assert_eq!(
+            report.failures,
+            vec![
+                "stdout at `.items | length` is 2, expected 3",
+                "stdout at `.items[1].name` can't be evaluated: no key \"name\"",
+            ]
+        );
+
+        let input = "command: [echo]\nassert-json: [{version: 1}]";
+        assert!(Configuration::from_reader(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_run_numeric_tolerance() {
+        let input = r#"
+tests:
+  - command: [echo, "took 2.46s"]
+    stdout: "took {2.5~0.1}s\n"
+  - command: [echo, "x=1.04 y=205"]
+    stdout: "x=1 y=200\n"
+    tolerance: {absolute: 0.05, relative: 0.01}
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert_eq!(
+            report.failures,
+            vec!["stdout differs from the expected stdout: 205 is not within 0.05 or 1% of 200"]
+        );
+    }
+
+    #[test]
+    fn test_run_placeholders() {
+        let config = Configuration::from_reader(
+            r#"
+script: echo "created $(pwd) at $(date -u +%Y-%m-%dT%H:%M:%SZ) in 0.3s"
+stdout: "created {{path}} at {{timestamp}} in {{number}}s\n"
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let report = run_test(&config).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+    }
+
+    #[test]
+    fn test_run_mask() {
+        let input = r#"
+tests:
+  - script: echo "started at $(date -u +%H:%M:%S) (pid $$)"; echo done
+    stdout: "started at 12:00:00 (pid 1)\ndone\n"
+    mask:
+      - '\d\d:\d\d:\d\d': <TIME>
+      - 'pid \d+': pid <PID>
+  - command: [echo, "version 1.2"]
+    stdout-lines: ["version 2.0 beta"]
+    mask:
+      - '(\d+)\.\d+': $1.x
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert_eq!(
+            report.failures[..4],
+            [
+                "stdout differs from the expected lines at line 1:",
+                "--- expected stdout",
+                "+++ actual stdout",
+                "@@ -1 +1 @@",
+            ]
+        );
+        assert_eq!(report.failures[4..], ["-version 2.x beta", "+version 1.x"]);
+    }
+
+    #[test]
+    fn test_run_unordered_lines() {
+        let input = r#"
+tests:
+  - command: [printf, "b\na\nc\na\n"]
+    stdout: "a\na\nb\nc\n"
+    compare: unordered-lines
+  - command: [printf, "b\na\nd\n"]
+    stdout: "a\na\nb\n"
+    compare: unordered-lines
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert_eq!(
+            report.failures,
+            vec![
+                "stdout differs from the expected stdout ignoring the order of lines:",
+                "--- expected stdout",
+                "+++ actual stdout",
+                "-a",
+                "+d",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_ignore_whitespace() {
+        let input = r#"
+tests:
+  - command: [printf, "NAME   SIZE \n\tfoo\t 12\n"]
+    stdout: "NAME SIZE\n foo 12\n"
+    compare: ignore-whitespace
+  - command: [printf, "NAME SIZE\nfoo 12\n"]
+    stdout: "NAME SIZE\nfoo 1 2\n"
+    compare: ignore-whitespace
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert!(!report.passed);
+        assert_eq!(collapse_whitespace("  a \t b  \nc\t"), " a b\nc");
+    }
+
+    #[test]
+    fn test_run_case_insensitive() {
+        let input = r#"
+tests:
+  - command: [printf, "Error: File Not Found\n"]
+    stdout: "error: file not found\n"
+    stdout-lines: ["ERROR: FILE NOT FOUND"]
+    stdout-contains: not found
+    stdout-not-contains: Warning
+    stdout-regex: "^error: FILE"
+    case-insensitive: true
+  - command: [printf, "Error\n"]
+    stdout: "error\n"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert!(!report.passed);
+    }
+
+    #[test]
+    fn test_run_strip_ansi() {
+        let input = r#"
+tests:
+  - command: [printf, "\x1b[1;31merror:\x1b[0m failed\n\x1b]8;;https://example.com\x07link\x1b]8;;\x07\n"]
+    stdout: "error: failed\nlink\n"
+    stdout-regex: "^error: failed"
+    strip-ansi: true
+  - command: [printf, "\x1b[31merror\x1b[0m\n"]
+    stdout: "error\n"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert!(!report.passed);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_diff_tool() {
+        let dir = std::env::temp_dir().join(format!("smokers-diff-tool-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("tool.sh");
+        std::fs::write(
+            &tool,
+            format!("cat \"$1\" \"$2\" >> {}\n", dir.join("diff").display()),
+        )
+        .unwrap();
+        let input = "tests:\n  - command: [echo, bar]\n    stdout: foo\n\
+                     \x20 - command: [echo, bar]\n    stdout: foo\n    expected-failure: true\n";
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let run = |tool: String| {
+            let options = RunOptions {
+                diff_tool: Some(tool),
+                retries: 1,
+                ..RunOptions::default()
+            };
+            let mut capture = capture();
+            let result = run_suites(
+                std::slice::from_ref(&suite),
+                &options,
+                &mut report::HumanReporter::new(&mut capture),
+            )
+            .unwrap();
+            assert!(!result);
+            String::from_utf8(capture.into_inner()).unwrap()
+        };
+        run(format!("sh {}", tool.display()));
+        let diff = std::fs::read_to_string(dir.join("diff")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // Only the final report of the failed test is shown.
+        assert_eq!(diff, "foobar\n");
+
+        let output = run("smokers-missing-diff-tool".to_string());
+        assert!(
+            output.contains("Failed to run the diff tool \"smokers-missing-diff-tool\""),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_validate_stdout() {
+        let input = r#"
+tests:
+  - command: [echo, '{"name": "smokers"}']
+    validate-stdout: [grep, -q, '"name"']
+  - command: [echo, '{}']
+    validate-stdout: [sh, -c, "echo missing name >&2; exit 1"]
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert!(!report.passed);
+        assert_eq!(
+            report.failures,
+            [
+                "The stdout validator [\"sh\", \"-c\", \"echo missing name >&2; exit 1\"] failed (exit status: 1):",
+                "missing name",
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_run_isolation() {
+        let marker = std::env::temp_dir().join(format!("smokers-isolated-{}", std::process::id()));
+        let input = format!(
+            r#"
+tests:
+  - script: "tail -n +3 /proc/net/dev | cut -d: -f1 | tr -d ' '"
+    isolation:
+      network: true
+    stdout: "lo\n"
+  - script: echo $$; exit 3
+    isolation:
+      pid: true
+    stdout: "1\n"
+    exit-code: 3
+  - script: ls -A /tmp | wc -l; touch {marker}
+    isolation:
+      private-tmp: true
+    stdout: "0\n"
+"#,
+            marker = marker.display()
+        );
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        for config in &suite.tests {
+            let report = run_test(config).unwrap();
+            assert!(report.passed, "{:?}", report.failures);
+        }
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_run_stdin() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: cat
+stdin: "foo bar baz\n"
+stdout: "foo bar baz\n"
+"#,
+        )
+        .unwrap();
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(result);
+    }
+
+    #[test]
+    fn test_run_duration() {
+        let config =
+            Configuration::from_reader("command: [sleep, \"0.2\"]\nmax-duration: 50ms".as_bytes())
+                .unwrap();
+        let report = run_test_with_options(&config, &RunOptions::default()).unwrap();
+        assert!(!report.passed);
+        assert!(report.failures[0].ends_with("longer than the maximum of 50ms"));
+
+        let config =
+            Configuration::from_reader("command: \"true\"\nmin-duration: 10s".as_bytes()).unwrap();
+        let report = run_test_with_options(&config, &RunOptions::default()).unwrap();
+        assert!(!report.passed);
+        assert!(report.failures[0].ends_with("shorter than the minimum of 10s"));
+
+        let config = Configuration::from_reader(
+            "command: [sleep, \"0.1\"]\nmin-duration: 50ms\nmax-duration: 10s".as_bytes(),
+        )
+        .unwrap();
+        assert!(
+            run_test_with_options(&config, &RunOptions::default())
+                .unwrap()
+                .passed
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_resource_usage() {
+        let input = r#"
+tests:
+  - command: [sh, -c, "i=0; while [ $i -lt 100000 ]; do i=$((i + 1)); done"]
+    max-memory: 1K
+    max-cpu-time: 1ms
+  - command: "true"
+    max-memory: 1G
+    max-cpu-time: 10s
+  - command: "true"
+    timeout: 10s
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        assert!(!report.passed);
+        assert_eq!(report.failures.len(), 2, "{:?}", report.failures);
+        assert!(report.failures[0].starts_with("The process used "));
+        assert!(report.failures[0].ends_with(" of memory, more than the maximum of 1.0 KiB"));
+        assert!(report.failures[1].ends_with(" of CPU time, more than the maximum of 1ms"));
+        let usage = report.resource_usage.unwrap();
+        assert!(usage.max_rss > 1024);
+        assert!(usage.cpu_time() > Duration::from_millis(1));
+
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+        // Processes with a timeout are polled instead of waited for.
+        let report = run_test(&suite.tests[2]).unwrap();
+        assert!(report.resource_usage.unwrap().max_rss > 0);
+
+        let input = "command: \"true\"\nrunner: ssh\nssh: {host: h}\nmax-memory: 1M";
+        let error = Configuration::from_reader(input.as_bytes()).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("only be used with the local runner"));
+    }
+
+    #[test]
+    fn test_run_merge_output() {
+        let input = r#"
+script: |
+  echo one
+  sleep 0.1
+  echo two >&2
+  sleep 0.1
+  echo three
+merge-output: true
+stdout: "one\ntwo\nthree\n"
+stderr: ""
+"#;
+        let config = Configuration::from_reader(input.as_bytes()).unwrap();
+        let report = run_test_with_options(&config, &RunOptions::default()).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+    }
+
+    #[test]
+    fn test_run_verbose() {
+        // The output is still captured while it is shown.
+        let input =
+            "command: [sh, -c, \"echo out; printf err >&2\"]\nstdout: \"out\\n\"\nstderr: err";
+        let options = RunOptions {
+            verbose: true,
+            ..RunOptions::default()
+        };
+        for pty in [false, true] {
+            let mut config = Configuration::from_reader(input.as_bytes()).unwrap();
+            config.pty = pty;
+            if pty {
+                config.stdout = Some("out\nerr".to_string());
+                config.stderr = None;
+            }
+            let report = run_test_with_options(&config, &options).unwrap();
+            assert!(report.passed, "{:?}", report.failures);
+        }
+    }
+
+    #[test]
+    fn test_run_stdin_file() {
+        let input = "command: [head, -n, \"1\"]\nstdin-file: Cargo.toml\nstdout: \"[package]\\n\"";
+        for pty in [false, true] {
+            let mut config = Configuration::from_reader(input.as_bytes()).unwrap();
+            config.pty = pty;
+            let mut log = capture();
+            let result = run(&config, &mut log).unwrap();
+            assert!(result, "{}", String::from_utf8_lossy(&log.into_inner()));
+        }
+
+        let input = "command: [cat]\nstdin: x\nstdin-file: Cargo.toml";
+        assert!(Configuration::from_reader(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_run_stdin_not_read() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: "true"
+stdin: "foo bar baz\n"
+"#,
+        )
+        .unwrap();
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(result);
+    }
+
+    #[test]
+    fn test_run_env() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "echo $SMOKERS_TEST"]
+env:
+  SMOKERS_TEST: foo
+stdout: "foo\n"
+"#,
+        )
+        .unwrap();
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(result);
+    }
+
+    #[test]
+    fn test_run_cwd() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: pwd
+cwd: /
+stdout: "/\n"
+"#,
+        )
+        .unwrap();
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(result);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_expected_signal() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "kill -TERM $$"]
+expected-signal: SIGTERM
+"#,
+        )
+        .unwrap();
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(result);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_unexpected_signal() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "kill -KILL $$"]
+expected-signal: SIGTERM
+"#,
+        )
+        .unwrap();
+        let mut capture = capture();
+        let result = run(&config, &mut capture).unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains("The process died due to SIGKILL"),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_expected_signal_but_exited() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: "true"
+expected-signal: SEGV
+"#,
+        )
+        .unwrap();
+        let result = run(&config, &mut discard()).unwrap();
+        assert!(!result);
+    }
+
+    #[test]
+    fn test_run_stdout_file() {
+        let dir = std::env::temp_dir().join(format!("smokers-golden-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("expected.txt"), "foo\n").unwrap();
+        std::fs::write(dir.join("expected-err.txt"), "bar\n").unwrap();
+        let path = dir.join("test.yaml");
+        std::fs::write(
+            &path,
+            r#"
+tests:
+  good:
+    command: [sh, -c, "echo foo; echo bar >&2"]
+    stdout-file: expected.txt
+    stderr-file: expected-err.txt
+  bad:
+    command: [echo, bar]
+    stdout-file: expected.txt
+"#,
+        )
+        .unwrap();
+        let suite = Suite::from_path(&path).unwrap();
+        let good = run(&suite.tests[0], &mut discard()).unwrap();
+        let mut capture = capture();
+        let bad = run(&suite.tests[1], &mut capture).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(good);
+        assert!(!bad);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains("expected.txt\n+++ actual stdout\n@@ -1 +1 @@\n-foo\n+bar\n"),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_stdout_file_missing() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [echo, foo]
+stdout-file: /nonexistent/expected.txt
+"#,
+        )
+        .unwrap();
+        let mut capture = capture();
+        let result = run(&config, &mut capture).unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains("The golden file /nonexistent/expected.txt could not be read: "),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_suite_parallel_keeps_order() {
+        let input = r#"
+tests:
+  slow:
+    command: [sleep, "1"]
+  slower:
+    command: [sleep, "1"]
+  fast:
+    command: "false"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let options = RunOptions {
+            jobs: 3,
+            ..RunOptions::default()
+        };
+        let mut capture = capture();
+        let start = std::time::Instant::now();
+        let result = run_suites(
+            &[suite],
+            &options,
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_millis(1900));
+        assert!(!result);
+        let output = without_durations(&capture.into_inner());
+        assert!(
+            output.starts_with("slow: passed\nslower: passed\nfast: failed\n"),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let original: Vec<usize> = (0..20).collect();
+        let mut a = original.clone();
+        let mut b = original.clone();
+        shuffle(&mut a, 42);
+        shuffle(&mut b, 42);
+        assert_eq!(a, b);
+        assert_ne!(a, original);
+        a.sort_unstable();
+        assert_eq!(a, original);
+        let mut c = original.clone();
+        shuffle(&mut c, 43);
+        assert_ne!(b, c);
+    }
+
+    #[test]
+    fn test_run_suites_depends_on() {
+        let dir = std::env::temp_dir().join(format!("smokers-depends-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = r#"
+tests:
+  check:
+    command: [cat, artifact]
+    stdout: built
+    depends-on: build
+    tags: check
+  build:
+    command: [sh, -c, "sleep 0.2; printf built > artifact"]
+  deploy:
+    command: "true"
+    depends-on: broken
+  broken:
+    command: "false"
+"#;
+        let load = || {
+            let suite = Suite::from_reader(input.as_bytes()).unwrap();
+            Suite {
+                tests: suite
+                    .tests
+                    .into_iter()
+                    .map(|test| Configuration {
+                        cwd: Some(dir.clone()),
+                        ..test
+                    })
+                    .collect(),
+                ..suite
+            }
+        };
+        let options = RunOptions {
+            jobs: 4,
+            ..RunOptions::default()
+        };
+        let mut capture = capture();
+        let result = run_suites(
+            &[load()],
+            &options,
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        assert!(!result);
+        let output = without_durations(&capture.into_inner());
+        assert!(
+            output.starts_with(
+                "build: passed\ncheck: passed\nbroken: failed\nUnexpected exit code 1, expected 0\nstdout: \"\"\nstderr: \"\"\ndeploy: skipped (prerequisite broken didn't pass)\n"
+            ),
+            "output: {:?}",
+            output
+        );
+
+        // Prerequisites run even if they aren't selected.
+        std::fs::remove_file(dir.join("artifact")).unwrap();
+        let options = RunOptions {
+            tags: vec!["check".to_string()],
+            ..RunOptions::default()
+        };
+        let mut out = self::capture();
+        let result = run_suites(
+            &[load()],
+            &options,
+            &mut report::HumanReporter::new(&mut out),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result.unwrap());
+        let output = without_durations(&out.into_inner());
+        assert!(
+            output.starts_with("build: passed\ncheck: passed\n"),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_suites_skip() {
+        let input = r#"
+before-all:
+  - "false"
+tests:
+  skipped:
+    command: "false"
+    skip: not ready yet
+  missing:
+    command: smokers-missing-binary
+    skip-if:
+      missing-binary: smokers-missing-binary
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let mut capture = capture();
+        let result = run_suites(
+            &[suite],
+            &RunOptions::default(),
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        // The before-all commands don't run for skipped tests.
+        assert!(result);
+        let output = without_durations(&capture.into_inner());
+        assert!(
+            output.starts_with("skipped: skipped (not ready yet)\nmissing: skipped (smokers-missing-binary is not installed)\n\n2 tests: 0 passed, 0 failed, 2 skipped"),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_suites_missing_binary() {
+        let input = r#"
+tests:
+  missing:
+    command: smokers-missing-binary
+  present:
+    command: "true"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let mut capture = capture();
+        let result = run_suites(
+            &[suite],
+            &RunOptions::default(),
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        assert!(!result);
+        let output = without_durations(&capture.into_inner());
+        assert!(
+            output.contains("The command [\"smokers-missing-binary\"] could not be started: "),
+            "output: {:?}",
+            output
+        );
+        assert!(
+            output.contains("2 tests: 1 passed, 1 failed"),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_suites_tag_filter() {
+        let input = r#"
+tests:
+  fast:
+    command: "true"
+    tags: [fast]
+  network:
+    command: "true"
+    tags: [fast, network]
+  slow:
+    command: "true"
+    tags: slow
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let options = RunOptions {
+            tags: vec!["fast".to_string()],
+            skip_tags: vec!["network".to_string()],
+            ..RunOptions::default()
+        };
+        let mut capture = capture();
+        let result = run_suites(
+            &[suite],
+            &options,
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        assert!(result);
+        let output = without_durations(&capture.into_inner());
+        assert_eq!(output, "fast: passed\nNo errors.\n");
+    }
+
+    #[test]
+    fn test_list_tests() {
+        let input = r#"
+tests:
+  fast:
+    command: "true"
+    tags: [fast]
+  slow:
+    command: "true"
+    tags: slow
+"#;
+        let mut suite = Suite::from_reader(input.as_bytes()).unwrap();
+        suite.path = Some("tests.yaml".into());
+        let options = RunOptions {
+            skip_tags: vec!["slow".to_string()],
+            ..RunOptions::default()
+        };
+        let mut capture = capture();
+        list_tests(&[suite], &options, &mut capture).unwrap();
+        let o = capture.into_inner();
+        assert_eq!(String::from_utf8_lossy(&o), "tests.yaml::fast\n");
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let input = r#"
+before-all:
+  - [./start.sh]
+tests:
+  hello:
+    command: [echo, "hello world", "it's"]
+    cwd: /tmp
+    clean-env: true
+    pass-env: [PATH, HOME]
+    env:
+      GREETING: hi there
+    setup:
+      - [touch, marker]
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let mut capture = capture();
+        dry_run(&[suite], &RunOptions::default(), &mut capture).unwrap();
+        let o = capture.into_inner();
+        assert_eq!(
+            String::from_utf8_lossy(&o),
+            "before-all: ./start.sh\n\
+             hello\n  \
+             cwd: /tmp\n  \
+             clean-env: passing PATH HOME\n  \
+             env: GREETING='hi there'\n  \
+             setup: touch marker\n  \
+             command: echo 'hello world' 'it'\\''s'\n"
+        );
+    }
+
+    #[test]
+    fn test_run_suites_fail_fast() {
+        let input = r#"
+tests:
+  first:
+    command: "true"
+  second:
+    command: "false"
+  third:
+    command: "true"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let options = RunOptions {
+            fail_fast: true,
+            ..RunOptions::default()
+        };
+        let mut capture = capture();
+        let result = run_suites(
+            &[suite],
+            &options,
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(output.contains("second: failed"), "output: {:?}", output);
+        assert!(!output.contains("third"), "output: {:?}", output);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_suites_artifacts_dir() {
+        let dir = std::env::temp_dir().join(format!("smokers-artifacts-{}", std::process::id()));
+        let input = r#"
+tests:
+  passing:
+    command: "true"
+  failing/one:
+    command: [sh, -c, "echo $GREETING; cat; echo err >&2; echo data > file; exit 1"]
+    env:
+      GREETING: hi there
+    stdin: "in\n"
+    sandbox: true
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let options = RunOptions {
+            artifacts_dir: Some(dir.clone()),
+            ..RunOptions::default()
+        };
+        let mut capture = capture();
+        let result = run_suites(
+            &[suite],
+            &options,
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        let artifacts = dir.join("failing_one");
+        let read = |name: &str| std::fs::read_to_string(artifacts.join(name)).unwrap();
+        let (stdout, stderr, config, result_json, file) = (
+            read("stdout"),
+            read("stderr"),
+            read("config.txt"),
+            read("result.json"),
+            read("sandbox/file"),
+        );
+        let passing = dir.join("passing").exists();
+        let repro = std::process::Command::new(artifacts.join("repro.sh"))
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!result);
+        assert!(!passing);
+        assert_eq!(stdout, "hi there\nin\n");
+        assert_eq!(repro.stdout, stdout.as_bytes());
+        assert_eq!(repro.status.code(), Some(1));
+        assert_eq!(stderr, "err\n");
+        assert!(config.contains("sandbox: true"), "{}", config);
+        assert!(result_json.contains("\"duration\""), "{}", result_json);
+        assert_eq!(file, "data\n");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_suites_artifacts_of_final_report() {
+        let dir = std::env::temp_dir().join(format!("smokers-final-{}", std::process::id()));
+        let marker = dir.join("marker");
+        std::fs::create_dir_all(&dir).unwrap();
+        // The first run fails and the second one passes.
+        let flaky = format!(
+            "tests:\n  flaky:\n    command: [sh, -c, 'test -e {0} || {{ touch {0}; exit 1; }}']\n",
+            marker.display()
+        );
+        let hooked = "after-each: [[\"false\"]]\ntests:\n  hooked:\n    command: \"true\"\n";
+        let suites = [
+            Suite::from_reader(flaky.as_bytes()).unwrap(),
+            Suite::from_reader(hooked.as_bytes()).unwrap(),
+        ];
+        let artifacts = dir.join("artifacts");
+        let options = RunOptions {
+            artifacts_dir: Some(artifacts.clone()),
+            repeat: 2,
+            ..RunOptions::default()
+        };
+        let mut capture = capture();
+        let result = run_suites(
+            &suites,
+            &options,
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        let flaky = std::fs::read_to_string(artifacts.join("flaky/result.json"));
+        let hooked = std::fs::read_to_string(artifacts.join("hooked/result.json"));
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!result);
+        assert!(flaky.unwrap().contains("Failed in 1 of 2 runs"));
+        assert!(hooked.unwrap().contains("after-each"));
+    }
+
+    #[test]
+    fn test_run_suites_update_expected() {
+        let dir = std::env::temp_dir().join(format!("smokers-update-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tests.yaml");
+        let yaml = r#"tests:
+  # Outdated.
+  - command: [printf, "one\ntwo\n"]
+    stdout: |
+      one
+  - command: [sh, -c, "echo same; exit 1"]
+    stdout: "same\n"
+  - command: [sh, -c, "echo 42; echo err >&2"]
+    stderr: ""
+    stdout: "{{number}}\n"
+"#;
+        std::fs::write(&path, yaml).unwrap();
+        let suite = Suite::from_path(&path).unwrap();
+        let options = RunOptions {
+            update_expected: true,
+            ..RunOptions::default()
+        };
+        let mut capture = capture();
+        let result = run_suites(
+            &[suite],
+            &options,
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        let updated = std::fs::read_to_string(&path).unwrap();
+        let rerun = run_suites(
+            &[Suite::from_path(&path).unwrap()],
+            &RunOptions::default(),
+            &mut report::HumanReporter::new(&mut discard()),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!result);
+        assert!(!rerun);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains("Updated the expected stdout in"),
+            "{}",
+            output
+        );
+        assert_eq!(
+            updated,
+            yaml.replace("      one\n", "      one\n      two\n")
+                .replace("stderr: \"\"", "stderr: \"err\\n\"")
+        );
+    }
+
+    #[test]
+    fn test_repro_script() {
+        let input = r#"
+name: greeting
+command: [echo, "it's me"]
+clean-env: true
+pass-env: [PATH]
+env:
+  GREETING: hi
+stdin-file: /dev/zero
+"#;
+        let config = Configuration::from_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            repro_script(&config, Path::new("/tmp/a b")),
+            "#!/bin/sh\n\
+             # Reproduces the test greeting.\n\
+             cd '/tmp/a b' || exit\n\
+             exec env -i ${PATH+\"PATH=$PATH\"} GREETING=hi echo 'it'\\''s me' < /dev/zero\n"
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_suites_cache() {
+        let dir = std::env::temp_dir().join(format!("smokers-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let counter = dir.join("counter");
+        let suite = |greeting: &str| {
+            let input = format!(
+                "tests:\n  \
+                   counted:\n    \
+                     command: [sh, -c, 'echo run >> {}']\n    \
+                     env: {{GREETING: {}}}\n  \
+                   failing:\n    \
+                     command: \"false\"\n",
+                counter.display(),
+                greeting
+            );
+            Suite::from_reader(input.as_bytes()).unwrap()
+        };
+        let options = RunOptions {
+            cache_dir: Some(dir.join("cache")),
+            ..RunOptions::default()
+        };
+        let run = |suite: Suite| {
+            let mut capture = capture();
+            run_suites(
+                &[suite],
+                &options,
+                &mut report::HumanReporter::new(&mut capture),
+            )
+            .unwrap();
+            String::from_utf8(capture.into_inner()).unwrap()
+        };
+        let first = run(suite("hi"));
+        let second = run(suite("hi"));
+        let changed = run(suite("hello"));
+        let runs = std::fs::read_to_string(&counter).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(first.contains("counted: passed"), "{}", first);
+        assert!(second.contains("counted: cached-pass\n"), "{}", second);
+        assert!(second.contains("failing: failed"), "{}", second);
+        assert!(changed.contains("counted: passed"), "{}", changed);
+        assert_eq!(runs, "run\nrun\n");
+    }
+
+    #[test]
+    fn test_run_test_with_retries() {
+        let dir = std::env::temp_dir().join(format!("smokers-retries-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Fails on the first attempt and passes on the second one.
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "test -e marker || { touch marker; exit 1; }"]
+"#,
+        )
+        .unwrap();
+        let config = Configuration {
+            cwd: Some(dir.clone()),
+            ..config
+        };
+        let options = RunOptions {
+            retries: 2,
+            ..RunOptions::default()
+        };
+        let flaky = run_test_with_retries(&config, &options).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(flaky.passed);
+        assert_eq!(flaky.attempts, 2);
+        assert!(flaky.is_flaky());
+
+        let config: Configuration = serde_yaml::from_str("command: \"false\"").unwrap();
+        let failed = run_test_with_retries(&config, &options).unwrap();
+        assert!(!failed.passed);
+        assert_eq!(failed.attempts, 3);
+    }
+
+    #[test]
+    fn test_run_expected_failure() {
+        let config =
+            Configuration::from_reader("command: \"false\"\nexpected-failure: true".as_bytes())
+                .unwrap();
+        let report = run_test_with_options(&config, &RunOptions::default()).unwrap();
+        assert!(report.passed);
+        assert!(report.expected_failure);
+        assert_eq!(report.failures, vec!["Unexpected exit code 1, expected 0"]);
+
+        let config =
+            Configuration::from_reader("command: \"true\"\nexpected-failure: true".as_bytes())
+                .unwrap();
+        let report = run_test_with_options(&config, &RunOptions::default()).unwrap();
+        assert!(!report.passed);
+        assert_eq!(
+            report.failures,
+            vec!["The test passed although it is expected to fail"]
+        );
+    }
+
+    #[test]
+    fn test_run_test_repeated() {
+        let dir = std::env::temp_dir().join(format!("smokers-repeat-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Fails on every second run.
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [sh, -c, "if test -e marker; then rm marker; else touch marker; exit 1; fi"]
+"#,
+        )
+        .unwrap();
+        let config = Configuration {
+            cwd: Some(dir.clone()),
+            ..config
+        };
+        let options = RunOptions {
+            repeat: 4,
+            ..RunOptions::default()
+        };
+        let report = run_test_repeated(&config, &options).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!report.passed);
+        assert_eq!((report.runs, report.failed_runs), (4, 2));
+        assert!(report.is_inconsistent());
+        assert_eq!(report.failures[0], "Failed in 2 of 4 runs");
+
+        let config: Configuration = serde_yaml::from_str("command: \"true\"").unwrap();
+        let report = run_test_repeated(&config, &options).unwrap();
+        assert!(report.passed);
+        assert_eq!((report.runs, report.failed_runs), (4, 0));
+        assert!(!report.is_inconsistent());
+    }
+
+    #[test]
+    fn test_run_setup_teardown() {
+        let dir = std::env::temp_dir().join(format!("smokers-setup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+setup:
+  - [sh, -c, "echo foo > input"]
+command: [cat, input]
+teardown:
+  - [rm, input]
+stdout: "foo\n"
+"#,
+        )
+        .unwrap();
+        let config = Configuration {
+            cwd: Some(dir.clone()),
+            ..config
+        };
+        let result = run(&config, &mut discard()).unwrap();
+        let leftover = dir.join("input").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result);
+        assert!(!leftover);
+    }
+
+    #[test]
+    fn test_run_setup_failure() {
+        let dir = std::env::temp_dir().join(format!("smokers-setup-fail-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+setup:
+  - "false"
+command: [touch, command-ran]
+teardown:
+  - [touch, teardown-ran]
+"#,
+        )
+        .unwrap();
+        let config = Configuration {
+            cwd: Some(dir.clone()),
+            ..config
+        };
+        let report = run_test(&config).unwrap();
+        let command_ran = dir.join("command-ran").exists();
+        let teardown_ran = dir.join("teardown-ran").exists();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(!report.passed);
+        assert!(!command_ran);
+        assert!(teardown_ran);
+        assert!(
+            report.failures[0].starts_with(r#"Setup command ["false"] failed"#),
+            "failures: {:?}",
+            report.failures
+        );
+    }
+
+    #[test]
+    fn test_run_suites_before_after_all() {
+        let dir = std::env::temp_dir().join(format!("smokers-before-all-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.yaml");
+        let log = dir.join("log");
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+before-all:
+  - [sh, -c, "echo started >> {log}"]
+after-all:
+  - [sh, -c, "echo stopped >> {log}"]
+tests:
+  first:
+    command: [cat, {log}]
+    stdout: "started\n"
+  second:
+    command: [cat, {log}]
+    stdout: "started\n"
+"#,
+                log = log.display()
+            ),
+        )
+        .unwrap();
+        let suite = Suite::from_path(&path).unwrap();
+        let options = RunOptions {
+            jobs: 2,
+            ..RunOptions::default()
+        };
+        let result = run_suites(
+            &[suite],
+            &options,
+            &mut report::HumanReporter::new(discard()),
+        )
+        .unwrap();
+        let log = std::fs::read_to_string(dir.join("log")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result);
+        assert_eq!(log, "started\nstopped\n");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_services() {
+        let dir = std::env::temp_dir().join(format!("smokers-services-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = format!(
+            r#"
+tests:
+  - cwd: {dir}
+    services:
+      server:
+        command: [sh, -c, "trap 'echo stopped >> events; exit' TERM; echo started >> events; touch ready; while :; do sleep 0.05; done"]
+        wait-for:
+          file: ready
+    command: [cat, events]
+    stdout: "started\n"
+  - services:
+      crashing:
+        command: [sh, -c, "echo bad config; exit 3"]
+        wait-for:
+          port: 1
+    command: "true"
+"#,
+            dir = dir.display()
+        );
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&suite.tests[0]).unwrap();
+        let events = std::fs::read_to_string(dir.join("events")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+        assert_eq!(events, "started\nstopped\n");
+
+        let report = run_test(&suite.tests[1]).unwrap();
+        assert_eq!(
+            report.failures,
+            [
+                "Service crashing exited (exit status: 3)",
+                "Log of service crashing:",
+                "bad config"
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_run_suites_services() {
+        let dir =
+            std::env::temp_dir().join(format!("smokers-suite-services-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let events = dir.join("events");
+        let input = format!(
+            r#"
+services:
+  server:
+    command: [sh, -c, "trap 'echo stopped >> {events}; exit' TERM; echo started >> {events}; while :; do sleep 0.05; done"]
+    wait-for:
+      file: {events}
+tests:
+  first:
+    command: [cat, {events}]
+    stdout: "started\n"
+  second:
+    command: [cat, {events}]
+    stdout: "started\n"
+"#,
+            events = events.display()
+        );
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let options = RunOptions {
+            jobs: 2,
+            ..RunOptions::default()
+        };
+        let result = run_suites(
+            &[suite],
+            &options,
+            &mut report::HumanReporter::new(discard()),
+        )
+        .unwrap();
+        let events = std::fs::read_to_string(&events).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result);
+        assert_eq!(events, "started\nstopped\n");
+    }
+
+    #[test]
+    fn test_run_suites_before_after_each() {
+        let dir = std::env::temp_dir().join(format!("smokers-each-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("log");
+        let input = format!(
+            r#"
+before-each:
+  - [sh, -c, "echo before >> {log}"]
+after-each:
+  - [sh, -c, "echo after >> {log}"]
+tests:
+  first:
+    setup:
+      - [sh, -c, "echo setup >> {log}"]
+    command: [sh, -c, "echo first >> {log}"]
+    teardown:
+      - [sh, -c, "echo teardown >> {log}"]
+  second:
+    command: [sh, -c, "echo second >> {log}"]
+"#,
+            log = log.display()
+        );
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let result = run_suites(
+            &[suite],
+            &RunOptions::default(),
+            &mut report::HumanReporter::new(discard()),
+        )
+        .unwrap();
+        let log = std::fs::read_to_string(&log).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(result);
+        assert_eq!(
+            log,
+            "before\nsetup\nfirst\nteardown\nafter\nbefore\nsecond\nafter\n"
+        );
+
+        let input =
+            "before-each: [\"false\"]\nafter-each: [\"false\"]\ntests:\n  - command: \"true\"\n";
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let report =
+            run_suite_test(&SuiteFixtures::new(&suite, 1), 0, &RunOptions::default()).unwrap();
+        assert!(!report.passed);
+        assert_eq!(report.failures.len(), 2);
+        assert!(report.failures[0].starts_with(r#"before-each command ["false"] failed"#));
+        assert!(report.failures[1].starts_with(r#"after-each command ["false"] failed"#));
+    }
+
+    #[test]
+    fn test_run_suites_before_all_failure() {
+        let input = r#"
+before-all:
+  - "false"
+tests:
+  first:
+    command: "true"
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        let mut capture = capture();
+        let result = run_suites(
+            &[suite],
+            &RunOptions::default(),
+            &mut report::HumanReporter::new(&mut capture),
+        )
+        .unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains(r#"before-all command ["false"] failed"#),
+            "output: {:?}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_run_stdout_mismatch_diff() {
+        let config: Configuration = serde_yaml::from_str(
+            r#"
+command: [printf, "foo\\nbar\\nbaz\\n"]
+stdout: "foo\nqux\nbaz\n"
+"#,
+        )
+        .unwrap();
+        let mut capture = capture();
+        let result = run(&config, &mut capture).unwrap();
+        assert!(!result);
+        let o = capture.into_inner();
+        let output = String::from_utf8_lossy(&o);
+        assert!(
+            output.contains(
+                "stdout differs from the expected stdout:\n--- expected stdout\n+++ actual stdout\n@@ -1,3 +1,3 @@\n foo\n-qux\n+bar\n baz\n"
+            ),
+            "output: {:?}",
+            output
+        );
+    }
+}
+
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+
+    #[test]
+    fn test_run_cmd_script() {
+        let input = r#"
+script: echo hello& exit 3
+stdout: "hello\n"
+exit-code: 3
+"#;
+        let config = Configuration::from_reader(input.as_bytes()).unwrap();
+        let report = run_test(&config).unwrap();
+        assert!(report.passed, "{:?}", report.failures);
+    }
+
+    #[test]
+    fn test_describe_exit_code() {
+        assert_eq!(describe_exit_code(1), "1");
+        assert_eq!(describe_exit_code(-1073741819), "-1073741819 (0xC0000005)");
+    }
+}
@@ -0,0 +1,57 @@
+//! Temporary directories that tests run in.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// A fresh directory that is removed with all its contents when dropped.
+#[derive(Debug)]
+pub struct Sandbox {
+    path: PathBuf,
+    keep: AtomicBool,
+}
+
+impl Sandbox {
+    /// Creates a new empty directory below the temporary directory of the
+    /// system.
+    pub fn create() -> std::io::Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let path = std::env::temp_dir().join(format!(
+                "smokers-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            match std::fs::create_dir(&path) {
+                Ok(()) => {
+                    return Ok(Self {
+                        path,
+                        keep: AtomicBool::new(false),
+                    })
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Retains the directory instead of removing it, returning its path.
+    pub fn keep(&self) -> PathBuf {
+        self.keep.store(true, Ordering::Relaxed);
+        self.path.clone()
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        if !*self.keep.get_mut() {
+            // Whatever the test left behind is not worth failing over.
+            if let Err(e) = std::fs::remove_dir_all(&self.path) {
+                tracing::warn!("Failed to remove {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Background processes that run while tests are executed.
+
+use crate::config::Service;
+use crate::process;
+use crate::sandbox::Sandbox;
+use crate::wait;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/// The number of lines at the end of a service log that are reported.
+const LOG_LINES: usize = 50;
+
+/// A started service. It is stopped when dropped.
+struct RunningService {
+    name: String,
+    child: Child,
+    /// The directory holding the log file.
+    dir: Sandbox,
+}
+
+impl RunningService {
+    fn log_path(&self) -> PathBuf {
+        self.dir.path().join("log")
+    }
+
+    /// Describes how the service exited, if it did.
+    fn exited(&mut self) -> Option<String> {
+        match self.child.try_wait() {
+            Ok(Some(status)) => Some(format!("Service {} exited ({})", self.name, status)),
+            Ok(None) => None,
+            Err(e) => Some(format!("Service {} could not be checked: {}", self.name, e)),
+        }
+    }
+
+    /// The end of the combined stdout and stderr of the service.
+    fn log(&self) -> Vec<String> {
+        let log = std::fs::read(self.log_path()).unwrap_or_default();
+        let log = String::from_utf8_lossy(&log);
+        let lines: Vec<&str> = log.lines().collect();
+        let mut report = vec![format!("Log of service {}:", self.name)];
+        let start = lines.len().saturating_sub(LOG_LINES);
+        if start > 0 {
+            report.push(format!("({} earlier lines omitted)", start));
+        }
+        report.extend(lines[start..].iter().map(|line| line.to_string()));
+        report
+    }
+}
+
+impl Drop for RunningService {
+    fn drop(&mut self) {
+        // A service that can't be stopped anymore has exited already.
+        match process::terminate(&mut self.child, cfg!(unix)) {
+            Ok(status) => tracing::debug!(service = %self.name, %status, "stopped"),
+            Err(e) => tracing::debug!(service = %self.name, "could not be stopped: {}", e),
+        }
+    }
+}
+
+/// The running services of a test or suite, stopped when dropped.
+#[derive(Default)]
+pub(crate) struct Services {
+    running: Vec<RunningService>,
+}
+
+impl Services {
+    /// Starts the `services` in order, each prepared by `process`, and waits
+    /// for each to be ready before starting the next. Files are awaited
+    /// relative to `dir`. Returns the reason and the logs of the services
+    /// if one of them failed; the services started so far are stopped.
+    pub(crate) fn start(
+        services: &[(String, Service)],
+        mut process: impl FnMut(&(String, Vec<String>)) -> Command,
+        dir: &Path,
+    ) -> Result<Self, Vec<String>> {
+        let mut started = Self::default();
+        for (name, service) in services {
+            let log_dir = Sandbox::create()
+                .map_err(|e| vec![format!("Service {} could not be started: {}", name, e)])?;
+            let mut command = process(&service.command);
+            tracing::debug!(service = %name, command = ?command, "starting");
+            let child = match spawn(&mut command, &log_dir.path().join("log")) {
+                Ok(child) => child,
+                Err(e) => {
+                    let mut failures =
+                        vec![format!("Service {} could not be started: {}", name, e)];
+                    failures.extend(started.logs());
+                    return Err(failures);
+                }
+            };
+            started.running.push(RunningService {
+                name: name.clone(),
+                child,
+                dir: log_dir,
+            });
+            let service_process = started.running.last_mut().expect("just started");
+            let failure = wait::wait_unless(&service.wait_for, dir, || service_process.exited());
+            if let Some(failure) = failure {
+                let mut failures = vec![failure];
+                failures.extend(started.logs());
+                return Err(failures);
+            }
+        }
+        Ok(started)
+    }
+
+    /// The ends of the logs of all services.
+    pub(crate) fn logs(&self) -> Vec<String> {
+        self.running.iter().flat_map(RunningService::log).collect()
+    }
+}
+
+impl Drop for Services {
+    /// Stops the services in the reverse order they were started.
+    fn drop(&mut self) {
+        while let Some(service) = self.running.pop() {
+            drop(service);
+        }
+    }
+}
+
+/// Spawns `command` with its output written to the file at `log`, in a
+/// process group of its own so that its children can be stopped with it.
+fn spawn(command: &mut Command, log: &Path) -> std::io::Result<Child> {
+    let file = std::fs::File::create(log)?;
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command, 0);
+    process::spawn(
+        command
+            .stdin(Stdio::null())
+            .stdout(file.try_clone()?)
+            .stderr(file),
+    )
+}
@@ -0,0 +1,133 @@
+//! Naming and parsing of the signals a process can be terminated with.
+
+use serde::de::Error as SerdeError;
+use serde::Deserialize;
+
+/// A signal identified by its platform specific number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signal(pub i32);
+
+#[cfg(unix)]
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+#[cfg(not(unix))]
+const SIGNALS: &[(&str, i32)] = &[];
+
+impl Signal {
+    /// The name of the signal without the `SIG` prefix, if known.
+    pub fn name(&self) -> Option<&'static str> {
+        SIGNALS
+            .iter()
+            .find(|(_, number)| *number == self.0)
+            .map(|(name, _)| *name)
+    }
+
+    /// The signal that terminated the process, if any.
+    #[cfg(unix)]
+    pub fn from_status(status: &std::process::ExitStatus) -> Option<Self> {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal().map(Signal)
+    }
+
+    /// The signal that terminated the process, if any.
+    #[cfg(not(unix))]
+    pub fn from_status(_status: &std::process::ExitStatus) -> Option<Self> {
+        None
+    }
+}
+
+impl std::fmt::Display for Signal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "SIG{} ({})", name, self.0),
+            None => write!(f, "signal {}", self.0),
+        }
+    }
+}
+
+impl std::str::FromStr for Signal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(number) = s.parse() {
+            return Ok(Signal(number));
+        }
+
+        let upper = s.to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        SIGNALS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, number)| Signal(*number))
+            .ok_or_else(|| format!("Unknown signal {:?}", s))
+    }
+}
+
+impl<'a> Deserialize<'a> for Signal {
+    fn deserialize<D: serde::Deserializer<'a>>(d: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Number(i32),
+            Name(String),
+        }
+
+        match Repr::deserialize(d)? {
+            Repr::Number(number) => Ok(Signal(number)),
+            Repr::Name(name) => name.parse().map_err(D::Error::custom),
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!("SIGSEGV".parse(), Ok(Signal(libc::SIGSEGV)));
+        assert_eq!("term".parse(), Ok(Signal(libc::SIGTERM)));
+        assert_eq!("9".parse(), Ok(Signal(9)));
+        assert!("SIGFOO".parse::<Signal>().is_err());
+    }
+
+    #[test]
+    fn test_display_signal() {
+        assert_eq!(
+            Signal(libc::SIGKILL).to_string(),
+            format!("SIGKILL ({})", libc::SIGKILL)
+        );
+        assert_eq!(Signal(1000).to_string(), "signal 1000");
+    }
+}
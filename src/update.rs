@@ -0,0 +1,399 @@
+//! Updating the expected output in configuration files with the actual
+//! output of failing tests.
+//!
+//! serde_yaml doesn't keep the positions of values, so the value is located
+//! in the text of the file by its indentation. Only the replaced value
+//! changes, the formatting and comments of the rest of the file are kept.
+
+use crate::config::{ConfigFormat, Suite};
+use serde_yaml::Value;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Serializes the updates of tests running in parallel, which may be
+/// defined in the same file.
+static UPDATES: Mutex<()> = Mutex::new(());
+
+/// Replaces the value of `key`, `stdout` or `stderr`, of the test at `index`
+/// of `suite` with `actual` in the file the suite was loaded from, which is
+/// returned. Returns the reason if the value can't be updated.
+pub(crate) fn update_expected<'a>(
+    suite: &'a Suite,
+    index: usize,
+    key: &str,
+    actual: &str,
+) -> Result<&'a Path, String> {
+    let path = suite
+        .path
+        .as_deref()
+        .ok_or("the suite wasn't loaded from a file")?;
+    if ConfigFormat::from_path(path) != ConfigFormat::Yaml {
+        return Err("only YAML files can be updated".to_string());
+    }
+    let _lock = UPDATES.lock().unwrap_or_else(|e| e.into_inner());
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let own_index = index
+        .checked_sub(suite.tests.len().saturating_sub(count_tests(&text)?))
+        .ok_or("the test is defined in an included file")?;
+    let updated = replace(&text, own_index, key, actual)?;
+    std::fs::write(path, updated).map_err(|e| e.to_string())?;
+    Ok(path)
+}
+
+/// Where a test case is defined in a configuration file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    /// The file consists of a single test case.
+    Whole,
+    /// The test case is the item at this position of the `tests` list.
+    Item(usize),
+    /// The test case is the value at this position of the `tests` map.
+    Named(usize),
+}
+
+/// The test cases defined in the configuration file itself, in order.
+fn entries(value: &Value) -> Result<Vec<(Entry, &Value)>, String> {
+    match value.get("tests") {
+        None => Ok(vec![(Entry::Whole, value)]),
+        Some(Value::Sequence(tests)) => Ok(tests
+            .iter()
+            .enumerate()
+            .map(|(i, test)| (Entry::Item(i), test))
+            .collect()),
+        Some(Value::Mapping(tests)) => Ok(tests
+            .iter()
+            .enumerate()
+            .map(|(i, (_, test))| (Entry::Named(i), test))
+            .collect()),
+        Some(_) => Err("tests must be a list or a map of test cases".to_string()),
+    }
+}
+
+/// The number of test cases a test case with a matrix is expanded to.
+fn expanded_count(test: &Value) -> usize {
+    match test.get("matrix").and_then(Value::as_mapping) {
+        Some(matrix) => matrix
+            .iter()
+            .map(|(_, values)| values.as_sequence().map_or(1, Vec::len))
+            .product(),
+        None => 1,
+    }
+}
+
+/// The number of test cases defined in the configuration `text` itself,
+/// excluding those of included files.
+fn count_tests(text: &str) -> Result<usize, String> {
+    let value: Value = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+    Ok(entries(&value)?
+        .into_iter()
+        .map(|(_, test)| expanded_count(test))
+        .sum())
+}
+
+/// Replaces the value of `key` of the test case at `index` of the test
+/// cases defined in the configuration `text`.
+fn replace(text: &str, index: usize, key: &str, actual: &str) -> Result<String, String> {
+    let value: Value = serde_yaml::from_str(text).map_err(|e| e.to_string())?;
+    let mut remaining = index;
+    let (entry, test) = entries(&value)?
+        .into_iter()
+        .find(
+            |(_, test)| match remaining.checked_sub(expanded_count(test)) {
+                Some(rest) => {
+                    remaining = rest;
+                    false
+                }
+                None => true,
+            },
+        )
+        .ok_or("the test isn't defined in the file")?;
+    if test.get("matrix").is_some() {
+        return Err(
+            "the expectations of matrix tests are shared by all their combinations".to_string(),
+        );
+    }
+    if !test.get(key).is_some_and(Value::is_string) {
+        return Err(format!("the test has no expected {} in the file", key));
+    }
+
+    let (span, indent) =
+        locate(text, entry, key).ok_or("the value could not be located in the file")?;
+    let mut updated = String::with_capacity(text.len() + actual.len());
+    updated.push_str(&text[..span.start]);
+    updated.push_str(&render(actual, indent + 2));
+    updated.push_str(&text[span.end..]);
+
+    // Locating values by their indentation is no YAML parser, so only keep
+    // the result if it parses to the intended value.
+    let check: Value = serde_yaml::from_str(&updated).map_err(|e| e.to_string())?;
+    let updated_value = entries(&check)?
+        .into_iter()
+        .find(|(other, _)| *other == entry)
+        .and_then(|(_, test)| test.get(key).and_then(Value::as_str).map(String::from));
+    if updated_value.as_deref() != Some(actual) {
+        return Err("the value could not be located in the file".to_string());
+    }
+    Ok(updated)
+}
+
+/// A line of the configuration with the offset of its start.
+struct Line<'a> {
+    start: usize,
+    text: &'a str,
+}
+
+impl Line<'_> {
+    fn indent(&self) -> usize {
+        self.text.len() - self.text.trim_start_matches(' ').len()
+    }
+
+    /// Whether the line contains neither YAML content nor a comment.
+    fn is_blank(&self) -> bool {
+        self.text.trim().is_empty()
+    }
+
+    /// Whether the line contains YAML content.
+    fn is_content(&self) -> bool {
+        !self.is_blank() && !self.text.trim_start().starts_with('#')
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+}
+
+fn lines(text: &str) -> Vec<Line<'_>> {
+    let mut start = 0;
+    text.split_inclusive('\n')
+        .map(|raw| {
+            let line = Line {
+                start,
+                text: raw.trim_end_matches('\n').trim_end_matches('\r'),
+            };
+            start += raw.len();
+            line
+        })
+        .collect()
+}
+
+/// Locates the value of `key` of the test case `entry` in the block style
+/// configuration `text`. Returns the span of the value, starting right
+/// after the colon, and the indentation of the key.
+fn locate(text: &str, entry: Entry, key: &str) -> Option<(Range<usize>, usize)> {
+    let lines = lines(text);
+    let region = match entry {
+        Entry::Whole => &lines[..],
+        Entry::Item(index) | Entry::Named(index) => {
+            let tests = lines
+                .iter()
+                .position(|line| line.indent() == 0 && is_key(line.text, "tests"))?;
+            // Flow style tests are not supported.
+            let value = line_value(lines[tests].text, "tests").trim();
+            if !value.is_empty() && !value.starts_with('#') {
+                return None;
+            }
+            let block = &lines[tests + 1..];
+            let item_indent = block.iter().find(|line| line.is_content())?.indent();
+            let sequence = matches!(entry, Entry::Item(_));
+            let end = block
+                .iter()
+                .position(|line| {
+                    line.is_content()
+                        && (line.indent() < item_indent
+                            || (line.indent() == item_indent && sequence != is_item(line.text)))
+                })
+                .unwrap_or(block.len());
+            let block = &block[..end];
+            let items: Vec<usize> = block
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| line.is_content() && line.indent() == item_indent)
+                .map(|(i, _)| i)
+                .collect();
+            let start = *items.get(index)?;
+            let end = items.get(index + 1).copied().unwrap_or(block.len());
+            if sequence {
+                &block[start..end]
+            } else {
+                &block[start + 1..end]
+            }
+        }
+    };
+
+    // The keys of an item may start on the line of its dash.
+    let key_indent = match region.first() {
+        Some(first) if is_item(first.text) && first.text.trim() != "-" => {
+            let rest = &first.text[first.indent() + 1..];
+            first.indent() + 1 + rest.len() - rest.trim_start_matches(' ').len()
+        }
+        Some(first) if is_item(first.text) => {
+            region[1..].iter().find(|line| line.is_content())?.indent()
+        }
+        _ => region.iter().find(|line| line.is_content())?.indent(),
+    };
+    let position = region.iter().position(|line| {
+        line.text.len() > key_indent
+            && matches!(line.text[..key_indent].trim(), "" | "-")
+            && is_key(&line.text[key_indent..], key)
+    })?;
+    let key_line = &region[position];
+    let start = key_line.start + key_indent + key.len() + 1;
+    let value = line_value(&key_line.text[key_indent..], key).trim_start();
+    let block_scalar = value.starts_with('|') || value.starts_with('>');
+
+    // The value continues on the lines indented deeper than the key. The
+    // comments following a value that isn't a block scalar are kept.
+    let mut end = key_line.end();
+    for line in &region[position + 1..] {
+        if line.is_blank() || (!block_scalar && !line.is_content()) {
+            continue;
+        }
+        if line.indent() <= key_indent {
+            break;
+        }
+        end = line.end();
+    }
+    Some((start..end, key_indent))
+}
+
+/// Whether the `line` starts with the mapping key `key`.
+fn is_key(line: &str, key: &str) -> bool {
+    line.strip_prefix(key)
+        .and_then(|rest| rest.strip_prefix(':'))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with([' ', '\t']))
+}
+
+/// The text following the mapping key `key` at the start of `line`.
+fn line_value<'a>(line: &'a str, key: &str) -> &'a str {
+    &line[key.len() + 1..]
+}
+
+/// Whether the `line` is an item of a sequence.
+fn is_item(line: &str) -> bool {
+    let line = line.trim_start_matches(' ');
+    line == "-" || line.starts_with("- ")
+}
+
+/// Formats `text` as a YAML value following a key indented by `indent - 2`,
+/// as a literal block scalar if it spans multiple lines and can be
+/// represented as one.
+fn render(text: &str, indent: usize) -> String {
+    let body = text.strip_suffix('\n').unwrap_or(text);
+    let first_line = body.lines().find(|line| !line.is_empty()).unwrap_or("");
+    let literal = body.contains('\n')
+        && !body.ends_with('\n')
+        && !first_line.starts_with([' ', '\t'])
+        && !body
+            .chars()
+            .any(|c| c.is_control() && c != '\n' && c != '\t');
+    if !literal {
+        // JSON strings are valid double-quoted YAML scalars.
+        return format!(" {}", serde_json::Value::from(text));
+    }
+    let mut rendered = String::from(if text.ends_with('\n') { " |" } else { " |-" });
+    for line in body.split('\n') {
+        rendered.push('\n');
+        if !line.is_empty() {
+            rendered.push_str(&" ".repeat(indent));
+            rendered.push_str(line);
+        }
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_list() {
+        let text = "\
+# The tests of the greeting.
+tests:
+  - command: [echo, hello]
+    stdout: |
+      hi
+    # Keep this comment.
+    exit-code: 0
+  - name: second
+    stdout: \"x\"  # replaced with the value
+  - stdout: old
+";
+        let updated = replace(text, 0, "stdout", "hello\nworld\n").unwrap();
+        assert_eq!(
+            updated,
+            "\
+# The tests of the greeting.
+tests:
+  - command: [echo, hello]
+    stdout: |
+      hello
+      world
+    # Keep this comment.
+    exit-code: 0
+  - name: second
+    stdout: \"x\"  # replaced with the value
+  - stdout: old
+"
+        );
+        let updated = replace(&updated, 1, "stdout", "a\tb").unwrap();
+        assert!(updated.contains("  - name: second\n    stdout: \"a\\tb\"\n  - stdout: old\n"));
+        let updated = replace(&updated, 2, "stdout", "new\n\nlines").unwrap();
+        assert!(updated.ends_with("  - stdout: |-\n      new\n\n      lines\n"));
+    }
+
+    #[test]
+    fn test_replace_map() {
+        let text = "\
+before-all: [[true]]
+tests:
+  first:
+    stdout: |-
+      one
+
+      two
+    stderr: ''
+  second:
+    command: [echo]
+    stdout: >
+      folded
+      text
+
+after-all: [[true]]
+";
+        let updated = replace(text, 1, "stdout", "\n").unwrap();
+        assert!(
+            updated.ends_with("    command: [echo]\n    stdout: \"\\n\"\n\nafter-all: [[true]]\n"),
+            "{}",
+            updated
+        );
+        let updated = replace(&updated, 0, "stderr", "error\n").unwrap();
+        assert!(updated.contains("      two\n    stderr: \"error\\n\"\n  second:"));
+    }
+
+    #[test]
+    fn test_replace_single_test() {
+        let text = "command: [echo, hi]\nstdout: \"hello\\n\"\n# trailing comment\n";
+        let updated = replace(text, 0, "stdout", "hi\n").unwrap();
+        assert_eq!(
+            updated,
+            "command: [echo, hi]\nstdout: \"hi\\n\"\n# trailing comment\n"
+        );
+    }
+
+    #[test]
+    fn test_replace_unsupported() {
+        let text = "tests:\n  - matrix: {n: [1, 2]}\n    stdout: x\n  - command: [true]\n";
+        assert!(replace(text, 0, "stdout", "y").is_err());
+        assert_eq!(
+            replace(text, 2, "stdout", "y"),
+            Err("the test has no expected stdout in the file".to_string())
+        );
+        let text = "tests: [{stdout: x}]\n";
+        assert_eq!(
+            replace(text, 0, "stdout", "y"),
+            Err("the value could not be located in the file".to_string())
+        );
+    }
+}
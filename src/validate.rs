@@ -0,0 +1,215 @@
+//! Checks of configuration files beyond what parsing them catches, for
+//! `smokers validate`.
+
+use crate::config::{Configuration, FixtureFile, Suite};
+use std::path::Path;
+
+/// The problems of the tests of `suite` that would make them fail or
+/// behave other than intended, each prefixed with the name of the test.
+pub fn problems(suite: &Suite) -> Vec<String> {
+    let mut problems = vec![];
+    for (index, config) in suite.tests.iter().enumerate() {
+        let prefix = suite
+            .display_name(index)
+            .map_or_else(String::new, |name| format!("{}: ", name));
+        problems.extend(
+            test_problems(config)
+                .into_iter()
+                .map(|problem| format!("{}{}", prefix, problem)),
+        );
+    }
+    problems
+}
+
+/// The problems of a single test.
+fn test_problems(config: &Configuration) -> Vec<String> {
+    let mut problems = vec![];
+    let commands = std::iter::once(("The command", &config.command))
+        .chain(config.setup.iter().map(|c| ("A setup command", c)))
+        .chain(config.teardown.iter().map(|c| ("A teardown command", c)))
+        .chain(
+            config
+                .validate_stdout
+                .iter()
+                .map(|c| ("validate-stdout", c)),
+        );
+    for (kind, command) in commands {
+        if command.0.trim().is_empty() {
+            problems.push(format!("{} has an empty program", kind));
+        }
+    }
+
+    let stdout_asserted = [
+        (
+            "stdout",
+            config.stdout.as_ref().is_some_and(|s| !s.is_empty()),
+        ),
+        (
+            "stdout-lines",
+            config.stdout_lines.as_ref().is_some_and(|l| !l.is_empty()),
+        ),
+        (
+            "stdout-line-count",
+            config.stdout_line_count.is_some_and(|n| n > 0),
+        ),
+        (
+            "stdout-contains",
+            config.stdout_contains.iter().any(|s| !s.is_empty()),
+        ),
+        ("stdout-json", config.stdout_json.is_some()),
+        ("assert-json", !config.assert_json.is_empty()),
+        ("assert-lines", !config.assert_lines.is_empty()),
+    ];
+    let stderr_asserted = [
+        (
+            "stderr",
+            config.stderr.as_ref().is_some_and(|s| !s.is_empty()),
+        ),
+        (
+            "stderr-lines",
+            config.stderr_lines.as_ref().is_some_and(|l| !l.is_empty()),
+        ),
+        (
+            "stderr-line-count",
+            config.stderr_line_count.is_some_and(|n| n > 0),
+        ),
+        (
+            "stderr-contains",
+            config.stderr_contains.iter().any(|s| !s.is_empty()),
+        ),
+    ];
+    for (stream, empty, asserted) in [
+        ("stdout", config.stdout_empty, &stdout_asserted[..]),
+        ("stderr", config.stderr_empty, &stderr_asserted[..]),
+    ] {
+        for (key, _) in asserted.iter().filter(|(_, asserted)| *asserted && empty) {
+            problems.push(format!("{}-empty contradicts {}", stream, key));
+        }
+    }
+    if config.merge_output || config.pty {
+        let reason = if config.pty { "pty" } else { "merge-output" };
+        for (key, _) in stderr_asserted.iter().filter(|(_, asserted)| *asserted) {
+            problems.push(format!(
+                "{} can't hold with {}, stderr is empty",
+                key, reason
+            ));
+        }
+    }
+    for (stream, lines, count) in [
+        ("stdout", &config.stdout_lines, config.stdout_line_count),
+        ("stderr", &config.stderr_lines, config.stderr_line_count),
+    ] {
+        if let (Some(lines), Some(count)) = (lines, count) {
+            if lines.len() != count {
+                problems.push(format!(
+                    "{}-lines has {} lines, but {}-line-count expects {}",
+                    stream,
+                    lines.len(),
+                    stream,
+                    count
+                ));
+            }
+        }
+    }
+    for (stream, contains, not_contains) in [
+        (
+            "stdout",
+            &config.stdout_contains,
+            &config.stdout_not_contains,
+        ),
+        (
+            "stderr",
+            &config.stderr_contains,
+            &config.stderr_not_contains,
+        ),
+    ] {
+        for text in contains.iter().filter(|text| not_contains.contains(text)) {
+            problems.push(format!(
+                "{:?} is in both {}-contains and {}-not-contains",
+                text, stream, stream
+            ));
+        }
+    }
+    if let (Some(min), Some(timeout)) = (config.min_duration, config.timeout) {
+        if min >= timeout {
+            problems.push(format!(
+                "min-duration {:?} can't be reached before the timeout of {:?}",
+                min, timeout
+            ));
+        }
+    }
+
+    let read = [
+        ("stdin-file", &config.stdin_file),
+        ("stdout-file", &config.stdout_file),
+        ("stderr-file", &config.stderr_file),
+    ];
+    let mut files: Vec<(&str, &Path)> = read
+        .into_iter()
+        .filter_map(|(key, path)| Some((key, path.as_deref()?)))
+        .collect();
+    files.extend(config.files.values().filter_map(|file| match file {
+        FixtureFile::Source { source } => Some(("The source of a file", source.as_path())),
+        FixtureFile::Content(_) => None,
+    }));
+    files.extend(
+        config
+            .expect_files
+            .values()
+            .filter_map(|file| file.content_file.as_deref())
+            .map(|path| ("content-file", path)),
+    );
+    for (key, path) in files {
+        if !path.is_file() {
+            problems.push(format!("{} {} does not exist", key, path.display()));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problems() {
+        let input = r#"
+tests:
+  fine:
+    command: [echo, hello]
+    stdout: "hello\n"
+    stderr-empty: true
+  broken:
+    command: [" "]
+    setup: [[""]]
+    stdout: "hello\n"
+    stdout-empty: true
+    stdout-lines: [a, b]
+    stdout-line-count: 1
+    stdout-contains: [x]
+    stdout-not-contains: [x, y]
+    stderr-contains: [oops]
+    merge-output: true
+    timeout: 1s
+    min-duration: 2s
+    stdin-file: /nonexistent/smokers/stdin
+"#;
+        let suite = Suite::from_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            problems(&suite),
+            [
+                "broken: The command has an empty program",
+                "broken: A setup command has an empty program",
+                "broken: stdout-empty contradicts stdout",
+                "broken: stdout-empty contradicts stdout-lines",
+                "broken: stdout-empty contradicts stdout-line-count",
+                "broken: stdout-empty contradicts stdout-contains",
+                "broken: stderr-contains can't hold with merge-output, stderr is empty",
+                "broken: stdout-lines has 2 lines, but stdout-line-count expects 1",
+                "broken: \"x\" is in both stdout-contains and stdout-not-contains",
+                "broken: min-duration 2s can't be reached before the timeout of 1s",
+                "broken: stdin-file /nonexistent/smokers/stdin does not exist",
+            ]
+        );
+    }
+}
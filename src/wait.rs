@@ -0,0 +1,167 @@
+//! Waiting for the readiness conditions of a test.
+
+use crate::config::WaitFor;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Waits until all conditions of `wait_for` hold, with files relative to
+/// `dir`. Returns the reason if they don't within the timeout.
+pub(crate) fn wait(wait_for: &WaitFor, dir: &Path) -> Option<String> {
+    wait_unless(wait_for, dir, || None)
+}
+
+/// Like [`wait`], but gives up as soon as `abort` returns a reason, which
+/// is checked before the conditions.
+pub(crate) fn wait_unless(
+    wait_for: &WaitFor,
+    dir: &Path,
+    mut abort: impl FnMut() -> Option<String>,
+) -> Option<String> {
+    let timeout = wait_for.timeout.unwrap_or(DEFAULT_TIMEOUT);
+    let interval = wait_for.interval.unwrap_or(DEFAULT_INTERVAL);
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(reason) = abort() {
+            return Some(reason);
+        }
+        // All conditions hold once none is pending anymore.
+        let pending = pending_condition(wait_for, dir, interval.max(Duration::from_secs(1)))?;
+        let now = Instant::now();
+        if now >= deadline {
+            return Some(format!(
+                "Timed out after {:?} waiting for {}",
+                timeout, pending
+            ));
+        }
+        std::thread::sleep(interval.min(deadline - now));
+    }
+}
+
+/// Describes the first condition that doesn't hold yet. Connections are
+/// given up after `connect_timeout`.
+fn pending_condition(wait_for: &WaitFor, dir: &Path, connect_timeout: Duration) -> Option<String> {
+    if let Some(file) = wait_for.file.iter().find(|file| !dir.join(file).exists()) {
+        return Some(format!("file {}", file.display()));
+    }
+    if let Some(address) = wait_for
+        .port
+        .iter()
+        .find(|address| connect(address, connect_timeout).is_none())
+    {
+        return Some(format!("port {}", address));
+    }
+    if let Some(url) = wait_for
+        .url
+        .iter()
+        .find(|url| !responds_ok(url, connect_timeout))
+    {
+        return Some(format!("URL {}", url));
+    }
+    None
+}
+
+/// Connects to the first of the addresses `address` resolves to that
+/// accepts connections.
+fn connect(address: &str, timeout: Duration) -> Option<TcpStream> {
+    address
+        .to_socket_addrs()
+        .ok()?
+        .find_map(|address| TcpStream::connect_timeout(&address, timeout).ok())
+}
+
+/// Whether a GET request of the `http://` URL `url` results in the status
+/// 200.
+fn responds_ok(url: &str, timeout: Duration) -> bool {
+    let rest = url.strip_prefix("http://").unwrap_or(url);
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') {
+        authority.to_string()
+    } else {
+        format!("{}:80", authority)
+    };
+    let Some(mut stream) = connect(&address, timeout) else {
+        return false;
+    };
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, authority
+    );
+    if stream.set_read_timeout(Some(timeout)).is_err()
+        || stream.write_all(request.as_bytes()).is_err()
+    {
+        return false;
+    }
+    // Only the status line is of interest.
+    let mut response = [0; 32];
+    let mut read = 0;
+    while read < response.len() {
+        match stream.read(&mut response[read..]) {
+            Ok(0) | Err(_) => break,
+            Ok(n) => read += n,
+        }
+    }
+    let status_line = String::from_utf8_lossy(&response[..read]);
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .is_some_and(|status| status == "200")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_wait_for_port_and_url() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            for status in ["404 Not Found", "200 OK"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 1024];
+                let _ = stream.read(&mut request).unwrap();
+                write!(stream, "HTTP/1.0 {}\r\n\r\n", status).unwrap();
+            }
+        });
+        let wait_for = WaitFor {
+            url: vec![format!("http://127.0.0.1:{}/health", port)],
+            interval: Some(Duration::from_millis(10)),
+            ..WaitFor::default()
+        };
+        assert_eq!(wait(&wait_for, Path::new(".")), None);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_wait_for_timeout() {
+        let wait_for = WaitFor {
+            port: vec!["127.0.0.1:1".to_string()],
+            file: vec!["Cargo.toml".into()],
+            timeout: Some(Duration::from_millis(50)),
+            interval: Some(Duration::from_millis(10)),
+            ..WaitFor::default()
+        };
+        assert_eq!(
+            wait(&wait_for, Path::new(".")).unwrap(),
+            "Timed out after 50ms waiting for port 127.0.0.1:1"
+        );
+        let wait_for = WaitFor {
+            file: vec!["missing".into()],
+            timeout: Some(Duration::from_millis(50)),
+            ..WaitFor::default()
+        };
+        assert_eq!(
+            wait(&wait_for, Path::new(".")).unwrap(),
+            "Timed out after 50ms waiting for file missing"
+        );
+    }
+}
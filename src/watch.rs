@@ -0,0 +1,170 @@
+//! Watching of configuration files and other inputs for `--watch`.
+//!
+//! Files are polled for changes of their modification time instead of
+//! relying on platform specific notification APIs.
+
+use crate::report::{Reporter, TestResult};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The modification times of a set of watched files.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Snapshot(BTreeMap<PathBuf, Option<SystemTime>>);
+
+impl Snapshot {
+    /// Records the modification times of `paths`, leaving out those below
+    /// the `excluded` paths, like the output directories of smokers.
+    /// Directories are searched recursively, missing paths are recorded as
+    /// such so their creation is noticed.
+    pub fn new<'a>(paths: impl IntoIterator<Item = &'a Path>, excluded: &[PathBuf]) -> Self {
+        // Relative paths are compared as absolute ones, which they may be
+        // given as on either side.
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let excluded: Vec<PathBuf> = excluded.iter().map(|path| cwd.join(path)).collect();
+        let mut files = BTreeMap::new();
+        for path in paths {
+            record(path, &cwd, &excluded, &mut files);
+        }
+        Self(files)
+    }
+
+    /// The paths that were created, modified or removed since `earlier`.
+    pub fn changes(&self, earlier: &Snapshot) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = self
+            .0
+            .iter()
+            .filter(|(path, modified)| earlier.0.get(*path) != Some(modified))
+            .map(|(path, _)| path.clone())
+            .collect();
+        changed.extend(
+            earlier
+                .0
+                .keys()
+                .filter(|path| !self.0.contains_key(*path))
+                .cloned(),
+        );
+        changed.sort();
+        changed
+    }
+}
+
+fn record(
+    path: &Path,
+    cwd: &Path,
+    excluded: &[PathBuf],
+    files: &mut BTreeMap<PathBuf, Option<SystemTime>>,
+) {
+    if excluded
+        .iter()
+        .any(|excluded| cwd.join(path).starts_with(excluded))
+    {
+        return;
+    }
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            files.insert(path.to_path_buf(), None);
+            return;
+        }
+    };
+    if metadata.is_dir() {
+        if let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                record(&entry.path(), cwd, excluded, files);
+            }
+        }
+    } else {
+        files.insert(path.to_path_buf(), metadata.modified().ok());
+    }
+}
+
+/// Blocks until any of `paths` differs from the `snapshot` taken of them,
+/// checking every `interval`. Returns the changed paths.
+pub fn wait_for_changes(
+    paths: &[PathBuf],
+    excluded: &[PathBuf],
+    snapshot: &Snapshot,
+    interval: Duration,
+) -> Vec<PathBuf> {
+    loop {
+        let changes = Snapshot::new(paths.iter().map(PathBuf::as_path), excluded).changes(snapshot);
+        if !changes.is_empty() {
+            return changes;
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Forwards all events to another reporter while counting the passed and
+/// failed tests for the summary printed between runs.
+pub struct SummaryReporter<'a> {
+    inner: &'a mut dyn Reporter,
+    pub passed: usize,
+    pub failed: usize,
+}
+
+impl<'a> SummaryReporter<'a> {
+    pub fn new(inner: &'a mut dyn Reporter) -> Self {
+        Self {
+            inner,
+            passed: 0,
+            failed: 0,
+        }
+    }
+}
+
+impl Reporter for SummaryReporter<'_> {
+    fn start(&mut self, total: usize) -> std::io::Result<()> {
+        self.inner.start(total)
+    }
+
+    fn test_started(&mut self, index: usize, name: &str) -> std::io::Result<()> {
+        self.inner.test_started(index, name)
+    }
+
+    fn test_finished(&mut self, index: usize, report: &TestResult) -> std::io::Result<()> {
+        if report.passed {
+            self.passed += 1;
+        } else {
+            self.failed += 1;
+        }
+        self.inner.test_finished(index, report)
+    }
+
+    fn finish(&mut self, reports: &[TestResult]) -> std::io::Result<()> {
+        self.inner.finish(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_changes() {
+        let dir = std::env::temp_dir().join(format!("smokers-watch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let a = dir.join("a.yaml");
+        let b = dir.join("sub/b.txt");
+        std::fs::write(&a, "a").unwrap();
+        std::fs::write(&b, "b").unwrap();
+        let missing = dir.join("missing.yaml");
+        let paths =
+            [a.as_path(), dir.join("sub").as_path(), missing.as_path()].map(Path::to_path_buf);
+        let excluded = [dir.join("sub/cache")];
+        let snapshot = || Snapshot::new(paths.iter().map(PathBuf::as_path), &excluded);
+        let before = snapshot();
+        assert!(snapshot().changes(&before).is_empty());
+
+        let c = dir.join("sub/c.txt");
+        std::fs::write(&c, "c").unwrap();
+        std::fs::write(&missing, "now there").unwrap();
+        std::fs::remove_file(&b).unwrap();
+        std::fs::create_dir_all(&excluded[0]).unwrap();
+        std::fs::write(excluded[0].join("entry"), "").unwrap();
+        let changes = snapshot().changes(&before);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(changes, vec![missing, b, c]);
+    }
+}